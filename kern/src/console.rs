@@ -35,6 +35,11 @@ impl Console {
         self.inner().read_byte()
     }
 
+    /// Returns `true` if a byte is available to read without blocking.
+    pub fn has_byte(&mut self) -> bool {
+        self.inner().has_byte()
+    }
+
     /// Writes the byte `byte` to the UART device.
     pub fn write_byte(&mut self, byte: u8) {
         self.inner().write_byte(byte);
@@ -66,6 +71,32 @@ impl fmt::Write for Console {
 /// Global `Console` singleton.
 pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
 
+/// A handle implementing `io::Read` and `io::Write` on top of the global
+/// `CONSOLE`. The lock is held only for the duration of each call, so other
+/// code may keep printing while a reader is blocked on the handle.
+pub struct ConsoleStream;
+
+impl io::Read for ConsoleStream {
+    /// Blocks until a byte is available and reads exactly one byte.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = CONSOLE.lock().read_byte();
+        Ok(1)
+    }
+}
+
+impl io::Write for ConsoleStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(&mut *CONSOLE.lock(), buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
@@ -1,10 +1,11 @@
 pub mod sd;
 
 use alloc::rc::Rc;
+use alloc::vec::Vec;
 use core::fmt::{self, Debug};
 use shim::io;
 use shim::ioerr;
-use shim::path::Path;
+use shim::path::{Path, PathBuf};
 
 pub use fat32::traits;
 use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
@@ -79,3 +80,19 @@ impl fat32::traits::FileSystem for &FileSystem {
      }
 
 }
+
+/// Appends the relative or absolute path `rel_path` to `path`, resolving `.`
+/// and `..` components.
+pub fn merge_paths(path: &mut PathBuf, rel_path: &PathBuf) {
+    let components: Vec<_> = rel_path.components().map(|comp| comp.as_os_str()).collect();
+    for component in components {
+        let chk1 = component.to_str().unwrap()=="..";
+        let chk2 = component.to_str().unwrap()==".";
+        let tpath = PathBuf::from(component.to_str().unwrap());
+        if chk1 {
+            path.pop();
+        } else if !chk2{
+            path.push(tpath);
+        }
+    }
+}
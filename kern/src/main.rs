@@ -88,6 +88,14 @@ unsafe fn kmain() -> ! {
        ALLOCATOR.initialize();
        FILESYSTEM.initialize();
        VMM.initialize();
+       // USPi completes USB transfers from its FIQ handler.
+       aarch64::enable_fiq_interrupt();
+       USB.initialize();
+       ETHERNET.initialize();
+       aarch64::disable_fiq_interrupt();
+       if !USB.is_eth_link_up() {
+           warn!("ethernet link is down");
+       }
        SCHEDULER.initialize();
        init::initialize_app_cores();
        VMM.wait();
@@ -1,4 +1,7 @@
 ///! Network device that wraps USPi in smoltcp abstraction
+pub mod pcap;
+pub mod telnet;
+pub mod tftp;
 pub mod uspi;
 
 use alloc::boxed::Box;
@@ -12,8 +15,9 @@ use core::time::Duration;
 use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
 use smoltcp::phy::{self, Device, DeviceCapabilities};
 use smoltcp::socket::{SocketHandle, SocketRef, TcpSocketBuffer};
+use smoltcp::socket::{UdpPacketMetadata, UdpSocketBuffer};
 use smoltcp::time::Instant;
-use smoltcp::wire::{IpAddress, IpCidr};
+use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address};
 
 use crate::mutex::Mutex;
 use crate::param::MTU;
@@ -22,6 +26,7 @@ use crate::USB;
 // We always use owned buffer as internal storage
 pub type SocketSet = smoltcp::socket::SocketSet<'static, 'static, 'static>;
 pub type TcpSocket = smoltcp::socket::TcpSocket<'static>;
+pub type UdpSocket = smoltcp::socket::UdpSocket<'static, 'static>;
 pub type EthernetInterface<T> = smoltcp::iface::EthernetInterface<'static, 'static, 'static, T>;
 
 /// 8-byte aligned `u8` slice.
@@ -112,10 +117,11 @@ pub struct RxToken {
 }
 
 impl phy::RxToken for RxToken {
-    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
+    fn consume<R, F>(mut self, timestamp: Instant, f: F) -> smoltcp::Result<R>
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
+        pcap::record(timestamp, self.frame.as_slice());
         f(self.frame.as_mut_slice())
     }
 }
@@ -123,13 +129,14 @@ impl phy::RxToken for RxToken {
 pub struct TxToken;
 
 impl phy::TxToken for TxToken {
-    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
         let mut frame = Frame::new();
         frame.set_len(len.try_into().unwrap());
         let result = f(frame.as_mut_slice());
+        pcap::record(timestamp, frame.as_slice());
         USB.send_frame(&frame);
         result
     }
@@ -138,16 +145,52 @@ impl phy::TxToken for TxToken {
 /// Creates and returns a new ethernet interface using `UsbEthernet` struct.
 pub fn create_interface() -> EthernetInterface<UsbEthernet> {
     // Lab 5 2.B
-    unimplemented!("create_interface")
+    let device = UsbEthernet;
+    let hw_addr = USB.get_eth_addr();
+    let neighbor_cache = NeighborCache::new(BTreeMap::new());
+    let private_cidr = IpCidr::new(IpAddress::v4(169, 254, 32, 10), 16);
+    let local_cidr = IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8);
+
+    EthernetInterfaceBuilder::new(device)
+        .ethernet_addr(hw_addr)
+        .neighbor_cache(neighbor_cache)
+        .ip_addrs(vec![private_cidr, local_cidr])
+        .finalize()
+}
+
+/// The DNS server user programs resolve names with, set with the shell's
+/// `dns` command.
+static DNS_SERVER: Mutex<Option<Ipv4Address>> = Mutex::new(None);
+
+/// Returns the configured DNS server, if any.
+pub fn dns_server() -> Option<Ipv4Address> {
+    *DNS_SERVER.lock()
+}
+
+/// Sets the DNS server handed out to user programs.
+pub fn set_dns_server(server: Option<Ipv4Address>) {
+    *DNS_SERVER.lock() = server;
+}
+
+/// A socket owned by a user process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UserSocket {
+    Tcp(SocketHandle),
+    Udp(SocketHandle),
 }
 
 const PORT_MAP_SIZE: usize = 65536 / 64;
 
+/// Ports the kernel listens on, which user sockets may not listen on.
+pub const KERNEL_PORTS: &[u16] = &[telnet::TELNET_PORT];
+
 pub struct EthernetDriver {
     /// A set of sockets
     socket_set: SocketSet,
     /// Bitmap to track the port usage
     port_map: [u64; PORT_MAP_SIZE],
+    /// Number of sockets sharing each port marked by `share_port()`.
+    port_refs: BTreeMap<u16, usize>,
     /// Internal ethernet interface
     ethernet: EthernetInterface<UsbEthernet>,
 }
@@ -156,40 +199,91 @@ impl EthernetDriver {
     /// Creates a fresh ethernet driver.
     fn new() -> EthernetDriver {
         // Lab 5 2.B
-        unimplemented!("new")
+        EthernetDriver {
+            socket_set: SocketSet::new(Vec::new()),
+            port_map: [0; PORT_MAP_SIZE],
+            port_refs: BTreeMap::new(),
+            ethernet: create_interface(),
+        }
     }
 
     /// Polls the ethernet interface.
     /// See also `smoltcp::iface::EthernetInterface::poll()`.
     fn poll(&mut self, timestamp: Instant) {
         // Lab 5 2.B
-        unimplemented!("poll")
+        if let Err(e) = self.ethernet.poll(&mut self.socket_set, timestamp) {
+            trace!("ethernet poll error: {:?}", e);
+        }
+        // Reclaim released sockets once their connections have closed.
+        self.socket_set.prune();
     }
 
     /// Returns an advisory wait time to call `poll()` the next time.
     /// See also `smoltcp::iface::EthernetInterface::poll_delay()`.
     fn poll_delay(&mut self, timestamp: Instant) -> Duration {
         // Lab 5 2.B
-        unimplemented!("poll_delay")
+        match self.ethernet.poll_delay(&self.socket_set, timestamp) {
+            Some(delay) => Duration::from_millis(delay.total_millis()),
+            None => Duration::from_millis(100),
+        }
     }
 
     /// Marks a port as used. Returns `Some(port)` on success, `None` on failure.
     pub fn mark_port(&mut self, port: u16) -> Option<u16> {
         // Lab 5 2.B
-        unimplemented!("mark_port")
+        let (idx, bit) = (port as usize / 64, port as usize % 64);
+        if self.port_map[idx] & (1 << bit) != 0 {
+            return None;
+        }
+        self.port_map[idx] |= 1 << bit;
+        Some(port)
     }
 
     /// Clears used bit of a port. Returns `Some(port)` on success, `None` on failure.
     pub fn erase_port(&mut self, port: u16) -> Option<u16> {
         // Lab 5 2.B
-        unimplemented!("erase_port")
+        let (idx, bit) = (port as usize / 64, port as usize % 64);
+        if self.port_map[idx] & (1 << bit) == 0 {
+            return None;
+        }
+        self.port_map[idx] &= !(1 << bit);
+        Some(port)
+    }
+
+    /// Marks a port as used by one more listening socket. Any number of
+    /// sockets may share a port this way, but not a port marked by
+    /// `mark_port()`. Returns `Some(port)` on success, `None` on failure.
+    pub fn share_port(&mut self, port: u16) -> Option<u16> {
+        match self.port_refs.get_mut(&port) {
+            Some(refs) => *refs += 1,
+            None => {
+                self.mark_port(port)?;
+                self.port_refs.insert(port, 1);
+            }
+        }
+        Some(port)
+    }
+
+    /// Frees a port used by a socket that is being closed. A port marked by
+    /// `share_port()` is cleared once the last socket sharing it is closed.
+    pub fn release_port(&mut self, port: u16) {
+        match self.port_refs.get_mut(&port) {
+            Some(refs) if *refs > 1 => *refs -= 1,
+            Some(_) => {
+                self.port_refs.remove(&port);
+                self.erase_port(port);
+            }
+            None => {
+                self.erase_port(port);
+            }
+        }
     }
 
     /// Returns the first open port between the ephemeral port range 49152 ~ 65535.
     /// Note that this function does not mark the returned port.
     pub fn get_ephemeral_port(&mut self) -> Option<u16> {
         // Lab 5 2.B
-        unimplemented!("get_ephemeral_port")
+        (49152..=65535u16).find(|&port| self.port_map[port as usize / 64] & (1 << (port % 64)) == 0)
     }
 
     /// Finds a socket with a `SocketHandle`.
@@ -206,11 +300,49 @@ impl EthernetDriver {
         self.socket_set.add(tcp_socket)
     }
 
+    /// Finds a UDP socket with a `SocketHandle`.
+    pub fn get_udp_socket(&mut self, handle: SocketHandle) -> SocketRef<'_, UdpSocket> {
+        self.socket_set.get::<UdpSocket>(handle)
+    }
+
+    /// This function creates a new UDP socket, adds it to the internal socket
+    /// set, and returns the `SocketHandle` of the new socket.
+    pub fn add_udp_socket(&mut self) -> SocketHandle {
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 16], vec![0; 16384]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 16], vec![0; 16384]);
+        let udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+        self.socket_set.add(udp_socket)
+    }
+
     /// Releases a socket from the internal socket set.
     pub fn release(&mut self, handle: SocketHandle) {
         self.socket_set.release(handle);
     }
 
+    /// Closes a user socket, releases it, and frees its local port. A TCP
+    /// connection is shut down gracefully; the socket is pruned once it has
+    /// closed.
+    pub fn close_user_socket(&mut self, socket: UserSocket) {
+        let (handle, port) = match socket {
+            UserSocket::Tcp(handle) => {
+                let mut socket = self.get_socket(handle);
+                let port = socket.local_endpoint().port;
+                socket.close();
+                (handle, port)
+            }
+            UserSocket::Udp(handle) => {
+                let mut socket = self.get_udp_socket(handle);
+                let port = socket.endpoint().port;
+                socket.close();
+                (handle, port)
+            }
+        };
+        self.release(handle);
+        if port != 0 {
+            self.release_port(port);
+        }
+    }
+
     /// Prunes the internal socket set.
     pub fn prune(&mut self) {
         self.socket_set.prune();
@@ -232,7 +364,11 @@ impl GlobalEthernetDriver {
 
     pub fn poll(&self, timestamp: Instant) {
         // Lab 5 2.B
-        unimplemented!("poll")
+        self.0
+            .lock()
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .poll(timestamp)
     }
 
     pub fn poll_delay(&self, timestamp: Instant) -> Duration {
@@ -267,6 +403,37 @@ impl GlobalEthernetDriver {
             .add_socket()
     }
 
+    pub fn erase_port(&self, port: u16) -> Option<u16> {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .erase_port(port)
+    }
+
+    pub fn add_udp_socket(&self) -> SocketHandle {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .add_udp_socket()
+    }
+
+    /// Enters a critical region and execute the provided closure with a mutable
+    /// reference to the UDP socket.
+    pub fn with_udp_socket<F, R>(&self, handle: SocketHandle, f: F) -> R
+    where
+        F: FnOnce(&mut SocketRef<'_, UdpSocket>) -> R,
+    {
+        let mut guard = self.0.lock();
+        let mut socket = guard
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .get_udp_socket(handle);
+
+        f(&mut socket)
+    }
+
     /// Enters a critical region and execute the provided closure with a mutable
     /// reference to the socket.
     pub fn with_socket<F, R>(&self, handle: SocketHandle, f: F) -> R
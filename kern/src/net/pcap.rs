@@ -0,0 +1,252 @@
+///! Packet capture of the frames passing through `UsbEthernet`, exported in
+///! the libpcap file format.
+use alloc::vec::Vec;
+
+use shim::io;
+use smoltcp::time::Instant;
+
+use crate::mutex::Mutex;
+
+/// Number of frames the ring keeps when no capacity is given.
+pub const DEFAULT_CAPACITY: usize = 128;
+
+/// libpcap magic number for microsecond timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// Largest frame a record can hold: an MTU-sized payload and the 14-byte
+/// Ethernet header.
+const SNAPLEN: u32 = crate::param::MTU + 14;
+
+/// Largest number of frames the ring can be asked to keep. Every slot is
+/// allocated up front, so this bounds the memory `start()` takes.
+pub const MAX_CAPACITY: usize = 4096;
+
+/// `LINKTYPE_ETHERNET`.
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// One captured frame.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub timestamp: Instant,
+    pub data: Vec<u8>,
+}
+
+/// A bounded ring of captured frames. Once full, the oldest frame is
+/// overwritten.
+///
+/// The slots are allocated by `start()` with room for `SNAPLEN` bytes each,
+/// so recording a frame only copies it.
+#[derive(Debug)]
+pub struct Capture {
+    enabled: bool,
+    slots: Vec<Record>,
+    /// Number of slots that hold a frame.
+    len: usize,
+    /// Index of the oldest record once the ring is full.
+    next: usize,
+    /// Frames that were overwritten since the last `clear()`.
+    dropped: usize,
+}
+
+/// Snapshot of the capture state for the shell.
+#[derive(Debug, Clone, Copy)]
+pub struct Status {
+    pub enabled: bool,
+    pub capacity: usize,
+    pub frames: usize,
+    pub dropped: usize,
+}
+
+impl Capture {
+    const fn new() -> Capture {
+        Capture {
+            enabled: false,
+            slots: Vec::new(),
+            len: 0,
+            next: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Empties the ring and gives it `capacity` slots, reusing the current
+    /// ones if there are already that many.
+    fn allocate(&mut self, capacity: usize) {
+        self.reset();
+        if self.slots.len() != capacity {
+            self.slots = (0..capacity)
+                .map(|_| Record {
+                    timestamp: Instant::from_millis(0),
+                    data: Vec::with_capacity(SNAPLEN as usize),
+                })
+                .collect();
+        }
+    }
+
+    fn push(&mut self, timestamp: Instant, frame: &[u8]) {
+        let capacity = self.slots.len();
+        if capacity == 0 {
+            return;
+        }
+        let index = if self.len < capacity {
+            self.len += 1;
+            self.len - 1
+        } else {
+            let index = self.next;
+            self.next = (self.next + 1) % capacity;
+            self.dropped += 1;
+            index
+        };
+        let slot = &mut self.slots[index];
+        let len = core::cmp::min(frame.len(), SNAPLEN as usize);
+        slot.timestamp = timestamp;
+        slot.data.clear();
+        slot.data.extend_from_slice(&frame[..len]);
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.next = 0;
+        self.dropped = 0;
+    }
+}
+
+static CAPTURE: Mutex<Capture> = Mutex::new(Capture::new());
+
+/// Records `frame` if capturing is enabled. Called from the device tokens
+/// while the ethernet driver is locked, so this never waits for the capture
+/// lock; a frame that arrives while the ring is being read is not recorded.
+pub fn record(timestamp: Instant, frame: &[u8]) {
+    if let Some(mut capture) = CAPTURE.try_lock() {
+        if capture.enabled {
+            capture.push(timestamp, frame);
+        }
+    }
+}
+
+/// Clears the ring and starts capturing up to `capacity` frames. `capacity`
+/// is clamped to `1..=MAX_CAPACITY`.
+pub fn start(capacity: usize) {
+    let capacity = core::cmp::min(core::cmp::max(capacity, 1), MAX_CAPACITY);
+    let mut capture = CAPTURE.lock();
+    capture.allocate(capacity);
+    capture.enabled = true;
+}
+
+/// Stops capturing. The frames captured so far are kept.
+pub fn stop() {
+    CAPTURE.lock().enabled = false;
+}
+
+/// Discards all captured frames.
+pub fn clear() {
+    CAPTURE.lock().reset();
+}
+
+pub fn status() -> Status {
+    let capture = CAPTURE.lock();
+    Status {
+        enabled: capture.enabled,
+        capacity: capture.slots.len(),
+        frames: capture.len,
+        dropped: capture.dropped,
+    }
+}
+
+/// Returns a copy of the captured frames, oldest first.
+pub fn snapshot() -> Vec<Record> {
+    let capture = CAPTURE.lock();
+    let (newer, older) = capture.slots[..capture.len].split_at(capture.next);
+    older.iter().chain(newer.iter()).cloned().collect()
+}
+
+/// Writes `records` to `out` as a libpcap file.
+pub fn write_pcap<W: io::Write>(records: &[Record], out: &mut W) -> io::Result<()> {
+    // Global header: magic, version 2.4, UTC offset, accuracy, snaplen, link type.
+    out.write_all(&PCAP_MAGIC.to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&4u16.to_le_bytes())?;
+    out.write_all(&0i32.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&SNAPLEN.to_le_bytes())?;
+    out.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+
+    for record in records {
+        let millis = record.timestamp.total_millis() as u64;
+        let len = record.data.len() as u32;
+        out.write_all(&((millis / 1000) as u32).to_le_bytes())?;
+        out.write_all(&((millis % 1000 * 1000) as u32).to_le_bytes())?;
+        out.write_all(&len.to_le_bytes())?;
+        out.write_all(&len.to_le_bytes())?;
+        out.write_all(&record.data)?;
+    }
+    Ok(())
+}
+
+/// Returns `records` as an in-memory libpcap file.
+pub fn to_pcap(records: &[Record]) -> Vec<u8> {
+    let size = 24 + records.iter().map(|r| 16 + r.data.len()).sum::<usize>();
+    let mut cursor = io::Cursor::new(Vec::with_capacity(size));
+    write_pcap(records, &mut cursor).expect("writing to a Vec cannot fail");
+    cursor.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_pcap, Capture, Record, SNAPLEN};
+    use alloc::vec::Vec;
+    use smoltcp::time::Instant;
+
+    /// A libpcap file holding a 3-byte frame at 1234.567s and an empty
+    /// frame at 0.005s.
+    const FILE: [u8; 24 + 16 + 3 + 16] = [
+        // Global header.
+        0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xea, 0x05, 0, 0, 1, 0, 0, 0,
+        // First record.
+        0xd2, 0x04, 0, 0, 0xd8, 0xa6, 0x08, 0, 3, 0, 0, 0, 3, 0, 0, 0, 1, 2, 3,
+        // Second record.
+        0, 0, 0, 0, 0x88, 0x13, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+
+    #[test]
+    fn test_to_pcap() {
+        assert_eq!(&to_pcap(&[])[..], &FILE[..24]);
+
+        let records = [
+            Record {
+                timestamp: Instant::from_millis(1_234_567),
+                data: vec![1, 2, 3],
+            },
+            Record {
+                timestamp: Instant::from_millis(5),
+                data: vec![],
+            },
+        ];
+        assert_eq!(&to_pcap(&records)[..], &FILE[..]);
+    }
+
+    #[test]
+    fn test_ring_overwrites_oldest() {
+        let mut capture = Capture::new();
+        capture.allocate(2);
+        for byte in 0..5u8 {
+            capture.push(Instant::from_millis(byte as i64), &[byte]);
+        }
+        assert_eq!(capture.len, 2);
+        assert_eq!(capture.dropped, 3);
+        let (newer, older) = capture.slots[..capture.len].split_at(capture.next);
+        let order: Vec<u8> = older
+            .iter()
+            .chain(newer.iter())
+            .map(|r| r.data[0])
+            .collect();
+        assert_eq!(order, [3, 4]);
+
+        capture.push(Instant::from_millis(0), &[0; 2 * SNAPLEN as usize]);
+        assert_eq!(capture.slots[1].data.len(), SNAPLEN as usize);
+
+        let slot = capture.slots[0].data.as_ptr();
+        capture.allocate(2);
+        assert_eq!(capture.slots[0].data.as_ptr(), slot);
+        assert_eq!((capture.len, capture.next, capture.dropped), (0, 0, 0));
+    }
+}
@@ -0,0 +1,367 @@
+///! Remote shell sessions over TCP (telnet-style) backed by the kernel shell.
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use shim::io;
+use smoltcp::socket::SocketHandle;
+
+use kernel_api::OsResult;
+
+use crate::process::{kernel_sleep, Process};
+use crate::{ETHERNET, SCHEDULER};
+
+/// The well-known telnet port.
+pub const TELNET_PORT: u16 = 23;
+
+/// The shell commands a session may run. Sessions are not authenticated, so
+/// they can look at the file system but not run programs, signal processes,
+/// change the network setup or send files elsewhere.
+const SESSION_COMMANDS: &[&str] = &["echo", "ls", "pwd", "cd", "cat", "sleep", "exit"];
+
+/// Set once `start()` has started the listener.
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// How long a session or the listener sleeps between socket polls.
+const POLL_INTERVAL_MS: u32 = 20;
+
+// Telnet commands and options (RFC 854, 857, 858).
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+
+/// Position of the receive side in the telnet command grammar.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RecvState {
+    Data,
+    /// The previous data byte was a carriage return.
+    Cr,
+    Iac,
+    /// Waiting for the option byte of a WILL/WONT/DO/DONT command.
+    Negotiate(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// A TCP connection speaking a minimal subset of the telnet protocol.
+///
+/// The server offers to echo and to suppress go-ahead, so clients switch to
+/// character-at-a-time mode and the shell's own echo is what the user sees.
+/// Every other option the client asks for is refused.
+pub struct TelnetStream {
+    handle: SocketHandle,
+    state: RecvState,
+}
+
+impl TelnetStream {
+    /// Wraps an established socket and sends the initial option offers.
+    pub fn new(handle: SocketHandle) -> io::Result<TelnetStream> {
+        let mut stream = TelnetStream {
+            handle,
+            state: RecvState::Data,
+        };
+        stream.send_all(&[IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA])?;
+        Ok(stream)
+    }
+
+    /// Sends every byte of `data`, sleeping while the transmit buffer is full.
+    fn send_all(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let sent = ETHERNET.with_socket(self.handle, |socket| {
+                if !socket.may_send() {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "connection closed"));
+                }
+                if !socket.can_send() {
+                    return Ok(0);
+                }
+                socket
+                    .send_slice(data)
+                    .map_err(|_| io::Error::new(io::ErrorKind::Other, "send failed"))
+            })?;
+            data = &data[sent..];
+            if sent == 0 {
+                kernel_sleep(POLL_INTERVAL_MS);
+            }
+        }
+        Ok(())
+    }
+
+    /// Feeds one received byte through the protocol state machine. Returns
+    /// the byte if it is user data, pushing any negotiation reply to `reply`.
+    fn filter(&mut self, byte: u8, reply: &mut Vec<u8>) -> Option<u8> {
+        match (self.state, byte) {
+            (RecvState::Data, IAC) | (RecvState::Cr, IAC) => {
+                self.state = RecvState::Iac;
+                None
+            }
+            // A carriage return is followed by LF or NUL; the shell only
+            // needs the CR to end a line.
+            (RecvState::Cr, b'\n') | (RecvState::Cr, 0) => {
+                self.state = RecvState::Data;
+                None
+            }
+            (RecvState::Data, b'\r') | (RecvState::Cr, b'\r') => {
+                self.state = RecvState::Cr;
+                Some(b'\r')
+            }
+            (RecvState::Data, byte) | (RecvState::Cr, byte) => {
+                self.state = RecvState::Data;
+                // Most clients send DEL for backspace.
+                Some(if byte == 127 { 8 } else { byte })
+            }
+            (RecvState::Iac, IAC) => {
+                self.state = RecvState::Data;
+                Some(IAC)
+            }
+            (RecvState::Iac, WILL) | (RecvState::Iac, WONT)
+            | (RecvState::Iac, DO) | (RecvState::Iac, DONT) => {
+                self.state = RecvState::Negotiate(byte);
+                None
+            }
+            (RecvState::Iac, SB) => {
+                self.state = RecvState::Subnegotiation;
+                None
+            }
+            (RecvState::Iac, _) => {
+                self.state = RecvState::Data;
+                None
+            }
+            (RecvState::Negotiate(command), option) => {
+                self.state = RecvState::Data;
+                match command {
+                    DO if option == OPT_ECHO || option == OPT_SGA => {}
+                    DO => reply.extend_from_slice(&[IAC, WONT, option]),
+                    WILL if option == OPT_SGA => {}
+                    WILL => reply.extend_from_slice(&[IAC, DONT, option]),
+                    _ => {}
+                }
+                None
+            }
+            (RecvState::Subnegotiation, IAC) => {
+                self.state = RecvState::SubnegotiationIac;
+                None
+            }
+            (RecvState::Subnegotiation, _) => None,
+            (RecvState::SubnegotiationIac, SE) => {
+                self.state = RecvState::Data;
+                None
+            }
+            (RecvState::SubnegotiationIac, _) => {
+                self.state = RecvState::Subnegotiation;
+                None
+            }
+        }
+    }
+
+    /// Closes the connection, waits for the close handshake to finish, and
+    /// releases the socket.
+    pub fn close(self) {
+        ETHERNET.with_socket(self.handle, |socket| socket.close());
+        for _ in 0..(5000 / POLL_INTERVAL_MS) {
+            if !ETHERNET.with_socket(self.handle, |socket| socket.is_open()) {
+                break;
+            }
+            kernel_sleep(POLL_INTERVAL_MS);
+        }
+        ETHERNET.critical(|driver| driver.release(self.handle));
+    }
+}
+
+impl io::Read for TelnetStream {
+    /// Blocks until user data is available. Returns `Ok(0)` once the peer has
+    /// closed its side of the connection.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut raw = [0u8; 64];
+        let max = core::cmp::min(buf.len(), raw.len());
+        loop {
+            let received = ETHERNET.with_socket(self.handle, |socket| {
+                if socket.can_recv() {
+                    socket
+                        .recv_slice(&mut raw[..max])
+                        .map(Some)
+                        .map_err(|_| io::Error::new(io::ErrorKind::Other, "recv failed"))
+                } else if !socket.may_recv() {
+                    Ok(Some(0))
+                } else {
+                    Ok(None)
+                }
+            })?;
+
+            match received {
+                Some(0) => return Ok(0),
+                Some(len) => {
+                    let mut reply = Vec::new();
+                    let mut read = 0;
+                    for &byte in raw[..len].iter() {
+                        if let Some(data) = self.filter(byte, &mut reply) {
+                            buf[read] = data;
+                            read += 1;
+                        }
+                    }
+                    self.send_all(&reply)?;
+                    if read > 0 {
+                        return Ok(read);
+                    }
+                }
+                None => kernel_sleep(POLL_INTERVAL_MS),
+            }
+        }
+    }
+}
+
+impl io::Write for TelnetStream {
+    /// Writes `buf` as network virtual terminal data: `\n` becomes `\r\n` and
+    /// `IAC` bytes are doubled.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = Vec::with_capacity(buf.len());
+        let mut prev = 0;
+        for &byte in buf.iter() {
+            match byte {
+                b'\n' if prev != b'\r' => data.extend_from_slice(b"\r\n"),
+                IAC => data.extend_from_slice(&[IAC, IAC]),
+                _ => data.push(byte),
+            }
+            prev = byte;
+        }
+        self.send_all(&data)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Starts the listener on `TELNET_PORT`. It is off until started from the
+/// console shell. Returns `Ok(false)` if it was already started.
+pub fn start() -> OsResult<bool> {
+    if STARTED.swap(true, Ordering::SeqCst) {
+        return Ok(false);
+    }
+    match Process::kernel_thread(listener, TELNET_PORT as u64) {
+        Ok(process) => {
+            SCHEDULER.add(process);
+            Ok(true)
+        }
+        Err(e) => {
+            STARTED.store(false, Ordering::SeqCst);
+            Err(e)
+        }
+    }
+}
+
+/// Returns `true` if `start()` has started the listener.
+pub fn is_started() -> bool {
+    STARTED.load(Ordering::SeqCst)
+}
+
+/// Kernel thread that accepts connections on port `port` and starts a shell
+/// session thread for each of them.
+extern "C" fn listener(port: u64) {
+    let port = port as u16;
+    if ETHERNET.mark_port(port).is_none() {
+        error!("telnet: port {} is already in use", port);
+        return;
+    }
+    info!("telnet: listening on port {}", port);
+
+    loop {
+        let handle = ETHERNET.add_socket();
+        if let Err(e) = ETHERNET.with_socket(handle, |socket| socket.listen(port)) {
+            error!("telnet: listen failed: {:?}", e);
+            ETHERNET.critical(|driver| driver.release(handle));
+            return;
+        }
+        while !ETHERNET.with_socket(handle, |socket| socket.is_active()) {
+            kernel_sleep(POLL_INTERVAL_MS);
+        }
+
+        let arg = Box::into_raw(Box::new(handle)) as u64;
+        match Process::kernel_thread(session, arg) {
+            Ok(process) => {
+                SCHEDULER.add(process);
+            }
+            Err(e) => {
+                error!("telnet: failed to start a session: {:?}", e);
+                drop(unsafe { Box::from_raw(arg as *mut SocketHandle) });
+                ETHERNET.with_socket(handle, |socket| socket.abort());
+                ETHERNET.critical(|driver| driver.release(handle));
+            }
+        }
+    }
+}
+
+/// Kernel thread running one shell session. `arg` is a boxed `SocketHandle`
+/// handed over by `listener()`.
+extern "C" fn session(arg: u64) {
+    let handle = *unsafe { Box::from_raw(arg as *mut SocketHandle) };
+    match TelnetStream::new(handle) {
+        Ok(mut stream) => {
+            crate::shell::shell_on("telnet> ", Some(SESSION_COMMANDS), &mut stream);
+            stream.close();
+        }
+        Err(_) => ETHERNET.critical(|driver| driver.release(handle)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecvState, TelnetStream, DO, DONT, IAC, OPT_ECHO, OPT_SGA, SB, SE, WILL, WONT};
+    use alloc::vec::Vec;
+    use smoltcp::socket::SocketHandle;
+
+    /// Input bytes, the user data the filter passes on, and the negotiation
+    /// replies it sends back.
+    const SESSIONS: [(&[u8], &[u8], &[u8]); 7] = [
+        (b"ls -l", b"ls -l", &[]),
+        // DEL is passed on as backspace.
+        (&[b'a', 127], &[b'a', 8], &[]),
+        // CR LF and CR NUL end a line with a single CR.
+        (b"a\r\nb\r\0c\r\r\n", b"a\rb\rc\r\r", &[]),
+        (&[b'a', IAC, IAC, b'b'], &[b'a', IAC, b'b'], &[]),
+        (&[b'\r', IAC, IAC], &[b'\r', IAC], &[]),
+        // ECHO and SGA are accepted, other options refused.
+        (
+            &[
+                IAC, DO, OPT_ECHO, IAC, DO, OPT_SGA, IAC, WILL, OPT_SGA, IAC, DO, 24, IAC, WILL,
+                31, IAC, DONT, OPT_ECHO, IAC, WONT, 24, b'x',
+            ],
+            &[b'x'],
+            &[IAC, WONT, 24, IAC, DONT, 31],
+        ),
+        // NOP, then a terminal type subnegotiation with an escaped IAC in it.
+        (
+            &[
+                IAC, 241, b'a', IAC, SB, 24, 0, IAC, IAC, b'V', IAC, SE, b'b',
+            ],
+            &[b'a', b'b'],
+            &[],
+        ),
+    ];
+
+    #[test]
+    fn test_filter() {
+        for &(input, data, reply) in SESSIONS.iter() {
+            let mut stream = TelnetStream {
+                handle: SocketHandle::default(),
+                state: RecvState::Data,
+            };
+            let mut filtered = Vec::new();
+            let mut replies = Vec::new();
+            for &byte in input.iter() {
+                filtered.extend(stream.filter(byte, &mut replies));
+            }
+            assert_eq!(&filtered[..], data);
+            assert_eq!(&replies[..], reply);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+///! A TFTP client (RFC 1350) for fetching program images over the network.
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use smoltcp::socket::SocketHandle;
+use smoltcp::time::Instant;
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+use kernel_api::{OsError, OsResult};
+
+use crate::mutex::Mutex;
+use crate::process::kernel_sleep;
+use crate::ETHERNET;
+
+/// The well-known TFTP server port.
+pub const TFTP_PORT: u16 = 69;
+
+/// Size of a full DATA block. A shorter block ends the transfer.
+const BLOCK_SIZE: usize = 512;
+
+/// How long to wait for a reply before retransmitting.
+const TIMEOUT_MS: u64 = 1000;
+
+/// How many retransmissions are attempted before giving up.
+const MAX_RETRIES: usize = 5;
+
+/// How long the client sleeps between socket polls.
+const POLL_INTERVAL_MS: u32 = 5;
+
+const OP_RRQ: u16 = 1;
+const OP_WRQ: u16 = 2;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+
+/// Program images downloaded with `tftp get`, keyed by destination name.
+static IMAGES: Mutex<Vec<(String, Vec<u8>)>> = Mutex::new(Vec::new());
+
+/// Saves `image` under `name`, replacing an image of the same name.
+pub fn store(name: &str, image: Vec<u8>) {
+    let mut images = IMAGES.lock();
+    images.retain(|(n, _)| n != name);
+    images.push((String::from(name), image));
+}
+
+/// Calls `f` with the image saved under `name`. Returns `None` if there is
+/// no such image.
+pub fn with_image<F, R>(name: &str, f: F) -> Option<R>
+where
+    F: FnOnce(&[u8]) -> R,
+{
+    let images = IMAGES.lock();
+    images
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, image)| f(image.as_slice()))
+}
+
+/// Downloads `filename` from the TFTP server at `server` in octet mode and
+/// returns its contents.
+///
+/// # Errors
+///
+/// - `OsError::NoEntry`: No local port is available, or the server does not have the file.
+/// - `OsError::NoAccess`: The server denied access to the file.
+/// - `OsError::IoErrorTimedOut`: The server stopped answering.
+/// - `OsError::IoErrorInvalidData`: The server sent a malformed packet.
+/// - `OsError::IoError`: Any other transfer failure.
+pub fn get(server: IpAddress, filename: &str) -> OsResult<Vec<u8>> {
+    with_bound_socket(|handle| transfer(handle, server, filename))
+}
+
+/// Uploads `data` to the TFTP server at `server` as `filename` in octet mode.
+///
+/// # Errors
+///
+/// Same as `get()`; `OsError::FileExists` is returned if the server refuses
+/// to overwrite the file.
+pub fn put(server: IpAddress, filename: &str, data: &[u8]) -> OsResult<()> {
+    with_bound_socket(|handle| upload(handle, server, filename, data))
+}
+
+/// Calls `f` with a UDP socket bound to a fresh ephemeral port and releases
+/// the socket afterwards.
+fn with_bound_socket<F, R>(f: F) -> OsResult<R>
+where
+    F: FnOnce(SocketHandle) -> OsResult<R>,
+{
+    let port = ETHERNET
+        .critical(|driver| driver.get_ephemeral_port().and_then(|port| driver.mark_port(port)))
+        .ok_or(OsError::NoEntry)?;
+    let handle = ETHERNET.add_udp_socket();
+
+    let result = ETHERNET
+        .with_udp_socket(handle, |socket| socket.bind(port))
+        .map_err(|_| OsError::IoError)
+        .and_then(|_| f(handle));
+
+    ETHERNET.critical(|driver| driver.release(handle));
+    ETHERNET.erase_port(port);
+    result
+}
+
+/// Runs the read request protocol on a bound socket.
+fn transfer(handle: SocketHandle, server: IpAddress, filename: &str) -> OsResult<Vec<u8>> {
+    let mut packet = request(OP_RRQ, filename);
+
+    // The server answers from a fresh port (its transfer ID); lock onto it
+    // with the first DATA block.
+    let mut remote = IpEndpoint::new(server, TFTP_PORT);
+    let mut locked = false;
+    let mut image = Vec::new();
+    let mut block: u16 = 1;
+    let mut retries = 0;
+    let mut resend = true;
+    let mut deadline = 0;
+
+    loop {
+        if resend {
+            send(handle, &packet, remote)?;
+            deadline = now() + TIMEOUT_MS;
+        }
+
+        let (reply, from) = match recv(handle, deadline) {
+            Some(received) => received,
+            None => {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    return Err(OsError::IoErrorTimedOut);
+                }
+                resend = true;
+                continue;
+            }
+        };
+
+        // Ignore strays from other hosts or transfers. They do not extend the
+        // deadline, which only moves when the packet is (re)sent.
+        resend = false;
+        if from.addr != server || (locked && from.port != remote.port) {
+            continue;
+        }
+        if reply.len() < 4 {
+            return Err(OsError::IoErrorInvalidData);
+        }
+
+        match u16::from_be_bytes([reply[0], reply[1]]) {
+            OP_DATA => {
+                let number = u16::from_be_bytes([reply[2], reply[3]]);
+                if number != block {
+                    // The server retransmitted the block we acknowledged last,
+                    // so that ACK was lost: send it again.
+                    if locked && number == block.wrapping_sub(1) {
+                        send(handle, &packet, remote)?;
+                    }
+                    continue;
+                }
+                if !locked {
+                    remote.port = from.port;
+                    locked = true;
+                }
+
+                let data = &reply[4..];
+                image.extend_from_slice(data);
+
+                packet.clear();
+                packet.extend_from_slice(&OP_ACK.to_be_bytes());
+                packet.extend_from_slice(&block.to_be_bytes());
+                if data.len() < BLOCK_SIZE {
+                    send(handle, &packet, remote)?;
+                    return Ok(image);
+                }
+
+                block = block.wrapping_add(1);
+                retries = 0;
+                resend = true;
+            }
+            OP_ERROR => return Err(server_error(&reply)),
+            _ => return Err(OsError::IoErrorInvalidData),
+        }
+    }
+}
+
+/// Runs the write request protocol on a bound socket.
+fn upload(handle: SocketHandle, server: IpAddress, filename: &str, data: &[u8]) -> OsResult<()> {
+    let mut packet = request(OP_WRQ, filename);
+
+    // As with reads, the server's transfer ID comes with its first ACK.
+    let mut remote = IpEndpoint::new(server, TFTP_PORT);
+    let mut locked = false;
+    let mut block: u16 = 0;
+    let mut offset = 0;
+    let mut last = false;
+    let mut retries = 0;
+    let mut resend = true;
+    let mut deadline = 0;
+
+    loop {
+        if resend {
+            send(handle, &packet, remote)?;
+            deadline = now() + TIMEOUT_MS;
+        }
+
+        let (reply, from) = match recv(handle, deadline) {
+            Some(received) => received,
+            None => {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    return Err(OsError::IoErrorTimedOut);
+                }
+                resend = true;
+                continue;
+            }
+        };
+
+        resend = false;
+        if from.addr != server || (locked && from.port != remote.port) {
+            continue;
+        }
+        if reply.len() < 4 {
+            return Err(OsError::IoErrorInvalidData);
+        }
+
+        match u16::from_be_bytes([reply[0], reply[1]]) {
+            OP_ACK => {
+                let number = u16::from_be_bytes([reply[2], reply[3]]);
+                if number != block {
+                    continue;
+                }
+                if !locked {
+                    remote.port = from.port;
+                    locked = true;
+                }
+                if last {
+                    return Ok(());
+                }
+
+                // A block shorter than BLOCK_SIZE, possibly empty, ends the
+                // transfer.
+                let chunk = &data[offset..core::cmp::min(offset + BLOCK_SIZE, data.len())];
+                offset += chunk.len();
+                last = chunk.len() < BLOCK_SIZE;
+                block = block.wrapping_add(1);
+
+                packet.clear();
+                packet.extend_from_slice(&OP_DATA.to_be_bytes());
+                packet.extend_from_slice(&block.to_be_bytes());
+                packet.extend_from_slice(chunk);
+                retries = 0;
+                resend = true;
+            }
+            OP_ERROR => return Err(server_error(&reply)),
+            _ => return Err(OsError::IoErrorInvalidData),
+        }
+    }
+}
+
+/// Builds an octet mode read or write request for `filename`.
+fn request(opcode: u16, filename: &str) -> Vec<u8> {
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&opcode.to_be_bytes());
+    packet.extend_from_slice(filename.as_bytes());
+    packet.push(0);
+    packet.extend_from_slice(b"octet");
+    packet.push(0);
+    packet
+}
+
+/// Logs an ERROR packet and maps its code to an `OsError`.
+fn server_error(reply: &[u8]) -> OsError {
+    let code = u16::from_be_bytes([reply[2], reply[3]]);
+    let message = core::str::from_utf8(&reply[4..])
+        .unwrap_or("")
+        .trim_end_matches('\0');
+    info!("tftp: server error {}: {}", code, message);
+    match code {
+        1 => OsError::NoEntry,
+        2 | 7 => OsError::NoAccess,
+        3 => OsError::NoMemory,
+        6 => OsError::FileExists,
+        _ => OsError::IoError,
+    }
+}
+
+/// Sends `packet` to `remote`.
+fn send(handle: SocketHandle, packet: &[u8], remote: IpEndpoint) -> OsResult<()> {
+    ETHERNET
+        .with_udp_socket(handle, |socket| socket.send_slice(packet, remote))
+        .map_err(|_| OsError::IoError)
+}
+
+/// Returns the current time in milliseconds.
+fn now() -> u64 {
+    pi::timer::current_time().as_millis() as u64
+}
+
+/// Waits for a datagram until `deadline`, in milliseconds. The interface is
+/// polled here as well, so this also works where the poll timer interrupt is
+/// masked.
+fn recv(handle: SocketHandle, deadline: u64) -> Option<(Vec<u8>, IpEndpoint)> {
+    loop {
+        let now = now();
+        ETHERNET.poll(Instant::from_millis(now as i64));
+
+        let received = ETHERNET.with_udp_socket(handle, |socket| {
+            socket
+                .recv()
+                .ok()
+                .map(|(data, endpoint)| (data.to_vec(), endpoint))
+        });
+        if received.is_some() {
+            return received;
+        }
+        if now > deadline {
+            return None;
+        }
+        kernel_sleep(POLL_INTERVAL_MS);
+    }
+}
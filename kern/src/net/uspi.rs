@@ -14,7 +14,8 @@ use smoltcp::wire::EthernetAddress;
 use crate::mutex::Mutex;
 use crate::net::Frame;
 use crate::traps::irq::IrqHandlerRegistry;
-use crate::ALLOCATOR;
+use crate::traps::TrapFrame;
+use crate::{ALLOCATOR, FIQ, GLOBAL_IRQ};
 
 const DEBUG_USPI: bool = false;
 pub macro uspi_trace {
@@ -151,37 +152,51 @@ unsafe fn layout(size: usize) -> Layout {
 #[no_mangle]
 fn malloc(size: u32) -> *mut c_void {
     // Lab 5 2.B
-    unimplemented!("malloc")
+    unsafe {
+        let ptr = ALLOCATOR.alloc(layout(size as usize));
+        if ptr.is_null() {
+            return ptr as *mut c_void;
+        }
+        (ptr as *mut usize).write(size as usize);
+        ptr.add(core::mem::size_of::<usize>()) as *mut c_void
+    }
 }
 
 #[no_mangle]
 fn free(ptr: *mut c_void) {
     // Lab 5 2.B
-    unimplemented!("free")
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let base = (ptr as *mut u8).sub(core::mem::size_of::<usize>());
+        let size = (base as *mut usize).read();
+        ALLOCATOR.dealloc(base, layout(size));
+    }
 }
 
 #[no_mangle]
 pub fn TimerSimpleMsDelay(nMilliSeconds: u32) {
     // Lab 5 2.B
-    unimplemented!("TimerSimpleMsDelay")
+    spin_sleep(Duration::from_millis(nMilliSeconds as u64));
 }
 
 #[no_mangle]
 pub fn TimerSimpleusDelay(nMicroSeconds: u32) {
     // Lab 5 2.B
-    unimplemented!("TimerSimpleusDelay")
+    spin_sleep(Duration::from_micros(nMicroSeconds as u64));
 }
 
 #[no_mangle]
 pub fn MsDelay(nMilliSeconds: u32) {
     // Lab 5 2.B
-    unimplemented!("MsDelay")
+    spin_sleep(Duration::from_millis(nMilliSeconds as u64));
 }
 
 #[no_mangle]
 pub fn usDelay(nMicroSeconds: u32) {
     // Lab 5 2.B
-    unimplemented!("usDelay")
+    spin_sleep(Duration::from_micros(nMicroSeconds as u64));
 }
 
 /// Registers `pHandler` to the kernel's IRQ handler registry.
@@ -193,14 +208,42 @@ pub fn usDelay(nMicroSeconds: u32) {
 #[no_mangle]
 pub unsafe fn ConnectInterrupt(nIRQ: u32, pHandler: TInterruptHandler, pParam: *mut c_void) {
     // Lab 5 2.B
-    unimplemented!("ConnectInterrupt")
+    assert!(nIRQ == Interrupt::Usb as u32 || nIRQ == Interrupt::Timer3 as u32);
+    let int = Interrupt::from(nIRQ as usize);
+    let handler = pHandler.expect("USPi registered an empty interrupt handler");
+    // Raw pointers are not `Send`; carry the parameter as an integer instead.
+    let param = pParam as usize;
+    let irq_handler = Box::new(move |_: &mut TrapFrame| unsafe {
+        handler(param as *mut c_void)
+    });
+
+    let mut controller = Controller::new();
+    if int == Interrupt::Usb {
+        FIQ.register((), irq_handler);
+        controller.enable_fiq(int);
+    } else {
+        GLOBAL_IRQ.register(int, irq_handler);
+        controller.enable(int);
+    }
+}
+
+/// Returns a `&str` view of a NUL-terminated C string.
+unsafe fn c_str<'a>(ptr: *const u8) -> &'a str {
+    if ptr.is_null() {
+        return "";
+    }
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    core::str::from_utf8(slice::from_raw_parts(ptr, len)).unwrap_or("<non-utf8>")
 }
 
 /// Writes a log message from USPi using `uspi_trace!` macro.
 #[no_mangle]
 pub unsafe fn DoLogWrite(_pSource: *const u8, _Severity: u32, pMessage: *const u8) {
     // Lab 5 2.B
-    unimplemented!("DoLogWrite")
+    uspi_trace!("{}", c_str(pMessage));
 }
 
 #[no_mangle]
@@ -211,7 +254,12 @@ pub fn DebugHexdump(_pBuffer: *const c_void, _nBufLen: u32, _pSource: *const u8)
 #[no_mangle]
 pub unsafe fn uspi_assertion_failed(pExpr: *const u8, pFile: *const u8, nLine: u32) {
     // Lab 5 2.B
-    unimplemented!("uspi_assertion_failed")
+    panic!(
+        "USPi assertion failed: {} ({}:{})",
+        c_str(pExpr),
+        c_str(pFile),
+        nLine
+    );
 }
 
 pub struct Usb(pub Mutex<Option<USPi>>);
@@ -19,8 +19,28 @@ const_assert_eq!(
     ((1 << USER_MASK_BITS) - 1) << (64 - USER_MASK_BITS)
 );
 pub const USER_STACK_BASE: usize = core::usize::MAX & PAGE_MASK; //0xffff_ffff_ffff_0000
+/// Most bytes the stack of a user process grows to. Only the page at
+/// `USER_STACK_BASE` is mapped at first; the pages below it are mapped when
+/// they are first touched.
+pub const USER_STACK_SIZE: usize = 16 * PAGE_SIZE;
+/// Lowest address of the stack, which ends at the top of the address space.
+pub const USER_STACK_LIMIT: usize = USER_STACK_BASE - (USER_STACK_SIZE - PAGE_SIZE);
+/// The page below the stack. It is never mapped, so a stack overflow faults,
+/// and every other mapping ends at or below it.
+pub const USER_STACK_GUARD: usize = USER_STACK_LIMIT - PAGE_SIZE;
 pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
 const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
+/// Largest argument and environment block placed on a new process's stack.
+pub const USER_ARG_MAX: usize = 16 * 1024;
+/// Most bytes one `read` or `write` system call transfers; longer requests
+/// are cut short.
+pub const USER_IO_MAX: usize = 64 * 1024;
+/// Most descriptors a process has open; descriptor numbers are below it.
+pub const OPEN_MAX: usize = 256;
+/// Size of the stack the kernel maps for each thread a user process starts.
+pub const USER_THREAD_STACK_SIZE: usize = 4 * PAGE_SIZE;
+/// Environment of the user processes the kernel starts.
+pub const USER_ENV: [&str; 1] = ["PATH=/"];
 
 pub const KERN_STACK_BASE: usize = 0x80_000;
 pub const KERN_STACK_ALIGN: usize = PAGE_ALIGN;
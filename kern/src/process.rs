@@ -1,10 +1,21 @@
+mod address_space;
+mod channel;
+mod descriptor;
+mod elf;
+mod pipe;
 mod process;
 mod scheduler;
+mod signal;
 mod stack;
 mod state;
 
-pub use self::process::{Id, Process};
+pub use self::address_space::AddressSpace;
+pub use self::channel::{Channel, Message};
+pub use self::descriptor::{Descriptor, DescriptorTable, STDERR, STDIN, STDOUT};
+pub use self::pipe::Pipe;
+pub use self::process::{kernel_sleep, Id, Process};
 pub use self::scheduler::GlobalScheduler;
+pub use self::signal::Signals;
 pub use self::stack::Stack;
 pub use self::state::State;
 pub use crate::param::TICK;
@@ -0,0 +1,190 @@
+use kernel_api::{OsError, OsResult};
+
+use crate::param::*;
+use crate::vm::{PagePerm, SharedMemory, UserPageTable, VirtualAddr};
+
+/// The memory of a process: its page table and the bounds of its heap. The
+/// threads of a process share one `AddressSpace`.
+#[derive(Debug)]
+pub struct AddressSpace {
+    pub vmap: UserPageTable,
+    /// Start of the heap: the first page after the loaded image.
+    pub heap_start: usize,
+    /// End of the heap as set by `brk`. Pages up to it are mapped.
+    pub brk: usize,
+}
+
+impl AddressSpace {
+    /// Returns an address space over `vmap` with an empty heap at
+    /// `heap_start`.
+    pub fn new(vmap: UserPageTable, heap_start: usize) -> AddressSpace {
+        AddressSpace {
+            vmap,
+            heap_start,
+            brk: heap_start,
+        }
+    }
+
+    /// Returns a copy for a forked process, sharing the pages copy-on-write
+    /// as described in `UserPageTable::fork()`. The TLB of every core is
+    /// flushed, since the pages of this address space were made read-only
+    /// while other threads of it may be running.
+    pub fn fork(&mut self) -> AddressSpace {
+        let vmap = self.vmap.fork();
+        aarch64::invalidate_tlb();
+        AddressSpace {
+            vmap,
+            heap_start: self.heap_start,
+            brk: self.brk,
+        }
+    }
+
+    /// Moves the end of the heap to `brk`, mapping zeroed pages as it grows
+    /// and unmapping the pages wholly past it as it shrinks. A `brk` of 0
+    /// only queries the current end. Returns the new end.
+    ///
+    /// Returns `NoMemory`, leaving the heap unchanged, if `brk` is below the
+    /// start of the heap or the heap would run into another mapping or out of
+    /// memory.
+    pub fn set_brk(&mut self, brk: usize) -> OsResult<usize> {
+        if brk == 0 {
+            return Ok(self.brk);
+        }
+        if brk < self.heap_start || brk > USER_STACK_GUARD {
+            return Err(OsError::NoMemory);
+        }
+        let old_end = (self.brk + PAGE_SIZE - 1) & PAGE_MASK;
+        let new_end = (brk + PAGE_SIZE - 1) & PAGE_MASK;
+        if new_end > old_end {
+            self.vmap
+                .map_range(old_end, new_end, PagePerm::RW)
+                .map_err(|_| OsError::NoMemory)?;
+        } else {
+            self.vmap.unmap_range(new_end, old_end);
+            aarch64::invalidate_tlb();
+        }
+        self.brk = brk;
+        Ok(brk)
+    }
+
+    /// Maps a zeroed page with read/write permission at each unmapped page of
+    /// the stack, from `USER_STACK_LIMIT` up, that overlaps `len` bytes at
+    /// `va`, as the stack grows into them. Other pages of the range are left
+    /// for the caller to check.
+    ///
+    /// Returns `NoMemory` if a page could not be allocated.
+    pub fn grow_stack(&mut self, va: usize, len: usize) -> OsResult<()> {
+        let last = match len {
+            0 => return Ok(()),
+            len => va.saturating_add(len - 1),
+        };
+        if last < USER_STACK_LIMIT {
+            return Ok(());
+        }
+        let mut page = core::cmp::max(va, USER_STACK_LIMIT) & PAGE_MASK;
+        loop {
+            if !self.vmap.is_mapped(VirtualAddr::from(page)) {
+                let frame = self.vmap.try_alloc(VirtualAddr::from(page), PagePerm::RW)?;
+                for byte in frame.iter_mut() {
+                    *byte = 0;
+                }
+            }
+            // The last page of the stack ends at the top of the address space.
+            if page == last & PAGE_MASK {
+                return Ok(());
+            }
+            page += PAGE_SIZE;
+        }
+    }
+
+    /// Maps `len` bytes, rounded up to whole pages, of zeroed memory with
+    /// permission `perm` between the heap and the stack, and returns its
+    /// address. The highest free range is used so the heap can keep growing.
+    ///
+    /// Returns `InvalidArgument` for an empty mapping, and `NoVmSpace` or
+    /// `NoMemory` if the pages could not be found or allocated.
+    pub fn mmap(&mut self, len: usize, perm: PagePerm) -> OsResult<usize> {
+        if len == 0 || len > USER_MAX_VM_SIZE {
+            return Err(OsError::InvalidArgument);
+        }
+        let size = (len + PAGE_SIZE - 1) & PAGE_MASK;
+        let low = (self.brk + PAGE_SIZE - 1) & PAGE_MASK;
+        let start = self
+            .vmap
+            .find_free(size, low, USER_STACK_GUARD)
+            .ok_or(OsError::NoVmSpace)?;
+        self.vmap.map_range(start, start + size, perm)?;
+        Ok(start)
+    }
+
+    /// Maps the pages of `shm` with permission `perm` at the page-aligned
+    /// address `va`, or at the highest free range between the heap and the
+    /// stack if `va` is 0, and returns the address. `munmap()` removes the
+    /// mapping.
+    ///
+    /// Returns `InvalidArgument` if `va` is not page-aligned, `BadAddress` if
+    /// the mapping would reach outside the user space below the stack, and
+    /// `NoVmSpace` if it would overlap another mapping or no range is free.
+    pub fn map_shared(&mut self, shm: &SharedMemory, va: usize, perm: PagePerm) -> OsResult<usize> {
+        if va % PAGE_SIZE != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        let start = match va {
+            0 => {
+                let low = (self.brk + PAGE_SIZE - 1) & PAGE_MASK;
+                self.vmap
+                    .find_free(shm.size(), low, USER_STACK_GUARD)
+                    .ok_or(OsError::NoVmSpace)?
+            }
+            va => va,
+        };
+        self.vmap.map_shared(start, shm.pages(), perm)?;
+        Ok(start)
+    }
+
+    /// Unmaps the pages overlapping `len` bytes at the page-aligned address
+    /// `addr`. Pages in the range that are not mapped are skipped.
+    ///
+    /// Returns `InvalidArgument` if `addr` is not page-aligned, the range is
+    /// empty, or it reaches outside the user space below the stack.
+    pub fn munmap(&mut self, addr: usize, len: usize) -> OsResult<()> {
+        let end = page_range(addr, len)?;
+        self.vmap.unmap_range(addr, end);
+        aarch64::invalidate_tlb();
+        Ok(())
+    }
+
+    /// Changes the permission of the pages overlapping `len` bytes at the
+    /// page-aligned address `addr` to `perm`, as described in
+    /// `UserPageTable::protect()`, and flushes the TLB of every core.
+    ///
+    /// Returns `InvalidArgument` if `addr` is not page-aligned, the range is
+    /// empty, or it reaches outside the user space below the stack, and
+    /// `BadAddress`, changing nothing, if a page in the range is not mapped.
+    pub fn mprotect(&mut self, addr: usize, len: usize, perm: PagePerm) -> OsResult<()> {
+        let end = page_range(addr, len)?;
+        if (addr..end).step_by(PAGE_SIZE).any(|va| !self.vmap.is_mapped(VirtualAddr::from(va))) {
+            return Err(OsError::BadAddress);
+        }
+        for va in (addr..end).step_by(PAGE_SIZE) {
+            self.vmap.protect(VirtualAddr::from(va), perm);
+        }
+        aarch64::invalidate_tlb();
+        Ok(())
+    }
+}
+
+/// Returns the end of the pages overlapping `len` bytes at the page-aligned
+/// address `addr`.
+///
+/// Returns `InvalidArgument` if `addr` is not page-aligned, the range is
+/// empty, or it reaches outside the user space below the stack.
+fn page_range(addr: usize, len: usize) -> OsResult<usize> {
+    if addr % PAGE_SIZE != 0 || len == 0 || addr < USER_IMG_BASE {
+        return Err(OsError::InvalidArgument);
+    }
+    match addr.checked_add(len) {
+        Some(end) if end <= USER_STACK_GUARD => Ok((end + PAGE_SIZE - 1) & PAGE_MASK),
+        _ => Err(OsError::InvalidArgument),
+    }
+}
@@ -0,0 +1,270 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::mutex::Mutex;
+use crate::process::Descriptor;
+
+/// Messages a direction of a channel holds before senders block.
+pub const CHANNEL_CAPACITY: usize = 16;
+
+/// A message: bytes and the descriptors sent along with them.
+#[derive(Debug)]
+pub struct Message {
+    pub data: Vec<u8>,
+    pub fds: Vec<Descriptor>,
+}
+
+#[derive(Debug)]
+struct Queues {
+    /// Messages waiting to be received by each side.
+    inbox: [VecDeque<Message>; 2],
+    /// Number of open ends of each side.
+    open: [usize; 2],
+}
+
+/// One side of a two-way channel. A process blocked on the channel holds a
+/// `Channel` to poll it without keeping an end open.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    queues: Arc<Mutex<Queues>>,
+    side: usize,
+}
+
+/// An open end of a channel, which receives what the other end sends. Clones
+/// count as separate open ends of the same side.
+#[derive(Debug)]
+pub struct ChannelEnd(Channel);
+
+impl Channel {
+    /// Creates a channel and returns its two ends.
+    pub fn new() -> (ChannelEnd, ChannelEnd) {
+        let queues = Arc::new(Mutex::new(Queues {
+            inbox: [VecDeque::new(), VecDeque::new()],
+            open: [1, 1],
+        }));
+        let end = |side| {
+            ChannelEnd(Channel {
+                queues: queues.clone(),
+                side,
+            })
+        };
+        (end(0), end(1))
+    }
+
+    /// Returns `true` if a receive would not block: a message is waiting, or
+    /// every end of the other side is closed.
+    pub fn can_recv(&self) -> bool {
+        let queues = self.queues.lock();
+        !queues.inbox[self.side].is_empty() || queues.open[1 - self.side] == 0
+    }
+
+    /// Returns `true` if a send would not block: the other side has room, or
+    /// every end of it is closed.
+    pub fn can_send(&self) -> bool {
+        let queues = self.queues.lock();
+        let peer = 1 - self.side;
+        queues.inbox[peer].len() < CHANNEL_CAPACITY || queues.open[peer] == 0
+    }
+}
+
+impl ChannelEnd {
+    pub fn channel(&self) -> &Channel {
+        &self.0
+    }
+
+    /// Queues `message` for the other side.
+    ///
+    /// Returns `Ok(Some(message))`, handing it back, if the other side's queue
+    /// is full, and `BrokenPipe` if every end of the other side is closed.
+    /// Returns `InvalidArgument` if `message` carries an end of this channel,
+    /// which the queue it sits in would keep alive forever.
+    pub fn send(&self, message: Message) -> OsResult<Option<Message>> {
+        let carries_self = message.fds.iter().any(|descriptor| match descriptor {
+            Descriptor::Channel(end) => Arc::ptr_eq(&end.0.queues, &self.0.queues),
+            _ => false,
+        });
+        if carries_self {
+            return Err(OsError::InvalidArgument);
+        }
+        let mut queues = self.0.queues.lock();
+        let peer = 1 - self.0.side;
+        if queues.open[peer] == 0 {
+            return Err(OsError::BrokenPipe);
+        }
+        if queues.inbox[peer].len() == CHANNEL_CAPACITY {
+            return Ok(Some(message));
+        }
+        queues.inbox[peer].push_back(message);
+        Ok(None)
+    }
+
+    /// Takes the next message for this side if it has at most `max_len`
+    /// bytes and `max_fds` descriptors.
+    ///
+    /// Returns `Ok(None)` if no message is waiting and the other side is still
+    /// open, `BrokenPipe` if none is waiting and every end of the other side
+    /// is closed, and `InvalidArgument`, leaving the message queued, if it
+    /// does not fit.
+    pub fn recv(&self, max_len: usize, max_fds: usize) -> OsResult<Option<Message>> {
+        let mut queues = self.0.queues.lock();
+        let side = self.0.side;
+        let fits = match queues.inbox[side].front() {
+            Some(message) => message.data.len() <= max_len && message.fds.len() <= max_fds,
+            None if queues.open[1 - side] == 0 => return Err(OsError::BrokenPipe),
+            None => return Ok(None),
+        };
+        if !fits {
+            return Err(OsError::InvalidArgument);
+        }
+        Ok(queues.inbox[side].pop_front())
+    }
+}
+
+impl Clone for ChannelEnd {
+    fn clone(&self) -> ChannelEnd {
+        self.0.queues.lock().open[self.0.side] += 1;
+        ChannelEnd(self.0.clone())
+    }
+}
+
+impl Drop for ChannelEnd {
+    fn drop(&mut self) {
+        let undelivered = {
+            let mut queues = self.0.queues.lock();
+            queues.open[self.0.side] -= 1;
+            if queues.open[self.0.side] == 0 {
+                core::mem::replace(&mut queues.inbox[self.0.side], VecDeque::new())
+            } else {
+                VecDeque::new()
+            }
+        };
+        // Messages nobody can receive any more are dropped outside the lock:
+        // they may carry ends of this very channel.
+        drop(undelivered);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Channel, Message, CHANNEL_CAPACITY};
+    use crate::process::Descriptor;
+    use alloc::vec::Vec;
+    use kernel_api::OsError;
+
+    #[test]
+    fn test_send_and_recv() {
+        let (left, right) = Channel::new();
+        assert!(!right.channel().can_recv());
+        for &data in [&b"one"[..], b"two"].iter() {
+            let message = Message {
+                data: data.to_vec(),
+                fds: Vec::new(),
+            };
+            assert!(left.send(message).unwrap().is_none());
+        }
+        assert!(!left.channel().can_recv());
+        assert!(right.channel().can_recv());
+        assert_eq!(right.recv(16, 0).unwrap().unwrap().data, b"one");
+        assert_eq!(right.recv(16, 0).unwrap().unwrap().data, b"two");
+        assert!(right.recv(16, 0).unwrap().is_none());
+
+        let message = Message {
+            data: b"back".to_vec(),
+            fds: Vec::new(),
+        };
+        assert!(right.send(message).unwrap().is_none());
+        assert_eq!(left.recv(16, 0).unwrap().unwrap().data, b"back");
+    }
+
+    #[test]
+    fn test_full_queue() {
+        let (left, right) = Channel::new();
+        for _ in 0..CHANNEL_CAPACITY {
+            let message = Message {
+                data: b"x".to_vec(),
+                fds: Vec::new(),
+            };
+            assert!(left.send(message).unwrap().is_none());
+        }
+        assert!(!left.channel().can_send());
+        let message = Message {
+            data: b"last".to_vec(),
+            fds: Vec::new(),
+        };
+        let message = left.send(message).unwrap().unwrap();
+        assert_eq!(message.data, b"last");
+
+        right.recv(16, 0).unwrap().unwrap();
+        assert!(left.channel().can_send());
+        assert!(left.send(message).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_message_too_large() {
+        let (left, right) = Channel::new();
+        let message = Message {
+            data: b"four".to_vec(),
+            fds: Vec::new(),
+        };
+        assert!(left.send(message).unwrap().is_none());
+        assert_eq!(right.recv(3, 0).unwrap_err(), OsError::InvalidArgument);
+        assert_eq!(right.recv(4, 0).unwrap().unwrap().data, b"four");
+    }
+
+    #[test]
+    fn test_closed_side() {
+        let (left, right) = Channel::new();
+        let second_right = right.clone();
+        let message = Message {
+            data: b"queued".to_vec(),
+            fds: Vec::new(),
+        };
+        assert!(left.send(message).unwrap().is_none());
+        drop(right);
+        let message = Message {
+            data: b"more".to_vec(),
+            fds: Vec::new(),
+        };
+        assert!(left.send(message).unwrap().is_none());
+        drop(second_right);
+        let message = Message {
+            data: b"lost".to_vec(),
+            fds: Vec::new(),
+        };
+        assert_eq!(left.send(message).unwrap_err(), OsError::BrokenPipe);
+
+        // Messages already sent are still received before the end.
+        let (left, right) = Channel::new();
+        let message = Message {
+            data: b"queued".to_vec(),
+            fds: Vec::new(),
+        };
+        assert!(right.send(message).unwrap().is_none());
+        drop(right);
+        assert_eq!(left.recv(16, 0).unwrap().unwrap().data, b"queued");
+        assert_eq!(left.recv(16, 0).unwrap_err(), OsError::BrokenPipe);
+    }
+
+    #[test]
+    fn test_send_own_end() {
+        let (left, right) = Channel::new();
+        for end in [left.clone(), right.clone()].iter() {
+            let message = Message {
+                data: Vec::new(),
+                fds: vec![Descriptor::Channel(end.clone())],
+            };
+            assert_eq!(left.send(message).unwrap_err(), OsError::InvalidArgument);
+        }
+
+        let (other, _) = Channel::new();
+        let message = Message {
+            data: Vec::new(),
+            fds: vec![Descriptor::Channel(other)],
+        };
+        assert!(left.send(message).unwrap().is_none());
+        assert_eq!(right.recv(16, 1).unwrap().unwrap().fds.len(), 1);
+    }
+}
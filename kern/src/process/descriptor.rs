@@ -0,0 +1,137 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use fat32::vfat::{Dir, File};
+use kernel_api::{OsError, OsResult};
+
+use crate::fs::PiVFatHandle;
+use crate::net::UserSocket;
+use crate::param::OPEN_MAX;
+use crate::process::channel::ChannelEnd;
+use crate::process::pipe::{PipeReader, PipeWriter};
+use crate::vm::SharedMemory;
+
+/// Descriptors every process starts with; all refer to the console.
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+/// An object a process refers to by descriptor number.
+#[derive(Debug)]
+pub enum Descriptor {
+    /// The kernel console.
+    Console,
+    /// A regular file. The read position is kept in the file itself.
+    File(File<PiVFatHandle>),
+    /// A directory and the index of the next entry `readdir` returns.
+    Dir { dir: Dir<PiVFatHandle>, next: usize },
+    /// A TCP or UDP socket.
+    Socket(UserSocket),
+    /// The read end of a pipe.
+    PipeRead(PipeReader),
+    /// The write end of a pipe.
+    PipeWrite(PipeWriter),
+    /// A shared memory region, mapped with `shm_map`.
+    Shm(Arc<SharedMemory>),
+    /// An end of a message channel.
+    Channel(ChannelEnd),
+}
+
+impl Descriptor {
+    /// Returns a copy of this descriptor for `dup` or a forked process. A
+    /// file or directory copy has its own position; a shared memory copy
+    /// refers to the same region. Sockets cannot be
+    /// duplicated and return `None`.
+    pub fn try_clone(&self) -> Option<Descriptor> {
+        match self {
+            Descriptor::Console => Some(Descriptor::Console),
+            Descriptor::File(file) => Some(Descriptor::File(file.clone())),
+            Descriptor::Dir { dir, next } => Some(Descriptor::Dir {
+                dir: dir.clone(),
+                next: *next,
+            }),
+            Descriptor::Socket(_) => None,
+            Descriptor::PipeRead(reader) => Some(Descriptor::PipeRead(reader.clone())),
+            Descriptor::PipeWrite(writer) => Some(Descriptor::PipeWrite(writer.clone())),
+            Descriptor::Shm(shm) => Some(Descriptor::Shm(shm.clone())),
+            Descriptor::Channel(end) => Some(Descriptor::Channel(end.clone())),
+        }
+    }
+}
+
+/// A process's open descriptors, indexed by descriptor number. Closed
+/// descriptors leave a hole that the next `insert()` reuses.
+#[derive(Debug)]
+pub struct DescriptorTable {
+    entries: Vec<Option<Descriptor>>,
+}
+
+impl DescriptorTable {
+    /// Returns a table with `STDIN`, `STDOUT` and `STDERR` open on the console.
+    pub fn new() -> DescriptorTable {
+        let mut entries = Vec::new();
+        for _ in STDIN..=STDERR {
+            entries.push(Some(Descriptor::Console));
+        }
+        DescriptorTable { entries }
+    }
+
+    /// Stores `descriptor` in the lowest free slot and returns its number.
+    pub fn insert(&mut self, descriptor: Descriptor) -> usize {
+        match self.entries.iter().position(|entry| entry.is_none()) {
+            Some(fd) => {
+                self.entries[fd] = Some(descriptor);
+                fd
+            }
+            None => {
+                self.entries.push(Some(descriptor));
+                self.entries.len() - 1
+            }
+        }
+    }
+
+    pub fn get(&self, fd: usize) -> Option<&Descriptor> {
+        self.entries.get(fd).and_then(|entry| entry.as_ref())
+    }
+
+    pub fn get_mut(&mut self, fd: usize) -> Option<&mut Descriptor> {
+        self.entries.get_mut(fd).and_then(|entry| entry.as_mut())
+    }
+
+    /// Removes and returns the descriptor `fd`.
+    pub fn remove(&mut self, fd: usize) -> Option<Descriptor> {
+        self.entries.get_mut(fd).and_then(|entry| entry.take())
+    }
+
+    /// Stores `descriptor` as `fd`, growing the table if needed, and returns
+    /// the descriptor it replaces.
+    ///
+    /// # Errors
+    /// Returns `OsError::InvalidArgument` if `fd` is not below `OPEN_MAX`.
+    pub fn set(&mut self, fd: usize, descriptor: Descriptor) -> OsResult<Option<Descriptor>> {
+        if fd >= OPEN_MAX {
+            return Err(OsError::InvalidArgument);
+        }
+        while self.entries.len() <= fd {
+            self.entries.push(None);
+        }
+        Ok(self.entries[fd].replace(descriptor))
+    }
+
+    /// Returns a copy of the table for a forked process, made with
+    /// `Descriptor::try_clone()`. Sockets are not inherited; their slots are
+    /// left closed in the copy.
+    pub fn fork(&self) -> DescriptorTable {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| entry.as_ref().and_then(Descriptor::try_clone))
+            .collect();
+        DescriptorTable { entries }
+    }
+
+    /// Removes and returns every open descriptor.
+    pub fn take_all(&mut self) -> Vec<Descriptor> {
+        self.entries.drain(..).flatten().collect()
+    }
+}
@@ -0,0 +1,264 @@
+use alloc::vec::Vec;
+
+use shim::io;
+
+use kernel_api::{OsError, OsResult};
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_AARCH64: u16 = 183;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Upper bound on the number of program headers a loadable image may have.
+const MAX_PROGRAM_HEADERS: u16 = 64;
+
+/// Segment type of a loadable segment.
+pub const PT_LOAD: u32 = 1;
+
+/// Segment permission flags.
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+/// The parts of the ELF64 file header the loader needs.
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub entry: u64,
+    pub phoff: u64,
+    pub phnum: u16,
+}
+
+/// An ELF64 program header.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+fn u16_at(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    let mut raw = [0; 4];
+    raw.copy_from_slice(&bytes[pos..pos + 4]);
+    u32::from_le_bytes(raw)
+}
+
+fn u64_at(bytes: &[u8], pos: usize) -> u64 {
+    let mut raw = [0; 8];
+    raw.copy_from_slice(&bytes[pos..pos + 8]);
+    u64::from_le_bytes(raw)
+}
+
+/// Fills `buf` from `image`, treating a short read as a truncated image.
+fn read_exact<R: io::Read>(image: &mut R, buf: &mut [u8]) -> OsResult<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match image.read(&mut buf[filled..]) {
+            Ok(0) => return Err(OsError::InvalidExecutable),
+            Ok(n) => filled += n,
+            Err(e) => return Err(OsError::from(e)),
+        }
+    }
+    Ok(())
+}
+
+impl Header {
+    /// Reads and validates the file header at the start of `image`.
+    ///
+    /// # Errors
+    ///
+    /// - `OsError::InvalidExecutable`: The image is not a little-endian ELF64
+    ///   executable, or its header is malformed.
+    /// - `OsError::UnsupportedArchitecture`: The image is built for a machine other
+    ///   than AArch64.
+    pub fn read<R: io::Read>(image: &mut R) -> OsResult<Header> {
+        let mut bytes = [0u8; HEADER_SIZE];
+        read_exact(image, &mut bytes)?;
+
+        if bytes[0..4] != ELF_MAGIC
+            || bytes[4] != ELFCLASS64
+            || bytes[5] != ELFDATA2LSB
+            || bytes[6] != EV_CURRENT
+        {
+            return Err(OsError::InvalidExecutable);
+        }
+        if u16_at(&bytes, 18) != EM_AARCH64 {
+            return Err(OsError::UnsupportedArchitecture);
+        }
+        if u16_at(&bytes, 16) != ET_EXEC {
+            return Err(OsError::InvalidExecutable);
+        }
+
+        let phentsize = u16_at(&bytes, 54) as usize;
+        let phnum = u16_at(&bytes, 56);
+        if phentsize != PROGRAM_HEADER_SIZE || phnum == 0 || phnum > MAX_PROGRAM_HEADERS {
+            return Err(OsError::InvalidExecutable);
+        }
+
+        Ok(Header {
+            entry: u64_at(&bytes, 24),
+            phoff: u64_at(&bytes, 32),
+            phnum,
+        })
+    }
+
+    /// Reads the program headers listed in this header.
+    pub fn program_headers<R: io::Read + io::Seek>(
+        &self,
+        image: &mut R,
+    ) -> OsResult<Vec<ProgramHeader>> {
+        image
+            .seek(io::SeekFrom::Start(self.phoff))
+            .map_err(|_| OsError::InvalidExecutable)?;
+
+        let mut headers = Vec::with_capacity(self.phnum as usize);
+        let mut bytes = [0u8; PROGRAM_HEADER_SIZE];
+        for _ in 0..self.phnum {
+            read_exact(image, &mut bytes)?;
+            let header = ProgramHeader {
+                kind: u32_at(&bytes, 0),
+                flags: u32_at(&bytes, 4),
+                offset: u64_at(&bytes, 8),
+                vaddr: u64_at(&bytes, 16),
+                filesz: u64_at(&bytes, 32),
+                memsz: u64_at(&bytes, 40),
+            };
+            if header.kind == PT_LOAD && header.filesz > header.memsz {
+                return Err(OsError::InvalidExecutable);
+            }
+            headers.push(header);
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Header, HEADER_SIZE, PF_R, PF_X, PT_LOAD};
+    use kernel_api::OsError;
+    use shim::io::Cursor;
+
+    /// An AArch64 executable with entry point 0x1234 and two program
+    /// headers: 8 bytes at offset 0x100 loaded R-X at 0x2000 and zero-filled
+    /// to 16 bytes, and 32 zero-filled bytes at 0x3000.
+    #[rustfmt::skip]
+    const IMAGE: [u8; 176] = [
+        // File header.
+        0x7f, b'E', b'L', b'F', 2, 1, 1, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        2, 0, 183, 0, 1, 0, 0, 0,
+        0x34, 0x12, 0, 0, 0, 0, 0, 0,
+        64, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 64, 0, 56, 0,
+        2, 0, 0, 0, 0, 0, 0, 0,
+        // First program header.
+        1, 0, 0, 0, 5, 0, 0, 0,
+        0, 1, 0, 0, 0, 0, 0, 0,
+        0, 0x20, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        8, 0, 0, 0, 0, 0, 0, 0,
+        16, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        // Second program header.
+        1, 0, 0, 0, 6, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0x30, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        32, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+
+    #[test]
+    fn test_read() {
+        let mut image = Cursor::new(&IMAGE[..]);
+        let header = Header::read(&mut image).unwrap();
+        assert_eq!((header.entry, header.phoff, header.phnum), (0x1234, 64, 2));
+
+        let program_headers = header.program_headers(&mut image).unwrap();
+        assert_eq!(program_headers.len(), 2);
+        assert_eq!(program_headers[0].kind, PT_LOAD);
+        assert_eq!(program_headers[0].flags, PF_R | PF_X);
+        assert_eq!(program_headers[0].offset, 0x100);
+        assert_eq!(program_headers[0].vaddr, 0x2000);
+        assert_eq!(program_headers[0].filesz, 8);
+        assert_eq!(program_headers[0].memsz, 16);
+        assert_eq!(program_headers[1].vaddr, 0x3000);
+        assert_eq!(program_headers[1].memsz, 32);
+    }
+
+    #[test]
+    fn test_bad_header() {
+        for &len in [0, 4, 20, HEADER_SIZE - 1].iter() {
+            let mut image = Cursor::new(&IMAGE[..len]);
+            assert_eq!(
+                Header::read(&mut image).unwrap_err(),
+                OsError::InvalidExecutable
+            );
+        }
+
+        // Magic, class, type, program header size, and no or too many
+        // program headers.
+        for &(pos, byte) in [(1, b'X'), (4, 1), (16, 3), (54, 32), (56, 0), (56, 65)].iter() {
+            let mut bytes = IMAGE;
+            bytes[pos] = byte;
+            let mut image = Cursor::new(&bytes[..]);
+            assert_eq!(
+                Header::read(&mut image).unwrap_err(),
+                OsError::InvalidExecutable
+            );
+        }
+
+        // x86-64.
+        let mut bytes = IMAGE;
+        bytes[18] = 62;
+        let mut image = Cursor::new(&bytes[..]);
+        assert_eq!(
+            Header::read(&mut image).unwrap_err(),
+            OsError::UnsupportedArchitecture
+        );
+    }
+
+    #[test]
+    fn test_bad_program_headers() {
+        // Program headers past the end of the image.
+        let mut bytes = IMAGE;
+        bytes[34] = 0x10;
+        let mut image = Cursor::new(&bytes[..]);
+        let header = Header::read(&mut image).unwrap();
+        assert_eq!(
+            header.program_headers(&mut image).unwrap_err(),
+            OsError::InvalidExecutable
+        );
+
+        // The image ends in the middle of the second program header.
+        let mut image = Cursor::new(&IMAGE[..IMAGE.len() - 1]);
+        let header = Header::read(&mut image).unwrap();
+        assert_eq!(
+            header.program_headers(&mut image).unwrap_err(),
+            OsError::InvalidExecutable
+        );
+
+        // A segment larger in the file than in memory.
+        let mut bytes = IMAGE;
+        bytes[96] = 32;
+        let mut image = Cursor::new(&bytes[..]);
+        let header = Header::read(&mut image).unwrap();
+        assert_eq!(
+            header.program_headers(&mut image).unwrap_err(),
+            OsError::InvalidExecutable
+        );
+    }
+}
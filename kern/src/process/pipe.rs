@@ -0,0 +1,189 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::mutex::Mutex;
+
+/// Bytes a pipe holds before writers block.
+pub const PIPE_CAPACITY: usize = 4096;
+
+#[derive(Debug)]
+struct Buffer {
+    data: VecDeque<u8>,
+    /// Number of open read ends.
+    readers: usize,
+    /// Number of open write ends.
+    writers: usize,
+}
+
+/// The buffer of a pipe, shared by its ends. A process blocked on the pipe
+/// holds a `Pipe` to poll it without keeping an end open.
+#[derive(Debug, Clone)]
+pub struct Pipe(Arc<Mutex<Buffer>>);
+
+/// The read end of a pipe. Clones count as separate open ends.
+#[derive(Debug)]
+pub struct PipeReader(Pipe);
+
+/// The write end of a pipe. Clones count as separate open ends.
+#[derive(Debug)]
+pub struct PipeWriter(Pipe);
+
+impl Pipe {
+    /// Creates an empty pipe and returns its two ends.
+    pub fn new() -> (PipeReader, PipeWriter) {
+        let pipe = Pipe(Arc::new(Mutex::new(Buffer {
+            data: VecDeque::with_capacity(PIPE_CAPACITY),
+            readers: 1,
+            writers: 1,
+        })));
+        (PipeReader(pipe.clone()), PipeWriter(pipe))
+    }
+
+    /// Returns `true` if a read would not block: there is data, or every
+    /// write end is closed.
+    pub fn is_readable(&self) -> bool {
+        let buffer = self.0.lock();
+        !buffer.data.is_empty() || buffer.writers == 0
+    }
+
+    /// Returns `true` if a write would not block: there is room, or every
+    /// read end is closed.
+    pub fn is_writable(&self) -> bool {
+        let buffer = self.0.lock();
+        buffer.data.len() < PIPE_CAPACITY || buffer.readers == 0
+    }
+}
+
+impl PipeReader {
+    pub fn pipe(&self) -> &Pipe {
+        &self.0
+    }
+
+    /// Moves up to `buf.len()` buffered bytes into `buf` and returns how many
+    /// were read; 0 means every write end is closed.
+    ///
+    /// Returns `None` if the pipe is empty and a write end is still open.
+    pub fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut buffer = (self.0).0.lock();
+        if buf.is_empty() {
+            return Some(0);
+        }
+        if buffer.data.is_empty() {
+            return if buffer.writers == 0 { Some(0) } else { None };
+        }
+        let len = core::cmp::min(buf.len(), buffer.data.len());
+        for (dst, src) in buf.iter_mut().zip(buffer.data.drain(..len)) {
+            *dst = src;
+        }
+        Some(len)
+    }
+}
+
+impl PipeWriter {
+    pub fn pipe(&self) -> &Pipe {
+        &self.0
+    }
+
+    /// Appends as much of `buf` as fits and returns how many bytes were
+    /// written.
+    ///
+    /// Returns `Ok(None)` if the pipe is full, and `BrokenPipe` if every read
+    /// end is closed.
+    pub fn write(&self, buf: &[u8]) -> OsResult<Option<usize>> {
+        let mut buffer = (self.0).0.lock();
+        if buffer.readers == 0 {
+            return Err(OsError::BrokenPipe);
+        }
+        if buf.is_empty() {
+            return Ok(Some(0));
+        }
+        let len = core::cmp::min(buf.len(), PIPE_CAPACITY - buffer.data.len());
+        if len == 0 {
+            return Ok(None);
+        }
+        buffer.data.extend(buf[..len].iter());
+        Ok(Some(len))
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> PipeReader {
+        (self.0).0.lock().readers += 1;
+        PipeReader(self.0.clone())
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> PipeWriter {
+        (self.0).0.lock().writers += 1;
+        PipeWriter(self.0.clone())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        (self.0).0.lock().readers -= 1;
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        (self.0).0.lock().writers -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pipe, PIPE_CAPACITY};
+    use kernel_api::OsError;
+
+    #[test]
+    fn test_read_in_order() {
+        let (reader, writer) = Pipe::new();
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read(&mut buf), None);
+        assert_eq!(writer.write(b"hello"), Ok(Some(5)));
+        assert_eq!(writer.write(b" world"), Ok(Some(6)));
+        assert!(reader.pipe().is_readable());
+        assert_eq!(reader.read(&mut buf), Some(8));
+        assert_eq!(&buf, b"hello wo");
+        assert_eq!(reader.read(&mut buf), Some(3));
+        assert_eq!(&buf[..3], b"rld");
+        assert!(!reader.pipe().is_readable());
+    }
+
+    #[test]
+    fn test_full_pipe() {
+        let (reader, writer) = Pipe::new();
+        let data = [7u8; PIPE_CAPACITY + 10];
+        assert_eq!(writer.write(&data), Ok(Some(PIPE_CAPACITY)));
+        assert!(!writer.pipe().is_writable());
+        assert_eq!(writer.write(&data), Ok(None));
+
+        let mut buf = [0u8; 16];
+        assert_eq!(reader.read(&mut buf), Some(16));
+        assert_eq!(writer.write(&data), Ok(Some(16)));
+        assert_eq!(writer.write(&data), Ok(None));
+    }
+
+    #[test]
+    fn test_closed_ends() {
+        let (reader, writer) = Pipe::new();
+        let second_writer = writer.clone();
+        assert_eq!(writer.write(b"abc"), Ok(Some(3)));
+        drop(writer);
+        drop(second_writer);
+
+        // Buffered data is still read before the end of the stream.
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read(&mut buf), Some(3));
+        assert_eq!(reader.read(&mut buf), Some(0));
+
+        let (reader, writer) = Pipe::new();
+        drop(reader);
+        assert!(writer.pipe().is_writable());
+        assert_eq!(writer.write(b"abc"), Err(OsError::BrokenPipe));
+    }
+}
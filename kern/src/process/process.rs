@@ -1,34 +1,71 @@
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use shim::io;
-use shim::path::Path;
+use shim::path::{Path, PathBuf};
 
 use aarch64;
-use smoltcp::socket::SocketHandle;
 
+use crate::mutex::Mutex;
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::{elf, AddressSpace, DescriptorTable, Signals, Stack, State};
 use crate::traps::TrapFrame;
 use crate::vm::*;
 use kernel_api::{OsError, OsResult};
 
-/// Type alias for the type of a process ID.
+/// Type alias for the type of a process ID, which is also used for thread IDs.
 pub type Id = u64;
 
-/// A structure that represents the complete state of a process.
+/// A structure that represents the complete state of a process, or of one
+/// thread of a process.
+///
+/// Each thread is scheduled as a `Process` of its own. Its thread ID is kept
+/// in `context.tpidr_el0`; the first thread's ID is the process ID. The
+/// threads of a process share its memory and descriptors; the other fields
+/// are per thread.
 #[derive(Debug)]
 pub struct Process {
     /// The saved trap frame of a process.
     pub context: Box<TrapFrame>,
-    /// The memory allocation used for the process's stack.
-    //pub stack: Stack,
-    /// The page table describing the Virtual Memory of the process
-    pub vmap: Box<UserPageTable>,
+    /// The memory allocation used for the process's stack. Only kernel
+    /// threads own one; user processes map their stack in `mem`.
+    pub stack: Option<Stack>,
+    /// The page table and heap of the process, shared by its threads.
+    pub mem: Arc<Mutex<AddressSpace>>,
+    /// The ID of the process this thread belongs to.
+    pub pid: Id,
     /// The scheduling state of the process.
     pub state: State,
     // Lab 5 2.C
-    // Socket handles held by the current process
-    // pub sockets: Vec<SocketHandle>,
+    /// Files, directories, sockets and the console opened by the process,
+    /// indexed by descriptor. Shared by its threads.
+    pub fds: Arc<Mutex<DescriptorTable>>,
+    /// The directory relative paths are resolved against.
+    pub cwd: PathBuf,
+    /// The process that started this one with `fork` or `spawn` and collects
+    /// its exit status, or `None` for processes started by the kernel and
+    /// orphans.
+    pub parent: Option<Id>,
+    /// Set when a child exits, to wake this process from `waitpid`.
+    pub child_exited: bool,
+    /// Pending signals and their handlers.
+    pub signals: Signals,
+    /// Set when another thread of the process exits, to wake this one from
+    /// `thread_join`.
+    pub thread_exited: bool,
+    /// Base of the stack mapping the kernel made for a thread started with
+    /// `thread_create`. It is unmapped when the thread exits.
+    pub thread_stack: Option<usize>,
+    /// Physical address of the futex word this process is waiting on, while
+    /// it waits in `futex_wait`. Cleared by `futex_wake`.
+    pub futex: Option<usize>,
+    /// Set to log the system calls of this thread. New threads copy it from
+    /// their creator; children started with `fork` or `spawn` do not.
+    pub traced: bool,
+    /// The status of the process, set on a thread that was running on another
+    /// core when its process exited or called `exec`. The thread becomes a
+    /// zombie the next time it enters the kernel.
+    pub exit_pending: Option<i32>,
 }
 
 impl Process {
@@ -43,69 +80,158 @@ impl Process {
             Some(st) => 
                 Ok(Process {
                     context : Box::new(TrapFrame::default()),
-                    //stack : st,
-                    vmap : Box::new(UserPageTable::new()),
-                    state : State::Ready
+                    stack : Some(st),
+                    mem : Arc::new(Mutex::new(AddressSpace::new(UserPageTable::new(), 0))),
+                    pid : 0,
+                    state : State::Ready,
+                    fds : Arc::new(Mutex::new(DescriptorTable::new())),
+                    cwd : PathBuf::from("/"),
+                    parent : None,
+                    child_exited : false,
+                    signals : Signals::new(),
+                    thread_exited : false,
+                    thread_stack : None,
+                    futex : None,
+                    traced : false,
+                    exit_pending : None,
                 })
         }
     }
 
-    /// Loads a program stored in the given path by calling `do_load()` method.
-    /// Sets trapframe `context` corresponding to its page table.
-    /// `sp` - the address of stack top
-    /// `elr` - the address of image base.
-    /// `ttbr0` - the base address of kernel page table
-    /// `ttbr1` - the base address of user page table
-    /// `spsr` - `F`, `A`, `D` bit should be set.
+    /// Creates a kernel thread that starts executing `entry` with `arg` in its
+    /// first argument register.
     ///
-    /// Returns Os Error if do_load fails.
-    pub fn load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
+    /// The thread runs at EL1 on its own `Stack` using `SP_EL0` (EL1t), so
+    /// exceptions taken from it are handled on the per-core kernel stack just
+    /// like those from user processes. IRQs are masked: a kernel thread is
+    /// never preempted while holding a lock and must yield explicitly with
+    /// `kernel_sleep()`. Returning from `entry` exits the thread.
+    ///
+    /// Returns Os Error if the stack could not be allocated.
+    pub fn kernel_thread(entry: extern "C" fn(u64), arg: u64) -> OsResult<Process> {
         use crate::VMM;
 
-        let mut p = Process::do_load(pn)?;
-        p.context.elr_el1 = USER_IMG_BASE as u64;
+        let mut p = Process::new()?;
+        p.context.sp_el0 = p.stack.as_ref().unwrap().top().as_u64();
+        p.context.elr_el1 = entry as u64;
+        p.context.x[0] = arg;
+        // The trap frame slot after `x29` holds the saved link register.
+        p.context.xzr = kernel_thread_exit as u64;
         p.context.ttbr0_el1 = VMM.get_baddr().as_u64();
-        p.context.ttbr1_el1 = p.vmap.as_ref().get_baddr().as_u64();
+        p.context.ttbr1_el1 = p.mem.lock().vmap.get_baddr().as_u64();
         p.context.spsr_el1 = (0b1<<9) | //'D'
                              (0b1<<8) | //'A'
-                             (0b1<<6) ;//'F'
+                             (0b1<<7) | //'I'
+                             (0b1<<6) | //'F'
+                             0b0100;    // EL1t
+        Ok(p)
+    }
+
+    /// Loads a program stored in the given path by calling `do_load()` method
+    /// and copies `argv` and `envp` onto its stack with `push_args()`.
+    /// Sets trapframe `context` corresponding to its page table.
+    /// `sp` - the address of `argc` on the stack
+    /// `elr` - the entry point of the image.
+    /// `x0`, `x1`, `x2` - `argc`, `argv` and `envp`
+    /// `ttbr0` - the base address of kernel page table
+    /// `ttbr1` - the base address of user page table
+    /// `spsr` - `F`, `A`, `D` bit should be set.
+    ///
+    /// Returns Os Error if do_load fails or the arguments do not fit in
+    /// `USER_ARG_MAX` bytes.
+    pub fn load<P: AsRef<Path>>(pn: P, argv: &[&str], envp: &[&str]) -> OsResult<Process> {
+        let mut p = Process::do_load(pn)?;
+        p.push_args(argv, envp)?;
+        p.init_user_context();
+        Ok(p)
+    }
 
+    /// Loads a program from an in-memory image, such as one received over the
+    /// network, and sets up its stack and trapframe the same way `load()` does.
+    ///
+    /// Returns Os Error if the image could not be copied into a new process.
+    pub fn load_image(image: &[u8], argv: &[&str], envp: &[&str]) -> OsResult<Process> {
+        let mut p = Process::do_load_from(&mut io::Cursor::new(image))?;
+        p.push_args(argv, envp)?;
+        p.init_user_context();
         Ok(p)
     }
 
+    /// Copies `argv` and `envp` to the top of the stack page in the AArch64
+    /// System V layout, and points `sp` at it:
+    ///
+    /// ```text
+    /// sp -> argc
+    ///       argv[0] .. argv[argc - 1], NULL
+    ///       envp[0] .. envp[n - 1], NULL
+    ///       AT_NULL, 0                      (empty auxiliary vector)
+    ///       NUL-terminated strings
+    /// ```
+    ///
+    /// `x0`, `x1` and `x2` are set to `argc`, `argv` and `envp` as well so the
+    /// entry point can take them as arguments.
+    fn push_args(&mut self, argv: &[&str], envp: &[&str]) -> OsResult<()> {
+        let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2;
+        let strings: usize = argv.iter().chain(envp.iter()).map(|s| s.len() + 1).sum();
+        let size = words * 8 + strings;
+        if size > USER_ARG_MAX {
+            return Err(OsError::InvalidArgument);
+        }
+
+        // Offsets into the stack page; its last byte is the top of the stack.
+        let sp = (PAGE_SIZE - size) & !(PAGE_ALIGN - 1);
+        let argv_offset = sp + 8;
+        let envp_offset = argv_offset + (argv.len() + 1) * 8;
+        let mut mem = self.mem.lock();
+        let page = mem
+            .vmap
+            .get_page(VirtualAddr::from(USER_STACK_BASE))
+            .ok_or(OsError::BadAddress)?;
+
+        let mut word = sp;
+        let mut string = sp + words * 8;
+        let mut push_word = |page: &mut [u8], value: u64| {
+            page[word..word + 8].copy_from_slice(&value.to_le_bytes());
+            word += 8;
+        };
+        push_word(page, argv.len() as u64);
+        for list in [argv, envp].iter() {
+            for s in list.iter() {
+                push_word(page, (USER_STACK_BASE + string) as u64);
+                page[string..string + s.len()].copy_from_slice(s.as_bytes());
+                page[string + s.len()] = 0;
+                string += s.len() + 1;
+            }
+            push_word(page, 0);
+        }
+        push_word(page, 0);
+        push_word(page, 0);
+
+        self.context.sp_el0 = (USER_STACK_BASE + sp) as u64;
+        self.context.x[0] = argv.len() as u64;
+        self.context.x[1] = (USER_STACK_BASE + argv_offset) as u64;
+        self.context.x[2] = (USER_STACK_BASE + envp_offset) as u64;
+        Ok(())
+    }
+
+    /// Points trapframe `context` at the loaded image's page table.
+    fn init_user_context(&mut self) {
+        use crate::VMM;
+
+        self.context.ttbr0_el1 = VMM.get_baddr().as_u64();
+        self.context.ttbr1_el1 = self.mem.lock().vmap.get_baddr().as_u64();
+        self.context.spsr_el1 = (0b1<<9) | //'D'
+                                (0b1<<8) | //'A'
+                                (0b1<<6) ;//'F'
+    }
+
     /// Creates a process and open a file with given path.
-    /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
-    /// permission to load file's contents.
+    /// Loads the file's contents with `do_load_from()`.
     fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-        use core::ops::AddAssign;
         use fat32::traits::FileSystem;
-        use io::Read;
         crate::console::kprintln!("{:?} program ", pn.as_ref().as_os_str());
         match crate::FILESYSTEM.open_file(pn) {
-            Ok(mut file) => {
-                let mut vmap = Box::new(UserPageTable::new());
-                let mut va =  VirtualAddr::from(USER_IMG_BASE as u64);
-                let mut context =  Box::new(TrapFrame::default());
-                loop {
-                    let mut buffer = vmap.alloc(va, PagePerm::RWX);
-                    match file.read(&mut buffer) {
-                        Ok(PAGE_SIZE) => va.add_assign(VirtualAddr::from(PAGE_SIZE as u64)),
-                        Ok(_) => break,
-                        Err(_) => return Err(OsError::IoError),
-                    }
-                }
-                //allocate stack memory
-                va.add_assign(VirtualAddr::from(PAGE_SIZE as u64));
-                vmap.alloc(va, PagePerm::RW);
-                context.sp_el0 = (va.as_usize()+ PAGE_SIZE - PAGE_ALIGN) as u64;
-                Ok (Process {
-                    context : context,
-                    vmap : vmap,
-                    state : State::Ready
-                })
-
-
-            },
+            Ok(mut file) => Process::do_load_from(&mut file),
             _ => {
                 crate::console::kprintln!("program not found");
                 Err(OsError::NoEntry)
@@ -116,6 +242,256 @@ impl Process {
 
     }
 
+    /// Creates a process from the ELF64 executable read from `image`.
+    /// Each `PT_LOAD` segment is mapped at its virtual address with the
+    /// permissions of its flags, so code is read-only and only code is
+    /// executable; bytes past the segment's file contents (`.bss`) are zero.
+    /// A page shared by several segments gets the union of their permissions.
+    /// The top page of the stack is allocated at `USER_STACK_BASE` with
+    /// read/write permission; the rest of the stack is mapped as it grows.
+    /// The heap starts empty at the first page past the image.
+    ///
+    /// Returns `InvalidExecutable` if the image is not a valid executable or
+    /// a segment lies outside the user image region, and
+    /// `UnsupportedArchitecture` if it is not built for AArch64.
+    fn do_load_from<R: io::Read + io::Seek>(image: &mut R) -> OsResult<Process> {
+        let header = elf::Header::read(image)?;
+        let segments: Vec<elf::ProgramHeader> = header
+            .program_headers(image)?
+            .into_iter()
+            .filter(|ph| ph.kind == elf::PT_LOAD && ph.memsz > 0)
+            .collect();
+        if segments.is_empty() {
+            return Err(OsError::InvalidExecutable);
+        }
+
+        let mut pages: Vec<(usize, PagePerm)> = Vec::new();
+        let mut heap_start = USER_IMG_BASE;
+        let mut entry_is_code = false;
+        for ph in segments.iter() {
+            let start = ph.vaddr as usize;
+            let end = start
+                .checked_add(ph.memsz as usize)
+                .ok_or(OsError::InvalidExecutable)?;
+            if start < USER_IMG_BASE || end > USER_STACK_GUARD {
+                return Err(OsError::InvalidExecutable);
+            }
+            if ph.flags & elf::PF_X != 0 && (start..end).contains(&(header.entry as usize)) {
+                entry_is_code = true;
+            }
+
+            heap_start = core::cmp::max(heap_start, (end + PAGE_SIZE - 1) & PAGE_MASK);
+
+            let perm = segment_perm(ph.flags);
+            let mut page = start & PAGE_MASK;
+            while page < end {
+                match pages.iter_mut().find(|(va, _)| *va == page) {
+                    Some((_, page_perm)) => *page_perm = merge_perm(*page_perm, perm),
+                    None => pages.push((page, perm)),
+                }
+                page += PAGE_SIZE;
+            }
+        }
+        if !entry_is_code {
+            return Err(OsError::InvalidExecutable);
+        }
+
+        let mut vmap = UserPageTable::new();
+        for &(va, perm) in pages.iter() {
+            for byte in vmap.alloc(VirtualAddr::from(va), perm).iter_mut() {
+                *byte = 0;
+            }
+        }
+
+        for ph in segments.iter() {
+            image
+                .seek(io::SeekFrom::Start(ph.offset))
+                .map_err(|_| OsError::InvalidExecutable)?;
+            let mut va = ph.vaddr as usize;
+            let end = va + ph.filesz as usize;
+            while va < end {
+                let page_offset = va % PAGE_SIZE;
+                let len = core::cmp::min(PAGE_SIZE - page_offset, end - va);
+                let page = vmap
+                    .get_page(VirtualAddr::from(va & PAGE_MASK))
+                    .ok_or(OsError::BadAddress)?;
+                let mut filled = 0;
+                while filled < len {
+                    match image.read(&mut page[page_offset + filled..page_offset + len]) {
+                        Ok(0) => return Err(OsError::InvalidExecutable),
+                        Ok(n) => filled += n,
+                        Err(_) => return Err(OsError::IoError),
+                    }
+                }
+                va += len;
+            }
+        }
+
+        //allocate stack memory
+        let mut context = Box::new(TrapFrame::default());
+        vmap.alloc(VirtualAddr::from(USER_STACK_BASE), PagePerm::RW);
+        context.sp_el0 = Process::get_stack_top().as_u64();
+        context.elr_el1 = header.entry;
+        Ok (Process {
+            context : context,
+            stack : None,
+            mem : Arc::new(Mutex::new(AddressSpace::new(vmap, heap_start))),
+            pid : 0,
+            state : State::Ready,
+            fds : Arc::new(Mutex::new(DescriptorTable::new())),
+            cwd : PathBuf::from("/"),
+            parent : None,
+            child_exited : false,
+            signals : Signals::new(),
+            thread_exited : false,
+            thread_stack : None,
+            futex : None,
+            traced : false,
+            exit_pending : None,
+        })
+    }
+
+    /// Creates a child of this user process whose state is `tf`, the trap
+    /// frame of the `fork` system call. The child returns 0 from the call,
+    /// shares the parent's memory copy-on-write, and gets a copy of its
+    /// descriptors and working directory. Only the calling thread is copied.
+    ///
+    /// Returns `InvalidArgument` for a kernel thread, which has no user
+    /// address space to copy.
+    pub fn fork(&mut self, tf: &TrapFrame) -> OsResult<Process> {
+        if self.stack.is_some() {
+            return Err(OsError::InvalidArgument);
+        }
+        let mem = self.mem.lock().fork();
+        let mut context = Box::new(*tf);
+        context.ttbr1_el1 = mem.vmap.get_baddr().as_u64();
+        context.x[0] = 0;
+        context.x[7] = OsError::Ok as u64;
+        Ok(Process {
+            context : context,
+            stack : None,
+            mem : Arc::new(Mutex::new(mem)),
+            pid : 0,
+            state : State::Ready,
+            fds : Arc::new(Mutex::new(self.fds.lock().fork())),
+            cwd : self.cwd.clone(),
+            parent : Some(self.pid),
+            child_exited : false,
+            signals : self.signals.fork(),
+            thread_exited : false,
+            thread_stack : None,
+            futex : None,
+            traced : false,
+            exit_pending : None,
+        })
+    }
+
+    /// Creates a new thread of this user process that starts executing `entry`
+    /// with `arg` in its first argument register, on the stack mapping of
+    /// `stack_size` bytes at `stack_base`. The thread shares the process's
+    /// memory and descriptors, and starts with a copy of this thread's working
+    /// directory and signal handlers. Its thread ID is set when it is queued.
+    ///
+    /// Returns `InvalidArgument` for a kernel thread.
+    pub fn thread(&self, entry: u64, arg: u64, stack_base: usize, stack_size: usize) -> OsResult<Process> {
+        if self.stack.is_some() {
+            return Err(OsError::InvalidArgument);
+        }
+        let mut thread = Process {
+            context : Box::new(TrapFrame::default()),
+            stack : None,
+            mem : self.mem.clone(),
+            pid : self.pid,
+            state : State::Ready,
+            fds : self.fds.clone(),
+            cwd : self.cwd.clone(),
+            parent : self.parent,
+            child_exited : false,
+            signals : self.signals.fork(),
+            thread_exited : false,
+            thread_stack : Some(stack_base),
+            futex : None,
+            traced : self.traced,
+            exit_pending : None,
+        };
+        thread.init_user_context();
+        thread.context.elr_el1 = entry;
+        thread.context.x[0] = arg;
+        thread.context.sp_el0 = (stack_base + stack_size) as u64;
+        Ok(thread)
+    }
+
+    /// Returns `true` if this is the first thread of its process, whose
+    /// thread ID is the process ID.
+    pub fn is_main_thread(&self) -> bool {
+        self.context.tpidr_el0 == self.pid
+    }
+
+    /// Replaces the memory and registers of this user process with those of
+    /// `image`, a freshly loaded program, and writes the new registers to
+    /// `tf`. The process keeps its ID, descriptors and working directory. The
+    /// caller must have ended the other threads of the process.
+    ///
+    /// Returns the old memory, which is still live in `TTBR1`; the caller
+    /// must keep it until `TTBR1` no longer refers to it.
+    ///
+    /// Returns `InvalidArgument` for a kernel thread.
+    pub fn exec(
+        &mut self,
+        image: Process,
+        tf: &mut TrapFrame,
+    ) -> OsResult<Arc<Mutex<AddressSpace>>> {
+        if self.stack.is_some() {
+            return Err(OsError::InvalidArgument);
+        }
+        *tf = *image.context;
+        tf.tpidr_el0 = self.pid;
+        let old = core::mem::replace(&mut self.mem, image.mem);
+        self.thread_stack = None;
+        self.signals.exec();
+        Ok(old)
+    }
+
+    /// Moves the end of the heap with `AddressSpace::set_brk()`.
+    ///
+    /// Returns `InvalidArgument` for a kernel thread.
+    pub fn set_brk(&mut self, brk: usize) -> OsResult<usize> {
+        if self.stack.is_some() {
+            return Err(OsError::InvalidArgument);
+        }
+        self.mem.lock().set_brk(brk)
+    }
+
+    /// Maps anonymous memory with `AddressSpace::mmap()`.
+    ///
+    /// Returns `InvalidArgument` for a kernel thread.
+    pub fn mmap(&mut self, len: usize, perm: PagePerm) -> OsResult<usize> {
+        if self.stack.is_some() {
+            return Err(OsError::InvalidArgument);
+        }
+        self.mem.lock().mmap(len, perm)
+    }
+
+    /// Unmaps memory with `AddressSpace::munmap()`.
+    ///
+    /// Returns `InvalidArgument` for a kernel thread.
+    pub fn munmap(&mut self, addr: usize, len: usize) -> OsResult<()> {
+        if self.stack.is_some() {
+            return Err(OsError::InvalidArgument);
+        }
+        self.mem.lock().munmap(addr, len)
+    }
+
+    /// Changes the permission of memory with `AddressSpace::mprotect()`.
+    ///
+    /// Returns `InvalidArgument` for a kernel thread.
+    pub fn mprotect(&mut self, addr: usize, len: usize, perm: PagePerm) -> OsResult<()> {
+        if self.stack.is_some() {
+            return Err(OsError::InvalidArgument);
+        }
+        self.mem.lock().mprotect(addr, len, perm)
+    }
+
     /// Returns the highest `VirtualAddr` that is supported by this system.
     pub fn get_max_va() -> VirtualAddr {
         VirtualAddr::from(USER_IMG_BASE +  USER_MAX_VM_SIZE -1)
@@ -128,15 +504,17 @@ impl Process {
     }
 
     /// Returns the `VirtualAddr` represents the base address of the user
-    /// process's stack.
+    /// process's stack: the lowest address it can grow to. The page below is
+    /// the guard page.
     pub fn get_stack_base() -> VirtualAddr {
-        unimplemented!();
+        VirtualAddr::from(USER_STACK_LIMIT)
     }
 
     /// Returns the `VirtualAddr` represents the top of the user process's
-    /// stack.
+    /// stack: the aligned address below the end of the address space that a
+    /// new process's stack pointer starts at.
     pub fn get_stack_top() -> VirtualAddr {
-        unimplemented!();
+        VirtualAddr::from(USER_STACK_BASE + PAGE_SIZE - PAGE_ALIGN)
     }
 
     /// Returns `true` if this process is ready to be scheduled.
@@ -152,21 +530,73 @@ impl Process {
     ///     occured. If it has, the state is switched to `Ready` and this
     ///     function returns `true`.
     ///
+    ///   * The state is `Waiting` and a signal that must be acted on is
+    ///     pending. The wait is abandoned; a blocking system call restarts
+    ///     after the signal is handled.
+    ///
     /// Returns `false` in all other cases.
     pub fn is_ready(&mut self) -> bool {
         let state = core::mem::replace(&mut self.state, State::Ready);
         match state {
             State::Waiting(mut event_poll_fn) =>
             {
-                if !event_poll_fn(self) {
+                if !self.signals.has_deliverable() && !event_poll_fn(self) {
                     self.state = State::Waiting(event_poll_fn);
                 }
             },
             _ =>  {core::mem::replace(&mut self.state, state);}
         };
         match self.state {
-            State::Ready => true,
+            State::Ready => {
+                // However the wait ended, the process no longer waits on a futex.
+                self.futex = None;
+                true
+            }
             _ => false,
         }
     }
 }
+
+/// Returns the page permission for an ELF segment with the given flags. Code
+/// is read-only unless the segment is also writable.
+fn segment_perm(flags: u32) -> PagePerm {
+    perm_of(flags & elf::PF_W != 0, flags & elf::PF_X != 0)
+}
+
+/// Returns the least permissive `PagePerm` that allows both `a` and `b`.
+fn merge_perm(a: PagePerm, b: PagePerm) -> PagePerm {
+    perm_of(
+        a.is_writable() || b.is_writable(),
+        a.is_executable() || b.is_executable(),
+    )
+}
+
+/// Returns the readable `PagePerm` that is writable and executable as given.
+fn perm_of(writable: bool, executable: bool) -> PagePerm {
+    match (writable, executable) {
+        (true, true) => PagePerm::RWX,
+        (true, false) => PagePerm::RW,
+        (false, true) => PagePerm::RX,
+        (false, false) => PagePerm::RO,
+    }
+}
+
+/// Landing address for a kernel thread whose entry function returns.
+extern "C" fn kernel_thread_exit() -> ! {
+    let _ = unsafe { kernel_api::raw::exit(0) };
+    loop {}
+}
+
+/// Puts the calling kernel thread to sleep for at least `ms` milliseconds,
+/// letting other processes run in the meantime.
+///
+/// Inside a thread created with `Process::kernel_thread()` this issues the
+/// `sleep` system call from EL1. Elsewhere (e.g. the debug shell running on the
+/// exception stack) there is no process to switch away from, so it spins.
+pub fn kernel_sleep(ms: u32) {
+    if aarch64::sp_sel() != 0 {
+        pi::timer::spin_sleep(core::time::Duration::from_millis(ms as u64));
+        return;
+    }
+    let _ = unsafe { kernel_api::raw::sleep(ms) };
+}
@@ -1,5 +1,6 @@
 use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use core::ffi::c_void;
@@ -15,10 +16,12 @@ use crate::mutex::Mutex;
 use crate::net::uspi::TKernelTimerHandle;
 use crate::param::*;
 use crate::percore::{get_preemptive_counter, is_mmu_ready, local_irq};
-use crate::process::{Id, Process, State};
+use crate::process::{Descriptor, Id, Process, State};
 use crate::traps::irq::IrqHandlerRegistry;
 use crate::traps::TrapFrame;
+use crate::vm::PagePerm;
 use crate::{ETHERNET, USB};
+use kernel_api::{OsError, OsResult, NSIG, SIGCHLD};
 
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
@@ -80,13 +83,55 @@ impl GlobalScheduler {
         }
     }
 
-    /// Kills currently running process and returns that process's ID.
-    /// For more details, see the documentation on `Scheduler::kill()`.
-    #[must_use]
-    pub fn kill(&self, tf: &mut TrapFrame) -> Option<Id> {
-        self.critical(|scheduler| scheduler.kill(tf))
+    /// Terminates the currently running process with `status` and switches
+    /// to the next process. For more details, see the documentation on
+    /// `Scheduler::exit()`.
+    pub fn exit(&self, status: i32, tf: &mut TrapFrame) -> Id {
+        self.critical(|scheduler| scheduler.exit(status, tf));
+        self.switch_to(tf)
+    }
+
+
+    /// Ends the calling thread with `status` and switches to the next process.
+    /// For more details, see the documentation on `Scheduler::thread_exit()`.
+    pub fn thread_exit(&self, status: i32, tf: &mut TrapFrame) -> Id {
+        self.critical(|scheduler| scheduler.thread_exit(status, tf));
+        self.switch_to(tf)
+    }
+
+    /// Sends `signal` to the process `pid`. For more details, see the
+    /// documentation on `Scheduler::signal()`.
+    pub fn signal(&self, pid: Id, signal: u32) -> OsResult<()> {
+        self.critical(|scheduler| scheduler.signal(pid, signal))
     }
 
+    /// Turns the logging of the system calls of process `pid` on or off. For
+    /// more details, see the documentation on `Scheduler::set_traced()`.
+    pub fn set_traced(&self, pid: Id, traced: bool) -> OsResult<()> {
+        self.critical(|scheduler| scheduler.set_traced(pid, traced))
+    }
+
+    /// Acts on the pending signals of the process about to return to EL0 with
+    /// `tf`: runs its handler, or terminates it with status `128 + signal`
+    /// and switches to the next process, whose signals are then handled the
+    /// same way. A thread whose process has exited ends here instead.
+    pub fn deliver_signals(&self, tf: &mut TrapFrame) {
+        loop {
+            let ending = self.critical(|scheduler| scheduler.find_process(tf).exit_pending.is_some());
+            if ending {
+                // `schedule_out()` turns the thread into a zombie.
+                self.switch(State::Ready, tf);
+                continue;
+            }
+            let terminate = self.critical(|scheduler| scheduler.find_process(tf).signals.deliver(tf));
+            match terminate {
+                Some(signal) => {
+                    self.exit(128 + signal as i32, tf);
+                }
+                None => return,
+            }
+        }
+    }
 
     /// Starts executing processes in user space using timer interrupt based
     /// preemptive scheduling. This method should not return under normal
@@ -126,6 +171,7 @@ impl GlobalScheduler {
         crate::GLOBAL_IRQ.register(Interrupt::Timer1, Box::new(timer1_handler));
         let mut controller = Controller::new();
         controller.enable(Interrupt::Timer1);*/
+        USB.start_kernel_timer(Duration::from_secs(1), Some(poll_ethernet));
     }
 
     /// Initializes the per-core local timer interrupt with `pi::local_interrupt`.
@@ -143,16 +189,8 @@ impl GlobalScheduler {
     pub unsafe fn initialize(&self) {
         use shim::path::Path;
         *self.0.lock() = Some(Scheduler::new());
-        let process1 = Process::load(Path::new("/fib")).unwrap();
-        self.add(process1);
-        let process2 = Process::load(Path::new("/fib")).unwrap();
-        self.add(process2);
-        let process3 = Process::load(Path::new("/fib")).unwrap();
-        self.add(process3);
-        let process4 = Process::load(Path::new("/fib")).unwrap();
-        self.add(process4);
-        let process5 = Process::load(Path::new("/fib")).unwrap();
-        self.add(process5);
+        let init = Process::load(Path::new("/init"), &["/init"], &USER_ENV).unwrap();
+        self.add(init);
     }
 
     // The following method may be useful for testing Lab 4 Phase 3:
@@ -160,9 +198,10 @@ impl GlobalScheduler {
     // * A method to load a extern function to the user process's page table.
     //
     pub fn test_phase_3(&self, proc: &mut Process){
-        use crate::vm::{VirtualAddr, PagePerm};
+        use crate::vm::VirtualAddr;
     
-        let mut page = proc.vmap.alloc(
+        let mut mem = proc.mem.lock();
+        let mut page = mem.vmap.alloc(
             VirtualAddr::from(USER_IMG_BASE as u64), PagePerm::RWX);
    
         let text = unsafe {
@@ -177,13 +216,19 @@ impl GlobalScheduler {
 /// `Usb::start_kernel_timer`.
 extern "C" fn poll_ethernet(_: TKernelTimerHandle, _: *mut c_void, _: *mut c_void) {
     // Lab 5 2.B
-    unimplemented!("poll_ethernet")
+    let now = Instant::from_millis(pi::timer::current_time().as_millis() as i64);
+    ETHERNET.poll(now);
+    let delay = ETHERNET.poll_delay(now);
+    USB.start_kernel_timer(delay, Some(poll_ethernet));
 }
 
 /// Internal scheduler struct which is not thread-safe.
 pub struct Scheduler {
     processes: VecDeque<Process>,
     last_id: Option<Id>,
+    /// The futex key and thread ID of each `futex_wait`, in arrival order.
+    /// Entries of threads that stopped waiting are dropped lazily.
+    futex_waiters: VecDeque<(usize, Id)>,
 }
 
 impl Scheduler {
@@ -192,6 +237,7 @@ impl Scheduler {
         Box::new(Scheduler {
             processes: VecDeque::new(),
             last_id : Some(0),
+            futex_waiters: VecDeque::new(),
         })
     }
 
@@ -203,6 +249,25 @@ impl Scheduler {
     /// It is the caller's responsibility to ensure that the first time `switch`
     /// is called, that process is executing on the CPU.
     fn add(&mut self, mut process: Process) -> Option<Id> {
+        let id = self.next_id()?;
+        process.pid = id;
+        process.context.tpidr_el0 = id;
+        self.processes.push_back(process);
+        Some(id)
+    }
+
+    /// Adds `thread`, a new thread of an existing process, to the queue and
+    /// returns its thread ID, which is allocated from the same IDs as process
+    /// IDs. If no further threads can be scheduled, returns `None`.
+    fn add_thread(&mut self, mut thread: Process) -> Option<Id> {
+        let id = self.next_id()?;
+        thread.context.tpidr_el0 = id;
+        self.processes.push_back(thread);
+        Some(id)
+    }
+
+    /// Allocates a process or thread ID.
+    fn next_id(&mut self) -> Option<Id> {
         match self.last_id {
             None => {
                 self.last_id = Some(1u64);
@@ -214,15 +279,15 @@ impl Scheduler {
                 }
             }
         }
-        process.context.tpidr_el0 = self.last_id.unwrap();
-        self.processes.push_back(process);
-        return self.last_id;
+        self.last_id
     }
 
     /// Finds the currently running process, sets the current process's state
     /// to `new_state`, prepares the context switch on `tf` by saving `tf`
     /// into the current process, and push the current process back to the
-    /// end of `processes` queue.
+    /// end of `processes` queue. A thread with `exit_pending` set becomes a
+    /// `Zombie` instead, and the parent of its process is woken since the
+    /// process may now be collected.
     ///
     /// If the `processes` queue is empty or there is no current process,
     /// returns `false`. Otherwise, returns `true`.
@@ -247,12 +312,49 @@ impl Scheduler {
             return false;
         }
         let mut current_process = self.processes.remove(idx).unwrap();
-        current_process.state = new_state;
+        let ended = current_process.exit_pending.take();
+        current_process.state = match ended {
+            Some(status) => State::Zombie(status),
+            None => new_state,
+        };
         *(current_process.context) = *tf;
+        let pid = current_process.pid;
         self.processes.push_back(current_process);
+        if ended.is_some() {
+            let parent = self
+                .processes
+                .iter()
+                .find(|process| process.pid == pid && process.is_main_thread())
+                .and_then(|process| process.parent);
+            for process in self.processes.iter_mut() {
+                if Some(process.pid) == parent {
+                    process.child_exited = true;
+                }
+            }
+        }
         return true;
     }
 
+    /// Returns `true` if a thread of process `pid` is running on a core.
+    fn is_running(&self, pid: Id) -> bool {
+        self.processes.iter().any(|process| {
+            process.pid == pid && match process.state {
+                State::Running => true,
+                _ => false,
+            }
+        })
+    }
+
+    /// Ends `thread`, another thread of a process that exits or calls `exec`,
+    /// with `status`. A thread running on another core cannot be stopped from
+    /// here; it is marked to end the next time it enters the kernel.
+    fn end_thread(thread: &mut Process, status: i32) {
+        match thread.state {
+            State::Running => thread.exit_pending = Some(status),
+            _ => thread.state = State::Zombie(status),
+        }
+    }
+
     /// Finds the next process to switch to, brings the next process to the
     /// front of the `processes` queue, changes the next process's state to
     /// `Running`, and performs context switch by restoring the next process`s
@@ -261,6 +363,7 @@ impl Scheduler {
     /// If there is no process to switch to, returns `None`. Otherwise, returns
     /// `Some` of the next process`s process ID.
     fn switch_to(&mut self, tf: &mut TrapFrame) -> Option<Id> {
+        self.reap_orphans();
         let mut idx = 0;
         for process in self.processes.iter_mut() {
             if process.is_ready() {
@@ -281,26 +384,317 @@ impl Scheduler {
         return Some(self.processes.front().unwrap().context.tpidr_el0);
     }
 
-    /// Kills currently running process by scheduling out the current process
-    /// as `Dead` state. Releases all process resources held by the process,
-    /// removes the dead process from the queue, drops the dead process's
-    /// instance, and returns the dead process's process ID.
-    fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        self.schedule_out(State::Dead, tf);
-        match self.processes.pop_back() {
-            Some(process) => {
-                let pid = process.context.tpidr_el0;
-                drop(process);
-                Some(pid)
-            },
-            None => None,
+    /// Terminates the currently running process with `status`. Releases its
+    /// descriptors and schedules it out as a `Zombie`, which keeps its memory
+    /// until it is reclaimed: the page table may still be live in `TTBR1` until
+    /// `tf` is switched to another process. The other threads of the process
+    /// end with `end_thread()`; the process is not reclaimed while one of them
+    /// still runs on another core. The parent is woken if it waits in
+    /// `waitpid`, and the process's children are orphaned.
+    ///
+    /// Returns the ID of the exited process.
+    fn exit(&mut self, status: i32, tf: &mut TrapFrame) -> Id {
+        let process = self.find_process(tf);
+        let (pid, parent) = (process.pid, process.parent);
+        self.release_process_resources(tf);
+        self.schedule_out(State::Zombie(status), tf);
+
+        for process in self.processes.iter_mut() {
+            if process.pid == pid {
+                Scheduler::end_thread(process, status);
+            }
+            if Some(process.pid) == parent {
+                process.child_exited = true;
+                if process.is_main_thread() {
+                    process.signals.raise(SIGCHLD);
+                }
+            }
+            if process.parent == Some(pid) {
+                process.parent = None;
+            }
+        }
+        pid
+    }
+
+    /// Ends the calling thread with `status`: its stack is unmapped and it is
+    /// scheduled out as a `Zombie` until another thread of the process
+    /// collects it with `join_thread()`. The first thread of a process cannot
+    /// end alone; it terminates the whole process as `exit()` does.
+    ///
+    /// Returns the ID of the exited thread.
+    fn thread_exit(&mut self, status: i32, tf: &mut TrapFrame) -> Id {
+        let process = self.find_process(tf);
+        if process.is_main_thread() {
+            return self.exit(status, tf);
+        }
+        let pid = process.pid;
+        if let Some(base) = process.thread_stack.take() {
+            // Nothing runs on the stack again before `tf` is replaced.
+            let _ = process.mem.lock().munmap(base, USER_THREAD_STACK_SIZE);
+        }
+        self.schedule_out(State::Zombie(status), tf);
+        for process in self.processes.iter_mut() {
+            if process.pid == pid {
+                process.thread_exited = true;
+            }
+        }
+        tf.tpidr_el0
+    }
+
+    /// Starts a thread of the current process, whose state is `tf`, at
+    /// `entry` with `arg` in its first argument register. The thread runs on
+    /// a new stack of `USER_THREAD_STACK_SIZE` bytes mapped in the process's
+    /// memory. Returns the thread's ID.
+    pub fn spawn_thread(&mut self, entry: u64, arg: u64, tf: &TrapFrame) -> OsResult<Id> {
+        let process = self.find_process(tf);
+        let base = process.mmap(USER_THREAD_STACK_SIZE, PagePerm::RW)?;
+        let thread = match process.thread(entry, arg, base, USER_THREAD_STACK_SIZE) {
+            Ok(thread) => thread,
+            Err(e) => {
+                let _ = process.munmap(base, USER_THREAD_STACK_SIZE);
+                return Err(e);
+            }
+        };
+        let mem = thread.mem.clone();
+        self.add_thread(thread).ok_or_else(|| {
+            let _ = mem.lock().munmap(base, USER_THREAD_STACK_SIZE);
+            OsError::NoMemory
+        })
+    }
+
+    /// Collects the thread `tid` of the current process, whose state is `tf`,
+    /// if it has exited. The thread is removed from the queue and its exit
+    /// status returned; `None` means it is still running.
+    ///
+    /// Returns `NoEntry` if `tid` is not another thread of the process, or is
+    /// its first thread.
+    pub fn join_thread(&mut self, tid: Id, tf: &TrapFrame) -> OsResult<Option<i32>> {
+        let pid = self.find_process(tf).pid;
+        let idx = self
+            .processes
+            .iter()
+            .position(|process| {
+                process.context.tpidr_el0 == tid
+                    && process.pid == pid
+                    && tid != tf.tpidr_el0
+                    && !process.is_main_thread()
+            })
+            .ok_or(OsError::NoEntry)?;
+        match self.processes[idx].state {
+            State::Zombie(status) => {
+                self.processes.remove(idx);
+                Ok(Some(status))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Ends the threads of the current process other than the calling one
+    /// with `end_thread()`, as `exec` does before replacing the process's
+    /// memory. They remain as zombies until the process is collected.
+    ///
+    /// Returns `InvalidArgument` if the caller is not the first thread of the
+    /// process.
+    pub fn end_other_threads(&mut self, tf: &TrapFrame) -> OsResult<()> {
+        let process = self.find_process(tf);
+        if !process.is_main_thread() {
+            return Err(OsError::InvalidArgument);
+        }
+        let pid = process.pid;
+        for process in self.processes.iter_mut() {
+            if process.pid == pid && process.context.tpidr_el0 != tf.tpidr_el0 {
+                Scheduler::end_thread(process, 0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects an exited child of the process `parent`: the child `pid`, or
+    /// any child if `pid` is `None`. The child and the remaining threads of it
+    /// are removed from the queue and dropped. Returns its ID and exit status,
+    /// or `None` if no matching child has exited yet or one of its threads
+    /// still runs on another core.
+    ///
+    /// Returns `NoEntry` if `parent` has no matching child.
+    pub fn reap(&mut self, parent: Id, pid: Option<Id>) -> OsResult<Option<(Id, i32)>> {
+        let is_match = |process: &Process| {
+            process.parent == Some(parent)
+                && process.is_main_thread()
+                && pid.map_or(true, |pid| process.pid == pid)
+        };
+        if !self.processes.iter().any(|process| is_match(process)) {
+            return Err(OsError::NoEntry);
+        }
+        let zombie = self.processes.iter().position(|process| {
+            is_match(process) && !self.is_running(process.pid) && match process.state {
+                State::Zombie(_) => true,
+                _ => false,
+            }
+        });
+        let child = match zombie.and_then(|idx| self.processes.remove(idx)) {
+            Some(child) => child,
+            None => return Ok(None),
+        };
+        self.processes.retain(|process| process.pid != child.pid);
+        let status = match child.state {
+            State::Zombie(status) => status,
+            _ => unreachable!(),
+        };
+        Ok(Some((child.pid, status)))
+    }
+
+    /// Marks `signal` pending for the process `pid`; it is acted on when the
+    /// process next returns to user space. Signal 0 only checks that the
+    /// process exists.
+    ///
+    /// # Errors
+    ///
+    /// - `OsError::InvalidArgument`: `signal` is not a valid signal number.
+    /// - `OsError::NoEntry`: There is no live process `pid`.
+    /// - `OsError::NoAccess`: `pid` is a kernel thread, which takes no signals.
+    pub fn signal(&mut self, pid: Id, signal: u32) -> OsResult<()> {
+        if signal as usize >= NSIG {
+            return Err(OsError::InvalidArgument);
+        }
+        let process = self
+            .processes
+            .iter_mut()
+            .find(|process| process.pid == pid && process.is_main_thread())
+            .ok_or(OsError::NoEntry)?;
+        if let State::Zombie(_) = process.state {
+            return Err(OsError::NoEntry);
+        }
+        if process.stack.is_some() {
+            return Err(OsError::NoAccess);
+        }
+        if signal != 0 {
+            process.signals.raise(signal);
+        }
+        Ok(())
+    }
+
+    /// Turns the logging of the system calls of every thread of process `pid`
+    /// on or off. Threads it starts later inherit the setting.
+    ///
+    /// # Errors
+    ///
+    /// - `OsError::NoEntry`: There is no live process `pid`.
+    pub fn set_traced(&mut self, pid: Id, traced: bool) -> OsResult<()> {
+        let mut found = false;
+        for process in self.processes.iter_mut().filter(|process| process.pid == pid) {
+            if let State::Zombie(_) = process.state {
+                continue;
+            }
+            process.traced = traced;
+            found = true;
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(OsError::NoEntry)
+        }
+    }
+
+    /// Drops the exited processes that have no parent to collect them, with
+    /// all their threads. A zombie that just exited may still be in a core's
+    /// `TTBR1`, but that core only runs kernel code until it restores the next
+    /// process's trap frame, which replaces `TTBR1` and flushes the TLB. A
+    /// process with a thread still running on another core is kept until
+    /// that thread ends.
+    fn reap_orphans(&mut self) {
+        let orphans: Vec<Id> = self
+            .processes
+            .iter()
+            .filter(|process| match process.state {
+                State::Zombie(_) => {
+                    process.is_main_thread()
+                        && process.parent.is_none()
+                        && !self.is_running(process.pid)
+                }
+                _ => false,
+            })
+            .map(|process| process.pid)
+            .collect();
+        if !orphans.is_empty() {
+            self.processes.retain(|process| !orphans.contains(&process.pid));
         }
     }
 
+    /// Returns `true` if thread `tid` still waits on the futex `key`.
+    fn waits_on_futex(&self, key: usize, tid: Id) -> bool {
+        self.processes
+            .iter()
+            .any(|process| process.context.tpidr_el0 == tid && process.futex == Some(key))
+    }
+
+    /// Queues thread `tid`, which has set its `futex` to `key`, to be woken by
+    /// `wake_futex()`. Entries of threads that no longer wait are dropped.
+    pub fn queue_futex_waiter(&mut self, key: usize, tid: Id) {
+        let mut waiters = mem::replace(&mut self.futex_waiters, VecDeque::new());
+        waiters.retain(|&(key, tid)| self.waits_on_futex(key, tid));
+        waiters.push_back((key, tid));
+        self.futex_waiters = waiters;
+    }
+
+    /// Wakes up to `n` threads waiting on the futex whose word is at the
+    /// physical address `key`, in the order they started waiting. Returns how
+    /// many were woken.
+    pub fn wake_futex(&mut self, key: usize, n: usize) -> usize {
+        let mut woken = 0;
+        let mut waiters = mem::replace(&mut self.futex_waiters, VecDeque::new());
+        let processes = &mut self.processes;
+        waiters.retain(|&(waiter_key, tid)| {
+            if waiter_key != key || woken == n {
+                return true;
+            }
+            let waiter = processes
+                .iter_mut()
+                .find(|process| process.context.tpidr_el0 == tid && process.futex == Some(key));
+            if let Some(waiter) = waiter {
+                waiter.futex = None;
+                // A waiter still running on its way to sleep sees `futex`
+                // cleared and does not sleep.
+                if let State::Waiting(_) = waiter.state {
+                    waiter.state = State::Ready;
+                }
+                woken += 1;
+            }
+            false
+        });
+        self.futex_waiters = waiters;
+        woken
+    }
+
+    /// Forks the current process, whose state is `tf`, and queues the child.
+    /// Returns the child's process ID.
+    pub fn fork(&mut self, tf: &TrapFrame) -> OsResult<Id> {
+        let child = self.find_process(tf).fork(tf)?;
+        self.add(child).ok_or(OsError::NoMemory)
+    }
+
+    /// Queues `child`, a program started by the current process, whose state
+    /// is `tf`. The child gets a copy of the current process's descriptors and
+    /// working directory. Returns the child's process ID.
+    pub fn spawn(&mut self, mut child: Process, tf: &TrapFrame) -> OsResult<Id> {
+        let parent = self.find_process(tf);
+        child.parent = Some(parent.pid);
+        child.fds = Arc::new(Mutex::new(parent.fds.lock().fork()));
+        child.cwd = parent.cwd.clone();
+        self.add(child).ok_or(OsError::NoMemory)
+    }
+
     /// Releases all process resources held by the current process such as sockets.
     fn release_process_resources(&mut self, tf: &mut TrapFrame) {
         // Lab 5 2.C
-        unimplemented!("release_process_resources")
+        let descriptors = self.find_process(tf).fds.lock().take_all();
+        ETHERNET.critical(|driver| {
+            for descriptor in descriptors {
+                if let Descriptor::Socket(socket) = descriptor {
+                    driver.close_user_socket(socket);
+                }
+            }
+            driver.prune();
+        });
     }
 
     /// Finds a process corresponding with tpidr saved in a trap frame.
@@ -0,0 +1,183 @@
+use alloc::boxed::Box;
+
+use kernel_api::{OsError, OsResult, NSIG, SIGCHLD, SIGKILL, SIG_DFL, SIG_IGN};
+
+use crate::traps::TrapFrame;
+
+/// What happens to a process when one of its signals is delivered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// The process is terminated.
+    Terminate,
+    /// The signal is discarded.
+    Ignore,
+    /// The user handler at this address is run.
+    Handle(u64),
+}
+
+/// The signal state of a process: which signals are pending, how each one is
+/// handled, and the trap frame a running handler returns to.
+#[derive(Debug)]
+pub struct Signals {
+    /// Bit `n` is set if signal `n` is pending.
+    pending: u32,
+    /// `SIG_DFL`, `SIG_IGN` or the address of a handler, indexed by signal.
+    handlers: [u64; NSIG],
+    /// Address handlers return to; it issues `sigreturn`.
+    restorer: u64,
+    /// The interrupted trap frame while a handler runs. Further caught
+    /// signals stay pending until the handler returns.
+    saved: Option<Box<TrapFrame>>,
+}
+
+/// The condition flags of `spsr`, the only bits a handler may change.
+const SPSR_NZCV: u64 = 0xf << 28;
+
+/// Returns the action taken for `signal` when its handler is `SIG_DFL`.
+fn default_action(signal: u32) -> Action {
+    match signal {
+        SIGCHLD => Action::Ignore,
+        _ => Action::Terminate,
+    }
+}
+
+impl Signals {
+    pub fn new() -> Signals {
+        Signals {
+            pending: 0,
+            handlers: [SIG_DFL; NSIG],
+            restorer: 0,
+            saved: None,
+        }
+    }
+
+    /// Returns the signal state of a child created by `fork`: the same
+    /// handlers, and no pending signals. The child does not inherit a running
+    /// handler, since the saved trap frame refers to the parent's page table.
+    pub fn fork(&self) -> Signals {
+        Signals {
+            pending: 0,
+            handlers: self.handlers,
+            restorer: self.restorer,
+            saved: None,
+        }
+    }
+
+    /// Resets caught signals to their default action when a new program is
+    /// executed, since the handlers no longer exist. Ignored signals stay
+    /// ignored.
+    pub fn exec(&mut self) {
+        for handler in self.handlers.iter_mut() {
+            if *handler != SIG_IGN {
+                *handler = SIG_DFL;
+            }
+        }
+        self.restorer = 0;
+        self.saved = None;
+    }
+
+    /// Returns the action for `signal`. `SIGKILL` cannot be caught or ignored.
+    fn action(&self, signal: u32) -> Action {
+        match (signal, self.handlers[signal as usize]) {
+            (SIGKILL, _) => Action::Terminate,
+            (_, SIG_DFL) => default_action(signal),
+            (_, SIG_IGN) => Action::Ignore,
+            (_, handler) => Action::Handle(handler),
+        }
+    }
+
+    /// Sets the handler of `signal` and the address handlers return to.
+    /// Returns the previous handler.
+    ///
+    /// Returns `InvalidArgument` for an unknown signal or `SIGKILL`.
+    pub fn set_handler(&mut self, signal: u32, handler: u64, restorer: u64) -> OsResult<u64> {
+        if signal == 0 || signal as usize >= NSIG || signal == SIGKILL {
+            return Err(OsError::InvalidArgument);
+        }
+        if handler != SIG_DFL && handler != SIG_IGN {
+            self.restorer = restorer;
+        }
+        Ok(core::mem::replace(&mut self.handlers[signal as usize], handler))
+    }
+
+    /// Marks `signal` pending.
+    pub fn raise(&mut self, signal: u32) {
+        self.pending |= 1 << signal;
+    }
+
+    /// Raises `signal` for a fault in user code. Returning to the faulting
+    /// instruction would fault again, so the signal terminates the process
+    /// if it is ignored or if a handler is already running.
+    pub fn raise_fault(&mut self, signal: u32) {
+        if self.saved.is_some() || self.action(signal) == Action::Ignore {
+            self.handlers[signal as usize] = SIG_DFL;
+        }
+        self.raise(signal);
+    }
+
+    /// Returns `true` if a pending signal would be acted on now. A process
+    /// waiting for an event is woken for such a signal.
+    pub fn has_deliverable(&self) -> bool {
+        (1..NSIG as u32).any(|signal| self.deliverable(signal))
+    }
+
+    fn deliverable(&self, signal: u32) -> bool {
+        self.pending & (1 << signal) != 0
+            && match self.action(signal) {
+                Action::Terminate => true,
+                Action::Ignore => false,
+                Action::Handle(_) => self.saved.is_none(),
+            }
+    }
+
+    /// Delivers the lowest pending signal that can be acted on to the process
+    /// about to return to EL0 with `tf`. Ignored signals are discarded. For a
+    /// caught signal, `tf` is saved and rewritten to run the handler with the
+    /// signal number in `x0` and the restorer in the link register.
+    ///
+    /// Returns the signal if it terminates the process.
+    pub fn deliver(&mut self, tf: &mut TrapFrame) -> Option<u32> {
+        for signal in 1..NSIG as u32 {
+            if self.pending & (1 << signal) == 0 {
+                continue;
+            }
+            match self.action(signal) {
+                Action::Ignore => self.pending &= !(1 << signal),
+                Action::Terminate => {
+                    self.pending &= !(1 << signal);
+                    return Some(signal);
+                }
+                Action::Handle(_) if self.saved.is_some() => {}
+                Action::Handle(handler) => {
+                    self.pending &= !(1 << signal);
+                    self.saved = Some(Box::new(*tf));
+                    tf.elr_el1 = handler;
+                    tf.x[0] = signal as u64;
+                    // The slot after `x29` holds the link register.
+                    tf.xzr = self.restorer;
+                    tf.sp_el0 &= !0xf;
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    /// Restores the trap frame interrupted by the running handler into `tf`.
+    /// The page table registers, the thread ID and all of `spsr` but the
+    /// condition flags are kept from `tf`, so the frame cannot move the
+    /// process to another address space or exception level.
+    ///
+    /// Returns `InvalidArgument` if no handler is running.
+    pub fn sigreturn(&mut self, tf: &mut TrapFrame) -> OsResult<()> {
+        let saved = self.saved.take().ok_or(OsError::InvalidArgument)?;
+        let current = *tf;
+        *tf = *saved;
+        tf.ttbr0_el1 = current.ttbr0_el1;
+        tf.ttbr1_el1 = current.ttbr1_el1;
+        tf.tpidr_el0 = current.tpidr_el0;
+        tf.spsr_el1 = (saved.spsr_el1 & SPSR_NZCV) | (current.spsr_el1 & !SPSR_NZCV);
+        Ok(())
+    }
+}
+
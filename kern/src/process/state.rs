@@ -19,8 +19,10 @@ pub enum State {
     Waiting(EventPollFn),
     /// The process is currently running.
     Running,
-    /// The process is currently dead (ready to be reclaimed).
-    Dead,
+    /// The process has exited with the given status and waits for its parent
+    /// to collect it with `waitpid`. A zombie without a parent is reclaimed by
+    /// the scheduler.
+    Zombie(i32),
 }
 
 impl fmt::Debug for State {
@@ -29,7 +31,7 @@ impl fmt::Debug for State {
             State::Ready => write!(f, "State::Ready"),
             State::Running => write!(f, "State::Running"),
             State::Waiting(_) => write!(f, "State::Waiting"),
-            State::Dead => write!(f, "State::Dead"),
+            State::Zombie(status) => write!(f, "State::Zombie({})", status),
         }
     }
 }
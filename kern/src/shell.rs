@@ -10,9 +10,16 @@ use pi::atags::Atags;
 use fat32::traits::FileSystem;
 use fat32::traits::{Dir, Entry};
 
-use crate::console::{kprint, kprintln, CONSOLE};
+use kernel_api::{OsResult, SIGHUP, SIGINT, SIGKILL, SIGTERM, SIGUSR1, SIGUSR2};
+
+use crate::console::ConsoleStream;
+use crate::net::{self, pcap, telnet, tftp};
+use crate::process::{Descriptor, Pipe, Process, STDIN, STDOUT};
+use crate::fs::merge_paths;
+use crate::param::USER_ENV;
 use crate::ALLOCATOR;
 use crate::FILESYSTEM;
+use crate::SCHEDULER;
 
 /// Error type for `Command` parse failures.
 #[derive(Debug)]
@@ -53,90 +60,143 @@ impl<'a> Command<'a> {
     }
 }
 
-/// Starts a shell using `prefix` as the prefix for each line.
+/// Starts a shell on the console using `prefix` as the prefix for each line.
 pub fn shell(prefix: &str)  {
+    shell_on(prefix, None, &mut ConsoleStream);
+}
+
+/// Reads a single byte from `stream`, blocking until one is available.
+/// Returns `None` once the stream has reached end-of-file or failed.
+fn read_byte<S: io::Read>(stream: &mut S) -> Option<u8> {
+    let mut byte = [0u8; 1];
+    loop {
+        match stream.read(&mut byte) {
+            Ok(0) => return None,
+            Ok(_) => return Some(byte[0]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Starts a shell on `stream` using `prefix` as the prefix for each line. If
+/// `commands` is given, only the commands it lists can be run.
+///
+/// Returns when the `exit` command is run, when `stream` reaches end-of-file,
+/// or when writing to `stream` fails.
+pub fn shell_on<S: io::Read + io::Write>(prefix: &str, commands: Option<&[&str]>, stream: &mut S) {
     let mut path = PathBuf::from("/");
     loop {
-        kprint!("\n\r");
-        kprint!("{}",prefix);
-        let mut buf = [0u8; 512];
-        let mut read_size = 0;
-        {
-            let mut console = CONSOLE.lock();
-            buf[read_size] = console.read_byte();
-        }
-        while read_size <511 && buf[read_size] != b'\n' && buf[read_size] != b'\r' {
-            if buf[read_size] >=32 && buf[read_size] <=126 {
-                let mut console = CONSOLE.lock();
-                console.write_byte(buf[read_size]);
-            } else if buf[read_size] == 8 {
-                if read_size > 0 {          // Not to backspace into prefix
-                    kprint!("\u{8} \u{8}");
-                }
-                read_size -= 1;
-            } else 
-            {
-                kprint!("\u{7}");
-                read_size -= 1;
-            }
+        match prompt(prefix, commands, &mut path, stream) {
+            Ok(true) => continue,
+            Ok(false) | Err(_) => return,
+        }
+    }
+}
+
+/// Reads and executes one command line. Returns `Ok(false)` if the shell
+/// should exit.
+fn prompt<S: io::Read + io::Write>(
+    prefix: &str,
+    commands: Option<&[&str]>,
+    path: &mut PathBuf,
+    stream: &mut S,
+) -> io::Result<bool> {
+    write!(stream, "\n\r")?;
+    write!(stream, "{}",prefix)?;
+    let mut buf = [0u8; 512];
+    let mut read_size = 0;
+    match read_byte(stream) {
+        Some(b) => buf[read_size] = b,
+        None => return Ok(false),
+    }
+    while read_size <511 && buf[read_size] != b'\n' && buf[read_size] != b'\r' {
+        if buf[read_size] >=32 && buf[read_size] <=126 {
+            stream.write_all(&buf[read_size..read_size + 1])?;
             read_size += 1;
-            {
-                let mut console = CONSOLE.lock();
-                buf[read_size] = console.read_byte();
+        } else if buf[read_size] == 8 {
+            // Erase the last character, but not into the prefix.
+            if read_size > 0 {
+                write!(stream, "\u{8} \u{8}")?;
+                read_size -= 1;
             }
+        } else 
+        {
+            write!(stream, "\u{7}")?;
         }
-        buf[read_size] = 0u8;
-        let cstr = core::str::from_utf8(&buf[0..read_size]).unwrap();
-        let mut bufstr = [""; 64]; 
-        match Command::parse(cstr, &mut bufstr){
-            Ok(cmd) => {
-                kprint!("\n\r");
-                if cmd.path()=="echo" {
-                    for i in 1..cmd.args.len() {
-                        kprint!("{} ",cmd.args[i]);
-                    }
-                } else if cmd.path()=="panic" {
-                    panic!();
-                } else if cmd.path()=="atag" {
-                    let mut atag = Atags::get(); 
-                    loop {
-                        match atag.next()  {
-                            Some(a) => kprintln!("{:#?}", a),
-                            None => break,
-                        }
+        match read_byte(stream) {
+            Some(b) => buf[read_size] = b,
+            None => return Ok(false),
+        }
+    }
+    buf[read_size] = 0u8;
+    let cstr = core::str::from_utf8(&buf[0..read_size]).unwrap();
+    let mut bufstr = [""; 64]; 
+    match Command::parse(cstr, &mut bufstr){
+        Ok(cmd) => {
+            write!(stream, "\n\r")?;
+            if commands.map_or(false, |commands| !commands.iter().any(|&command| command == cmd.path())) {
+                write!(stream, "command not allowed: {}", cmd.path())?;
+            } else if cmd.path()=="echo" {
+                for i in 1..cmd.args.len() {
+                    write!(stream, "{} ",cmd.args[i])?;
+                }
+            } else if cmd.path()=="panic" {
+                panic!();
+            } else if cmd.path()=="atag" {
+                let mut atag = Atags::get(); 
+                loop {
+                    match atag.next()  {
+                        Some(a) => writeln!(stream, "{:#?}", a)?,
+                        None => break,
                     }
-                } else if cmd.path()=="ls" {
-                    ls_function(&cmd, &path);
-                } else if cmd.path()=="pwd" {
-                    cwd_function(&path);
-                } else if cmd.path()=="cd" {
-                    cd_function(&cmd, &mut path);
-                } else if cmd.path()=="cat" {
-                    cat_function(&cmd, &path);
-                } else if cmd.path()=="sleep" {
-                    sleep_function(&cmd);
-                } else if cmd.path()=="exit" {
-                    return;
-                } else {
-                    kprint!("unknown command: {}", cmd.path());
                 }
-            },
-            Err(Error::Empty) => {
-                kprint!(" ")
-            }, 
-            Err(Error::TooManyArgs) => {
-                kprint!("\n\r");
-                kprint!("error: too many arguments");
+            } else if cmd.path()=="ls" {
+                ls_function(&cmd, path, stream)?;
+            } else if cmd.path()=="pwd" {
+                cwd_function(path, stream)?;
+            } else if cmd.path()=="cd" {
+                cd_function(&cmd, path, stream)?;
+            } else if cmd.path()=="cat" {
+                cat_function(&cmd, path, stream)?;
+            } else if cmd.path()=="sleep" {
+                sleep_function(&cmd, stream)?;
+            } else if cmd.path()=="tftp" {
+                tftp_function(&cmd, stream)?;
+            } else if cmd.path()=="dns" {
+                dns_function(&cmd, stream)?;
+            } else if cmd.path()=="pcap" {
+                pcap_function(&cmd, stream)?;
+            } else if cmd.path()=="telnet" {
+                telnet_function(&cmd, stream)?;
+            } else if cmd.path()=="run" {
+                run_function(&cmd, path, stream)?;
+            } else if cmd.path()=="kill" {
+                kill_function(&cmd, stream)?;
+            } else if cmd.path()=="strace" {
+                strace_function(&cmd, stream)?;
+            } else if cmd.path()=="exit" {
+                return Ok(false);
+            } else {
+                write!(stream, "unknown command: {}", cmd.path())?;
             }
+        },
+        Err(Error::Empty) => {
+            write!(stream, " ")?
+        }, 
+        Err(Error::TooManyArgs) => {
+            write!(stream, "\n\r")?;
+            write!(stream, "error: too many arguments")?;
         }
     }
+    Ok(true)
 }
 
-fn ls_function(cmd: &Command, cwd_path: &PathBuf) {
+fn ls_function<W: io::Write>(cmd: &Command, cwd_path: &PathBuf, out: &mut W) -> io::Result<()> {
     if cmd.args.len()==1 {
         let entries: Vec<_> = FILESYSTEM.open_dir(cwd_path).unwrap().entries().expect("entries interator").collect();
         for entry in entries.iter() {
-            kprintln!("{}", entry.name());
+            writeln!(out, "{}", entry.name())?;
         }
     } else if cmd.args.len()==2 {
         let mut cwd_path_clone = cwd_path.clone();
@@ -147,37 +207,39 @@ fn ls_function(cmd: &Command, cwd_path: &PathBuf) {
                 Ok(itr) => {
                     let entries : Vec<_> = itr.collect();
                     for entry in entries.iter() {
-                        kprintln!("{}", entry.name());
+                        writeln!(out, "{}", entry.name())?;
                     }
                 },
-                Err(_) => kprintln!("Error in getting the entries for the directory")
+                Err(_) => writeln!(out, "Error in getting the entries for the directory")?
             }
-            Err(_) => kprintln!("Invalid input")
+            Err(_) => writeln!(out, "Invalid input")?
         };
     } else {
-        kprintln!("Incorrect command\n ls [directory path]");
+        writeln!(out, "Incorrect command\n ls [directory path]")?;
     }
+    Ok(())
 }
 
-fn cwd_function(cwd_path: &PathBuf) {
-    kprintln!("{}", cwd_path.to_str().unwrap());
+fn cwd_function<W: io::Write>(cwd_path: &PathBuf, out: &mut W) -> io::Result<()> {
+    writeln!(out, "{}", cwd_path.to_str().unwrap())
 }
 
-fn cd_function(cmd: &Command, cwd_path: &mut PathBuf) {
+fn cd_function<W: io::Write>(cmd: &Command, cwd_path: &mut PathBuf, out: &mut W) -> io::Result<()> {
     if cmd.args.len()==2 {
         let mut cwd_path_clone = cwd_path.clone();
         let path = PathBuf::from(cmd.args[1]);
         merge_paths(&mut cwd_path_clone, &path);
         match FILESYSTEM.open_dir(cwd_path_clone) {
             Ok(_) => merge_paths(cwd_path, &path),
-            Err(_) => kprintln!("Directory does not exist"),
+            Err(_) => writeln!(out, "Directory does not exist")?,
         };
     } else {
-        kprintln!("Incorrect command\n cd <directory path>");
+        writeln!(out, "Incorrect command\n cd <directory path>")?;
     }
+    Ok(())
 }
 
-fn cat_function(cmd: &Command, cwd_path: &PathBuf) {
+fn cat_function<W: io::Write>(cmd: &Command, cwd_path: &PathBuf, out: &mut W) -> io::Result<()> {
     use io::Read;
     if cmd.args.len()>=2 {
         for i in 1..cmd.args.len() {
@@ -192,49 +254,259 @@ fn cat_function(cmd: &Command, cwd_path: &PathBuf) {
                         Ok(0) => break,
                         //Ok(read_size) => kprint!("{:?}", String::from_utf8(buffer[..read_size].to_vec()).unwrap()),
                         Ok(read_size) => match String::from_utf8(buffer[..read_size].to_vec()){
-                            Ok(s) => kprint!("{}", s),
+                            Ok(s) => write!(out, "{}", s)?,
                             Err(e) => {
-                                kprintln!("{:?}", e);
+                                writeln!(out, "{:?}", e)?;
                                 break;
                             },
                         },
-                        _ => kprintln!("\n error reading file"),
+                        _ => writeln!(out, "\n error reading file")?,
                     };
                 }   
-                Err(e) => kprintln!("Invalid input {:?}", e)
+                Err(e) => writeln!(out, "Invalid input {:?}", e)?
             };
         }
     } else {
-        kprintln!("Incorrect command\n cat <file path....>");
-    }
-}
-
-fn merge_paths(path: &mut PathBuf, rel_path: &PathBuf) {
-    let components: Vec<_> = rel_path.components().map(|comp| comp.as_os_str()).collect();
-    for component in components {
-        let chk1 = component.to_str().unwrap()=="..";
-        let chk2 = component.to_str().unwrap()==".";
-        let tpath = PathBuf::from(component.to_str().unwrap());
-        if chk1 {
-            path.pop();
-        } else if !chk2{
-            path.push(tpath);
-        }
+        writeln!(out, "Incorrect command\n cat <file path....>")?;
     }
+    Ok(())
 }
 
-fn sleep_function(cmd: &Command) {
+fn sleep_function<W: io::Write>(cmd: &Command, out: &mut W) -> io::Result<()> {
     if cmd.args.len()!= 2 {
-        kprintln!("Incorrect command\n sleep <duration in ms>");
+        writeln!(out, "Incorrect command\n sleep <duration in ms>")?;
+        return Ok(());
     }
     let delay ;
     match cmd.args[1].parse::<u32>() {
         Ok(d) => delay = core::time::Duration::from_millis(d as u64),
         _   => {
-            kprintln!("Incorrect command\n sleep <duration in ms>");
-            return;
+            writeln!(out, "Incorrect command\n sleep <duration in ms>")?;
+            return Ok(());
+        }
+    }
+    writeln!(out, "sleep {:?}", delay)
+}
+
+/// Sends a signal, `SIGTERM` unless one is given by number or name, to a
+/// process.
+fn kill_function<W: io::Write>(cmd: &Command, out: &mut W) -> io::Result<()> {
+    let usage = "Incorrect command\n kill [-<signal>] <pid>";
+    let (signal, pid) = match cmd.args.len() {
+        2 => (Some(SIGTERM), cmd.args[1]),
+        3 if cmd.args[1].starts_with('-') => (parse_signal(&cmd.args[1][1..]), cmd.args[2]),
+        _ => return writeln!(out, "{}", usage),
+    };
+    match (signal, pid.parse::<u64>()) {
+        (Some(signal), Ok(pid)) => match SCHEDULER.signal(pid, signal) {
+            Ok(()) => Ok(()),
+            Err(e) => writeln!(out, "kill: {:?}", e),
+        },
+        _ => writeln!(out, "{}", usage),
+    }
+}
+
+/// Turns the logging of a process's system calls on, or off with `-d`.
+fn strace_function<W: io::Write>(cmd: &Command, out: &mut W) -> io::Result<()> {
+    let usage = "Incorrect command\n strace [-d] <pid>";
+    let (traced, pid) = match cmd.args.len() {
+        2 => (true, cmd.args[1]),
+        3 if cmd.args[1] == "-d" => (false, cmd.args[2]),
+        _ => return writeln!(out, "{}", usage),
+    };
+    match pid.parse::<u64>() {
+        Ok(pid) => match SCHEDULER.set_traced(pid, traced) {
+            Ok(()) => Ok(()),
+            Err(e) => writeln!(out, "strace: {:?}", e),
+        },
+        _ => writeln!(out, "{}", usage),
+    }
+}
+
+/// Parses a signal number or one of the common signal names.
+fn parse_signal(s: &str) -> Option<u32> {
+    match s {
+        "HUP" => Some(SIGHUP),
+        "INT" => Some(SIGINT),
+        "KILL" => Some(SIGKILL),
+        "USR1" => Some(SIGUSR1),
+        "USR2" => Some(SIGUSR2),
+        "TERM" => Some(SIGTERM),
+        _ => s.parse().ok(),
+    }
+}
+
+fn tftp_function<W: io::Write>(cmd: &Command, out: &mut W) -> io::Result<()> {
+    use smoltcp::wire::{IpAddress, Ipv4Address};
+    if cmd.args.len() != 5 || cmd.args[1] != "get" {
+        return writeln!(out, "Incorrect command\n tftp get <server> <file> <dest>");
+    }
+    let server = match cmd.args[2].parse::<Ipv4Address>() {
+        Ok(addr) => IpAddress::Ipv4(addr),
+        Err(_) => return writeln!(out, "Invalid server address {}", cmd.args[2]),
+    };
+    match tftp::get(server, cmd.args[3]) {
+        Ok(image) => {
+            writeln!(out, "received {} bytes as {}", image.len(), cmd.args[4])?;
+            tftp::store(cmd.args[4], image);
+        }
+        Err(e) => writeln!(out, "tftp: {:?}", e)?,
+    }
+    Ok(())
+}
+
+/// Shows or sets the DNS server that user programs resolve names with.
+fn dns_function<W: io::Write>(cmd: &Command, out: &mut W) -> io::Result<()> {
+    use smoltcp::wire::Ipv4Address;
+    match cmd.args.len() {
+        1 => match net::dns_server() {
+            Some(server) => writeln!(out, "dns server {}", server),
+            None => writeln!(out, "no dns server"),
+        },
+        2 if cmd.args[1] == "none" => {
+            net::set_dns_server(None);
+            writeln!(out, "dns server cleared")
+        }
+        2 => match cmd.args[1].parse::<Ipv4Address>() {
+            Ok(server) => {
+                net::set_dns_server(Some(server));
+                writeln!(out, "dns server {}", server)
+            }
+            Err(_) => writeln!(out, "Invalid server address {}", cmd.args[1]),
+        },
+        _ => writeln!(out, "Incorrect command\n dns [<server> | none]"),
+    }
+}
+
+/// Starts the telnet listener, or reports whether it runs. Sessions are not
+/// authenticated, so it is off until started here.
+fn telnet_function<W: io::Write>(cmd: &Command, out: &mut W) -> io::Result<()> {
+    match (cmd.args.len(), cmd.args.get(1).copied()) {
+        (1, None) if telnet::is_started() => writeln!(out, "telnet listening on port {}", telnet::TELNET_PORT),
+        (1, None) => writeln!(out, "telnet off"),
+        (2, Some("start")) => match telnet::start() {
+            Ok(true) => writeln!(out, "telnet listening on port {}", telnet::TELNET_PORT),
+            Ok(false) => writeln!(out, "telnet already started"),
+            Err(e) => writeln!(out, "telnet: {:?}", e),
+        },
+        _ => writeln!(out, "Incorrect command\n telnet [start]"),
+    }
+}
+
+/// Controls packet capture. `dump` prints the ring as a pcap file in hex, which
+/// `xxd -r -p` turns back into a file for Wireshark; `save` uploads it to a TFTP
+/// server instead.
+fn pcap_function<W: io::Write>(cmd: &Command, out: &mut W) -> io::Result<()> {
+    use smoltcp::wire::{IpAddress, Ipv4Address};
+    let usage = "Incorrect command\n pcap start [frames] | stop | status | clear | dump | save <server> <file>";
+    match (cmd.args.len(), cmd.args.get(1).copied()) {
+        (2, Some("start")) | (3, Some("start")) => {
+            let capacity = match cmd.args.get(2) {
+                Some(frames) => match frames.parse::<usize>() {
+                    Ok(frames) if frames > 0 && frames <= pcap::MAX_CAPACITY => frames,
+                    _ => return writeln!(out, "{}", usage),
+                },
+                None => pcap::DEFAULT_CAPACITY,
+            };
+            pcap::start(capacity);
+            writeln!(out, "capturing up to {} frames", capacity)
+        }
+        (2, Some("stop")) => {
+            pcap::stop();
+            writeln!(out, "capture stopped")
+        }
+        (2, Some("status")) => {
+            let status = pcap::status();
+            writeln!(
+                out,
+                "{}, {}/{} frames, {} overwritten",
+                if status.enabled { "capturing" } else { "stopped" },
+                status.frames,
+                status.capacity,
+                status.dropped
+            )
+        }
+        (2, Some("clear")) => {
+            pcap::clear();
+            writeln!(out, "capture cleared")
+        }
+        (2, Some("dump")) => {
+            let file = pcap::to_pcap(&pcap::snapshot());
+            for line in file.chunks(32) {
+                for byte in line {
+                    write!(out, "{:02x}", byte)?;
+                }
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+        (4, Some("save")) => {
+            let server = match cmd.args[2].parse::<Ipv4Address>() {
+                Ok(addr) => IpAddress::Ipv4(addr),
+                Err(_) => return writeln!(out, "Invalid server address {}", cmd.args[2]),
+            };
+            let file = pcap::to_pcap(&pcap::snapshot());
+            match tftp::put(server, cmd.args[3], &file) {
+                Ok(()) => writeln!(out, "sent {} bytes as {}", file.len(), cmd.args[3]),
+                Err(e) => writeln!(out, "pcap: {:?}", e),
+            }
+        }
+        _ => writeln!(out, "{}", usage),
+    }
+}
+
+/// Loads the program named by `argv[0]`, an image downloaded with `tftp get`
+/// or a file relative to `cwd_path`, with `argv` as its arguments.
+fn load_program(argv: &[&str], cwd_path: &PathBuf) -> OsResult<Process> {
+    let mut process = match tftp::with_image(argv[0], |image| Process::load_image(image, argv, &USER_ENV)) {
+        Some(process) => process,
+        None => {
+            let mut cwd_path_clone = cwd_path.clone();
+            merge_paths(&mut cwd_path_clone, &PathBuf::from(argv[0]));
+            Process::load(cwd_path_clone, argv, &USER_ENV)
+        }
+    }?;
+    process.cwd = cwd_path.clone();
+    Ok(process)
+}
+
+/// Starts a user program with the rest of the command line as its arguments;
+/// the program name is `argv[0]`. Programs separated by `|` are connected
+/// with pipes, the standard output of each feeding the standard input of the
+/// next. With `-t`, the system calls of the programs are logged.
+fn run_function<W: io::Write>(cmd: &Command, cwd_path: &PathBuf, out: &mut W) -> io::Result<()> {
+    let usage = "Incorrect command\n run [-t] <program> [args...] [| <program> [args...]]...";
+    let traced = cmd.args.len() > 1 && cmd.args[1] == "-t";
+    let args = if traced { &cmd.args[2..] } else { &cmd.args[1..] };
+    if args.is_empty() {
+        return writeln!(out, "{}", usage);
+    }
+    let stages: Vec<&[&str]> = args.split(|arg| *arg == "|").collect();
+    if stages.iter().any(|argv| argv.is_empty()) {
+        return writeln!(out, "{}", usage);
+    }
+
+    let mut processes = Vec::with_capacity(stages.len());
+    for argv in stages.iter() {
+        match load_program(argv, cwd_path) {
+            Ok(mut process) => {
+                process.traced = traced;
+                processes.push(process)
+            }
+            Err(e) => return writeln!(out, "run: {}: {:?}", argv[0], e),
+        }
+    }
+    for i in 1..processes.len() {
+        let (reader, writer) = Pipe::new();
+        let _ = processes[i - 1].fds.lock().set(STDOUT, Descriptor::PipeWrite(writer));
+        let _ = processes[i].fds.lock().set(STDIN, Descriptor::PipeRead(reader));
+    }
+    for process in processes {
+        match SCHEDULER.add(process) {
+            Some(pid) => writeln!(out, "started process {}", pid)?,
+            None => writeln!(out, "run: no process id available")?,
         }
     }
-    kprintln!("sleep {:?}", delay);
+    Ok(())
 }
 
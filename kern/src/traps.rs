@@ -9,7 +9,7 @@ use pi::interrupt::{Controller, Interrupt};
 use crate::shell;
 use pi::local_interrupt::{LocalController, LocalInterrupt};
 
-use self::syndrome::Syndrome;
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
 use crate::percore;
 use crate::traps::irq::IrqHandlerRegistry;
@@ -39,22 +39,83 @@ pub struct Info {
     kind: Kind,
 }
 
+/// Gives the current process a private copy of the copy-on-write page written
+/// at `FAR_EL1`. Returns `false` if the fault was not caused by copy-on-write.
+///
+/// The write may come from a system call copying into user memory while it
+/// holds the scheduler lock, so the page table is reached through the
+/// trapframe's `ttbr1` instead of through the scheduler.
+fn handle_cow_fault(tf: &TrapFrame) -> bool {
+    use crate::param::{PAGE_MASK, USER_IMG_BASE};
+    use crate::vm::{PageTable, VirtualAddr};
+
+    let far = unsafe { aarch64::FAR_EL1.get() } as usize;
+    if far < USER_IMG_BASE || tf.ttbr1_el1 == 0 {
+        return false;
+    }
+    let page_table = unsafe { &mut *(tf.ttbr1_el1 as *mut PageTable) };
+    page_table.handle_cow_fault(VirtualAddr::from((far & PAGE_MASK) - USER_IMG_BASE))
+}
+
+/// Maps the page of the current process's stack that user code touched at
+/// `FAR_EL1`, growing the stack. Returns `false` if the address is not in the
+/// stack, including its guard page, or no page could be allocated.
+fn handle_stack_fault(tf: &TrapFrame) -> bool {
+    use crate::param::USER_STACK_LIMIT;
+
+    let far = unsafe { aarch64::FAR_EL1.get() } as usize;
+    far >= USER_STACK_LIMIT
+        && crate::SCHEDULER.critical(|scheduler| {
+            scheduler.find_process(tf).mem.lock().grow_stack(far, 1).is_ok()
+        })
+}
+
+/// Returns `true` if `tf` returns to user space (EL0t).
+fn returns_to_user(tf: &TrapFrame) -> bool {
+    tf.spsr_el1 & 0b1111 == 0
+}
+
 /// This function is called when an exception occurs. The `info` parameter
 /// specifies the source and kind of exception that has occurred. The `esr` is
 /// the value of the exception syndrome register. Finally, `tf` is a pointer to
 /// the trap frame for the exception.
+///
+/// Before returning to a user process, its pending signals are delivered.
 #[no_mangle]
 pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
+    dispatch_exception(info, esr, tf);
+    if returns_to_user(tf) {
+        crate::SCHEDULER.deliver_signals(tf);
+    }
+}
+
+fn dispatch_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
     use crate::console::kprintln;
+    use kernel_api::{SIGILL, SIGSEGV};
 
     if info.kind == Kind::Irq {
         /*if aarch64::affinity()==0 {
             crate::GLOBAL_IRQ.invoke(Interrupt::Timer1, tf);
         }*/
+        // Global interrupts (e.g. the USPi kernel timer) are routed to core 0.
+        if aarch64::affinity() == 0 {
+            let controller = Controller::new();
+            for int in Interrupt::iter() {
+                if controller.is_pending(int) {
+                    crate::GLOBAL_IRQ.invoke(int, tf);
+                }
+            }
+        }
         percore::local_irq().invoke(LocalInterrupt::CNTPNSIRQ, tf);
         return;
     }
-        
+
+    if info.kind == Kind::Fiq {
+        crate::FIQ.invoke((), tf);
+        return;
+    }
+
+    let from_user = info.source == Source::LowerAArch64;
     match Syndrome::from(esr) {
         Syndrome::Brk(x) => {
             kprintln!("Brk{:?} encountered", x);
@@ -66,6 +127,18 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
             //trace!("Svc{:?} encountered", y);
             handle_syscall(y, tf);
         },
+        Syndrome::DataAbort { kind: Fault::Permission, .. } if handle_cow_fault(tf) => {},
+        Syndrome::DataAbort { kind: Fault::Translation, .. }
+            if from_user && handle_stack_fault(tf) => {},
+        // Faults in user code, including accesses its page permissions do not
+        // allow, are reported to the process as signals.
+        Syndrome::DataAbort { .. }
+        | Syndrome::InstructionAbort { .. }
+        | Syndrome::PCAlignmentFault
+        | Syndrome::SpAlignmentFault if from_user => raise_fault(SIGSEGV, tf),
+        Syndrome::Unknown
+        | Syndrome::IllegalExecutionState
+        | Syndrome::MsrMrsSystem if from_user => raise_fault(SIGILL, tf),
         Syndrome::DataAbort {
             kind:x, level: y
             }=> {
@@ -78,3 +151,7 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
     }
 }
 
+/// Raises `signal` on the current process for a fault in its code.
+fn raise_fault(signal: u32, tf: &TrapFrame) {
+    crate::SCHEDULER.critical(|scheduler| scheduler.find_process(tf).signals.raise_fault(signal));
+}
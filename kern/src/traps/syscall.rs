@@ -1,12 +1,24 @@
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
 use core::time::Duration;
 
+use shim::path::PathBuf;
+use smoltcp::socket::SocketHandle;
 use smoltcp::wire::{IpAddress, IpEndpoint};
 
-use crate::console::{kprint, CONSOLE};
-use crate::param::USER_IMG_BASE;
-use crate::process::{State, Process};
+use crate::console::{kprint, kprintln, CONSOLE};
+use crate::fs::{merge_paths, PiVFatHandle};
+use crate::mutex::Mutex;
+use crate::net::{UserSocket, KERNEL_PORTS};
+use crate::param::USER_IO_MAX;
+use crate::process::{Channel, Descriptor, DescriptorTable, Message, Pipe, State, Process};
 use crate::traps::TrapFrame;
+use crate::vm::{PagePerm, SharedMemory, VirtualAddr};
 use crate::{ETHERNET, SCHEDULER};
 
 use kernel_api::*;
@@ -53,13 +65,46 @@ pub fn sys_time(tf: &mut TrapFrame) {
     tf.x[1] = curr_time.subsec_nanos().into();
 }
 
-/// Kills the current process.
+/// Terminates the current process.
 ///
-/// This system call does not take paramer and does not return any value.
-pub fn sys_exit(tf: &mut TrapFrame) {
-    //Can't use SCHEDULER.kill(tf) as the user programs effectively have a empty loop after NR_EXIT
-    //call. Kill() drops the process pagtables which causes Instruction Abort for loop statement
-    SCHEDULER.switch(State::Dead, tf);
+/// This system call takes one parameter: the exit status, which the parent
+/// collects with `waitpid`. It does not return.
+pub fn sys_exit(status: i32, tf: &mut TrapFrame) {
+    SCHEDULER.exit(status, tf);
+}
+
+/// Waits for a child process to exit and collects its exit status.
+///
+/// This system call takes two parameters: the ID of the child to wait for, or
+/// 0 for any child, and flags. With `WNOHANG` it returns immediately if no
+/// matching child has exited; otherwise it blocks until one does.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the ID of the collected child, or 0 if there was none under
+/// `WNOHANG`, and its exit status.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: The process has no matching child.
+pub fn sys_waitpid(pid: u64, flags: u64, tf: &mut TrapFrame) {
+    let pid = if pid == 0 { None } else { Some(pid) };
+    let result = SCHEDULER.critical(|scheduler| {
+        let parent = scheduler.find_process(tf).pid;
+        scheduler.reap(parent, pid)
+    });
+    match result {
+        Ok(Some((child, status))) => {
+            tf.x[1] = status as u64;
+            set_result(Ok(child), tf);
+        }
+        Ok(None) if flags & WNOHANG != 0 => {
+            tf.x[1] = 0;
+            set_result(Ok(0), tf);
+        }
+        Ok(None) => block_on(WaitFor::ChildExit, tf),
+        Err(e) => set_result(Err(e), tf),
+    }
 }
 
 /// Writes to console.
@@ -79,189 +124,648 @@ pub fn sys_write(b: u8, tf: &mut TrapFrame) {
 /// In addition to the usual status value, this system call returns a
 /// parameter: the current process's ID.
 pub fn sys_getpid(tf: &mut TrapFrame) {
+    tf.x[7] = 1;
+    tf.x[0] = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).pid);
+}
+
+/// Returns the current thread's ID.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the current thread's ID, which is the process ID in the first
+/// thread of a process.
+pub fn sys_gettid(tf: &mut TrapFrame) {
     tf.x[7] = 1;
     tf.x[0] = tf.tpidr_el0;
 }
 
-pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
-    match num as usize{
-        NR_SLEEP => sys_sleep(tf.x[0] as u32, tf),
-        NR_TIME => sys_time(tf),
-        NR_EXIT => sys_exit(tf),
-        NR_WRITE => sys_write(tf.x[0] as u8, tf),
-        NR_GETPID => sys_getpid(tf),
-        NR_WRITE_STR => sys_write_str(tf.x[0] as usize, tf.x[1] as usize, tf),
-        _ => {}
+/// Starts a thread in the current process.
+///
+/// This system call takes two parameters: the address the thread starts
+/// executing at, and a value passed to it in its first argument register. The
+/// thread runs on a stack of its own, shares the process's memory and
+/// descriptors, and starts with the caller's working directory and signal
+/// handlers. It must end with `thread_exit`; returning from the entry point
+/// is not possible.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new thread's ID.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The caller is a kernel thread.
+/// - `OsError::NoVmSpace`, `OsError::NoMemory`: The stack could not be mapped,
+///   or no thread ID is available.
+pub fn sys_thread_create(entry: u64, arg: u64, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| scheduler.spawn_thread(entry, arg, tf));
+    set_result(result, tf);
+}
+
+/// Ends the current thread.
+///
+/// This system call takes one parameter: the exit status, which another
+/// thread collects with `thread_join`. It does not return. In the first thread
+/// of a process it ends the whole process, like `exit`.
+pub fn sys_thread_exit(status: i32, tf: &mut TrapFrame) {
+    SCHEDULER.thread_exit(status, tf);
+}
+
+/// Waits for a thread of the current process to end and collects its exit
+/// status.
+///
+/// This system call takes one parameter: the ID of the thread to wait for.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the thread's exit status.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: The ID is not another thread of the process, is its
+///   first thread, or the thread was already joined.
+pub fn sys_thread_join(tid: u64, tf: &mut TrapFrame) {
+    match SCHEDULER.critical(|scheduler| scheduler.join_thread(tid, tf)) {
+        Ok(Some(status)) => set_result(Ok(status as u64), tf),
+        Ok(None) => block_on(WaitFor::ThreadExit, tf),
+        Err(e) => set_result(Err(e), tf),
     }
 }
 
-/// Creates a socket and saves the socket handle in the current process's
-/// socket list.
+/// Creates a copy of the current process.
 ///
-/// This function does neither take any parameter nor return anything,
-/// except the usual return code that indicates successful syscall execution.
-pub fn sys_sock_create(tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_create")
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the child's process ID in the parent, and 0 in the child. The
+/// child shares the parent's memory copy-on-write.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The caller is a kernel thread.
+/// - `OsError::NoMemory`: No process ID is available for the child.
+pub fn sys_fork(tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| scheduler.fork(tf));
+    set_result(result, tf);
 }
 
-/// Returns the status of a socket.
+/// Sends a signal to a process.
 ///
-/// This system call takes a socket descriptor as the first parameter.
+/// This system call takes two parameters: the ID of the process and the
+/// signal number. Signal 0 only checks that the process exists. The signal is
+/// acted on when the process next returns to user space.
 ///
-/// In addition to the usual status value, this system call returns four boolean
-/// values that describes the status of the queried socket.
+/// It only returns the usual status value.
 ///
-/// - x0: is_active
-/// - x1: is_listening
-/// - x2: can_send
-/// - x3: can_recv
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The signal number is not valid.
+/// - `OsError::NoEntry`: There is no live process with the ID.
+/// - `OsError::NoAccess`: The process is a kernel thread.
+pub fn sys_kill(pid: u64, signal: u64, tf: &mut TrapFrame) {
+    let result = u32::try_from(signal)
+        .map_err(|_| OsError::InvalidArgument)
+        .and_then(|signal| SCHEDULER.signal(pid, signal))
+        .map(|_| 0);
+    set_result(result, tf);
+}
+
+/// Sets how the current process handles a signal.
+///
+/// This system call takes three parameters: the signal number, the handler
+/// (`SIG_DFL`, `SIG_IGN` or the address of a function taking the signal
+/// number), and the address the handler returns to, which must issue
+/// `sigreturn`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the previous handler.
 ///
 /// # Errors
-/// This function returns `OsError::InvalidSocket` if a socket that corresponds
-/// to the provided descriptor is not found.
-pub fn sys_sock_status(sock_idx: usize, tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_status")
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The signal number is not valid or is `SIGKILL`.
+pub fn sys_signal(signal: u64, handler: u64, restorer: u64, tf: &mut TrapFrame) {
+    let result = u32::try_from(signal)
+        .map_err(|_| OsError::InvalidArgument)
+        .and_then(|signal| {
+            SCHEDULER.critical(|scheduler| {
+                scheduler.find_process(tf).signals.set_handler(signal, handler, restorer)
+            })
+        });
+    set_result(result, tf);
 }
 
-/// Connects a local ephemeral port to a remote IP endpoint with a socket.
+/// Returns from a signal handler to the code it interrupted.
 ///
-/// This system call takes a socket descriptor as the first parameter, the IP
-/// of the remote endpoint as the second paramter in big endian, and the port
-/// number of the remote endpoint as the third parameter.
+/// This system call does not take parameter. On success it does not return to
+/// the caller; the interrupted registers are restored.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: No signal handler is running.
+pub fn sys_sigreturn(tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        process.signals.sigreturn(tf)
+    });
+    if let Err(e) = result {
+        set_result(Err(e), tf);
+    }
+}
+
+/// Sets the end of the current process's heap.
+///
+/// This system call takes one parameter: the new end of the heap, or 0 to
+/// leave it unchanged. Pages up to the end are mapped read/write and zeroed
+/// when the heap grows, and unmapped when it shrinks.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new end of the heap.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoMemory`: The heap cannot end at the given address.
+pub fn sys_brk(brk: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).set_brk(brk));
+    set_result(result.map(|brk| brk as u64), tf);
+}
+
+/// Returns the `PagePerm` for a `PERM_*` value of `NR_MMAP`.
+fn page_perm(perm: u64) -> OsResult<PagePerm> {
+    match perm {
+        PERM_RO => Ok(PagePerm::RO),
+        PERM_RW => Ok(PagePerm::RW),
+        PERM_RWX => Ok(PagePerm::RWX),
+        PERM_RX => Ok(PagePerm::RX),
+        _ => Err(OsError::InvalidArgument),
+    }
+}
+
+/// Maps anonymous memory into the current process.
+///
+/// This system call takes two parameters: the length in bytes, rounded up to
+/// whole pages, and the permission (`PERM_RO`, `PERM_RW`, `PERM_RWX` or
+/// `PERM_RX`). The pages are zeroed.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the page-aligned address of the mapping.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The length is 0 or the permission is unknown.
+/// - `OsError::NoVmSpace`: No free range of addresses is large enough.
+/// - `OsError::NoMemory`: The pages could not be allocated.
+pub fn sys_mmap(len: usize, perm: u64, tf: &mut TrapFrame) {
+    let result = page_perm(perm).and_then(|perm| {
+        SCHEDULER.critical(|scheduler| scheduler.find_process(tf).mmap(len, perm))
+    });
+    set_result(result.map(|addr| addr as u64), tf);
+}
+
+/// Unmaps memory of the current process.
 ///
-/// `handle_syscall` should read the value of registers and create a struct that
-/// implements `Into<IpEndpoint>` when calling this function.
+/// This system call takes two parameters: the page-aligned address and the
+/// length in bytes. Every mapped page overlapping the range is unmapped.
 ///
 /// It only returns the usual status value.
 ///
 /// # Errors
 /// This function can return following errors:
 ///
-/// - `OsError::NoEntry`: Fails to allocate an ephemeral port
-/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
-/// - `OsError::IllegalSocketOperation`: `connect()` returned `smoltcp::Error::Illegal`.
-/// - `OsError::BadAddress`: `connect()` returned `smoltcp::Error::Unaddressable`.
-/// - `OsError::Unknown`: All the other errors from calling `connect()`.
-pub fn sys_sock_connect(
-    sock_idx: usize,
-    remote_endpoint: impl Into<IpEndpoint>,
-    tf: &mut TrapFrame,
-) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_connect")
+/// - `OsError::InvalidArgument`: The address is not page-aligned, or the range
+///   is empty or outside the user space below the stack.
+pub fn sys_munmap(addr: usize, len: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).munmap(addr, len));
+    set_result(result.map(|_| 0), tf);
 }
 
-/// Listens on a local port for an inbound connection.
+/// Changes the permission of memory of the current process.
 ///
-/// This system call takes a socket descriptor as the first parameter and the
-/// local ports to listen on as the second parameter.
+/// This system call takes three parameters: the page-aligned address, the
+/// length in bytes, and the permission (`PERM_RO`, `PERM_RW`, `PERM_RWX` or
+/// `PERM_RX`). Every page overlapping the range gets the permission; a write
+/// to a page still shared copy-on-write after `fork` copies it first. Accesses
+/// the permission does not allow raise `SIGSEGV`.
 ///
 /// It only returns the usual status value.
 ///
 /// # Errors
 /// This function can return following errors:
 ///
-/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
-/// - `OsError::IllegalSocketOperation`: `listen()` returned `smoltcp::Error::Illegal`.
-/// - `OsError::BadAddress`: `listen()` returned `smoltcp::Error::Unaddressable`.
-/// - `OsError::Unknown`: All the other errors from calling `listen()`.
-pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_listen")
+/// - `OsError::InvalidArgument`: The address is not page-aligned, the range
+///   is empty or outside the user space below the stack, the permission is
+///   unknown, or the caller is a kernel thread.
+/// - `OsError::BadAddress`: A page in the range is not mapped. No permission
+///   is changed.
+pub fn sys_mprotect(va: usize, len: usize, perm: u64, tf: &mut TrapFrame) {
+    let result = page_perm(perm).and_then(|perm| {
+        SCHEDULER.critical(|scheduler| scheduler.find_process(tf).mprotect(va, len, perm))
+    });
+    set_result(result.map(|_| 0), tf);
 }
 
-/// Returns a slice from a virtual address and a legnth.
+/// Creates a shared memory region.
+///
+/// This system call takes one parameter: the size of the region in bytes,
+/// which is rounded up to whole pages. The region is zeroed. Processes map it
+/// with `shm_map`; forked and spawned children inherit the descriptor, and
+/// their mappings of the region stay shared rather than copy-on-write. The
+/// memory is freed once the descriptor is closed everywhere and every mapping
+/// is unmapped.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the descriptor of the region.
 ///
 /// # Errors
-/// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
-unsafe fn to_user_slice<'a>(va: usize, len: usize) -> OsResult<&'a [u8]> {
-    let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
-        Ok(core::slice::from_raw_parts(va as *const u8, len))
-    } else {
-        Err(OsError::BadAddress)
-    }
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The size is 0 or larger than the user address space.
+/// - `OsError::NoMemory`: The pages could not be allocated.
+pub fn sys_shm_create(size: usize, tf: &mut TrapFrame) {
+    let result = SharedMemory::new(size).map(|shm| {
+        SCHEDULER.critical(|scheduler| {
+            scheduler.find_process(tf).fds.lock().insert(Descriptor::Shm(Arc::new(shm))) as u64
+        })
+    });
+    set_result(result, tf);
 }
-/// Returns a mutable slice from a virtual address and a legnth.
+
+/// Maps a shared memory region into the current process.
+///
+/// This system call takes three parameters: the descriptor of the region, the
+/// page-aligned address to map it at, or 0 to let the kernel choose one between
+/// the heap and the stack, and the permission (`PERM_RO`, `PERM_RW`,
+/// `PERM_RWX` or `PERM_RX`). `munmap` removes the mapping.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the address of the mapping.
 ///
 /// # Errors
-/// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
-unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]> {
-    let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
-        Ok(core::slice::from_raw_parts_mut(va as *mut u8, len))
-    } else {
-        Err(OsError::BadAddress)
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The descriptor does not refer to a shared
+///   memory region, the address is not aligned, the permission is unknown, or
+///   the caller is a kernel thread.
+/// - `OsError::BadAddress`: The mapping would reach outside user space.
+/// - `OsError::NoVmSpace`: The mapping would overlap another one, or no range is free.
+pub fn sys_shm_map(fd: usize, va: usize, perm: u64, tf: &mut TrapFrame) {
+    let result = page_perm(perm).and_then(|perm| {
+        SCHEDULER.critical(|scheduler| {
+            let process = scheduler.find_process(tf);
+            if process.stack.is_some() {
+                return Err(OsError::InvalidArgument);
+            }
+            let shm = match process.fds.lock().get(fd) {
+                Some(Descriptor::Shm(shm)) => shm.clone(),
+                _ => return Err(OsError::InvalidArgument),
+            };
+            process.mem.lock().map_shared(&shm, va, perm)
+        })
+    });
+    set_result(result.map(|va| va as u64), tf);
+}
+
+/// Returns the physical address of the user word at `va`, which identifies a
+/// futex even when its page is mapped by several processes. A copy-on-write
+/// page gets its private copy first, so the key does not change when the
+/// page is next written.
+fn futex_key(process: &mut Process, va: usize) -> OsResult<usize> {
+    use crate::param::{PAGE_MASK, USER_IMG_BASE};
+
+    if va % core::mem::size_of::<u32>() != 0 {
+        return Err(OsError::InvalidArgument);
+    }
+    let mut mem = process.mem.lock();
+    let (_, perm) = mem.vmap.translate(VirtualAddr::from(va)).ok_or(OsError::BadAddress)?;
+    let page = VirtualAddr::from((va & PAGE_MASK) - USER_IMG_BASE);
+    if !perm.is_writable() && mem.vmap.handle_cow_fault(page) {
+        aarch64::invalidate_tlb();
     }
+    let (paddr, _) = mem.vmap.translate(VirtualAddr::from(va)).ok_or(OsError::BadAddress)?;
+    Ok(paddr.as_usize())
 }
 
-/// Sends data with a connected socket.
+/// Waits on a futex.
 ///
-/// This system call takes a socket descriptor as the first parameter, the
-/// address of the buffer as the second parameter, and the length of the buffer
-/// as the third parameter.
+/// This system call takes three parameters: the address of an aligned 32-bit
+/// word, the value the word is expected to hold, and a timeout in
+/// milliseconds, or 0 to wait without one. If the word holds the expected
+/// value, the process sleeps until `futex_wake` is called on the same word;
+/// otherwise it returns at once. The check and the sleep are atomic with
+/// respect to `futex_wake`. The wait may also end early when a signal is
+/// delivered, so callers re-check the word.
 ///
-/// In addition to the usual status value, this system call returns one
-/// parameter: the number of bytes sent.
+/// It only returns the usual status value.
 ///
 /// # Errors
 /// This function can return following errors:
 ///
-/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
-/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
-/// - `OsError::IllegalSocketOperation`: `send_slice()` returned `smoltcp::Error::Illegal`.
-/// - `OsError::Unknown`: All the other errors from smoltcp.
-pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_send")
+/// - `OsError::InvalidArgument`: The address is not aligned.
+/// - `OsError::BadAddress`: The address is not mapped.
+/// - `OsError::IoErrorTimedOut`: The timeout expired before a wake.
+pub fn sys_futex_wait(va: usize, expected: u32, timeout: u64, tf: &mut TrapFrame) {
+    use pi::timer::current_time;
+
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        let key = futex_key(process, va)?;
+        // The key is where the kernel sees the word.
+        let value = unsafe { (key as *const u32).read_volatile() };
+        if value != expected {
+            return Ok(false);
+        }
+        process.futex = Some(key);
+        scheduler.queue_futex_waiter(key, tf.tpidr_el0);
+        Ok(true)
+    });
+    match result {
+        Ok(true) => {
+            let deadline = match timeout {
+                0 => None,
+                ms => Some(current_time() + Duration::from_millis(ms)),
+            };
+            tf.x[7] = OsError::Ok as u64;
+            // `futex_wake` makes the process ready; this only covers a wake
+            // that came before the process slept, and the timeout.
+            let woken = Box::new(move |process: &mut Process| -> bool {
+                if process.futex.is_none() {
+                    return true;
+                }
+                match deadline {
+                    Some(deadline) if current_time() >= deadline => {
+                        process.context.x[7] = OsError::IoErrorTimedOut as u64;
+                        true
+                    }
+                    _ => false,
+                }
+            });
+            SCHEDULER.switch(State::Waiting(woken), tf);
+        }
+        Ok(false) => set_result(Ok(0), tf),
+        Err(e) => set_result(Err(e), tf),
+    }
 }
 
-/// Receives data from a connected socket.
+/// Wakes processes waiting on a futex.
 ///
-/// This system call takes a socket descriptor as the first parameter, the
-/// address of the buffer as the second parameter, and the length of the buffer
-/// as the third parameter.
+/// This system call takes two parameters: the address of an aligned 32-bit
+/// word and the most processes to wake.
 ///
 /// In addition to the usual status value, this system call returns one
-/// parameter: the number of bytes read.
+/// parameter: the number of processes woken.
 ///
 /// # Errors
 /// This function can return following errors:
 ///
-/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
-/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
-/// - `OsError::IllegalSocketOperation`: `recv_slice()` returned `smoltcp::Error::Illegal`.
-/// - `OsError::Unknown`: All the other errors from smoltcp.
-pub fn sys_sock_recv(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_recv")
+/// - `OsError::InvalidArgument`: The address is not aligned.
+/// - `OsError::BadAddress`: The address is not mapped.
+pub fn sys_futex_wake(va: usize, n: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| {
+        let key = futex_key(scheduler.find_process(tf), va)?;
+        Ok(scheduler.wake_futex(key, n) as u64)
+    });
+    set_result(result, tf);
 }
 
-/// Writes a UTF-8 string to the console.
+/// Reads `count` `ArgRef`s at `va` and the UTF-8 strings they refer to.
+fn user_args(va: usize, count: usize, tf: &TrapFrame) -> OsResult<Vec<String>> {
+    if count > ARGV_MAX {
+        return Err(OsError::InvalidArgument);
+    }
+    read_user::<ArgRef>(va, count, tf)?
+        .iter()
+        .map(|arg| {
+            let bytes = copy_from_user(arg.ptr as usize, arg.len as usize, tf)?;
+            String::from_utf8(bytes).map_err(|_| OsError::InvalidArgument)
+        })
+        .collect()
+}
+
+/// Loads the program named by the arguments of `exec` or `spawn` into a new
+/// process.
+fn load_user_program(
+    path_va: usize,
+    path_len: usize,
+    argv_va: usize,
+    argc: usize,
+    envp_va: usize,
+    envc: usize,
+    tf: &TrapFrame,
+) -> OsResult<Process> {
+    let path = user_path(path_va, path_len, tf)?;
+    let argv = user_args(argv_va, argc, tf)?;
+    let envp = user_args(envp_va, envc, tf)?;
+    let argv: Vec<&str> = argv.iter().map(|arg| arg.as_str()).collect();
+    let envp: Vec<&str> = envp.iter().map(|var| var.as_str()).collect();
+    Process::load(path, &argv, &envp)
+}
+
+/// Replaces the current process with a program.
 ///
-/// This system call takes the address of the buffer as the first parameter and
-/// the length of the buffer as the second parameter.
+/// This system call takes six parameters: the address and length of the
+/// program's UTF-8 path, the address and number of `ArgRef`s for its
+/// arguments, and the address and number of `ArgRef`s for its environment
+/// (`name=value` strings). A relative path is resolved against the current
+/// working directory.
 ///
-/// In addition to the usual status value, this system call returns the length
-/// of the UTF-8 message.
+/// It does not return on success: the process continues at the entry point of
+/// the new program with the same ID, descriptors and working directory. The
+/// other threads of the process end; only its first thread may call `exec`.
 ///
 /// # Errors
 /// This function can return following errors:
 ///
-/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
-/// - `OsError::InvalidArgument`: The provided buffer is not UTF-8 encoded.
-pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
-    let result = unsafe { to_user_slice(va, len) }
-        .and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument));
+/// - `OsError::BadAddress`: The path, an array or a string is not entirely mapped in the process.
+/// - `OsError::InvalidArgument`: A string is not UTF-8 encoded, there are more
+///   than `ARGV_MAX` arguments or variables, they do not fit on the new stack,
+///   the caller is a kernel thread, or it is not the first thread of its process.
+/// - `OsError::NoEntry`: There is no program at the path.
+/// - `OsError::InvalidExecutable`, `OsError::UnsupportedArchitecture`: The
+///   program is not an AArch64 ELF executable.
+pub fn sys_exec(
+    path_va: usize,
+    path_len: usize,
+    argv_va: usize,
+    argc: usize,
+    envp_va: usize,
+    envc: usize,
+    tf: &mut TrapFrame,
+) {
+    let result = load_user_program(path_va, path_len, argv_va, argc, envp_va, envc, tf)
+        .and_then(|image| {
+            SCHEDULER.critical(|scheduler| {
+                scheduler.end_other_threads(tf)?;
+                let process = scheduler.find_process(tf);
+                process.exec(image, tf)
+            })
+        });
     match result {
-        Ok(msg) => {
-            kprint!("{}", msg);
+        Ok(old) => {
+            // Move off the old page table before its pages are freed.
+            unsafe { aarch64::TTBR1_EL1.set(tf.ttbr1_el1) };
+            aarch64::invalidate_tlb();
+            drop(old);
+        }
+        Err(e) => set_result(Err(e), tf),
+    }
+}
 
-            tf.x[0] = msg.len() as u64;
+/// Starts a program in a new process.
+///
+/// This system call takes the same six parameters as `exec`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new process's ID. The new process gets a copy of the
+/// caller's descriptors and working directory.
+///
+/// # Errors
+/// This function can return the errors of `exec`, and:
+///
+/// - `OsError::NoMemory`: No process ID is available.
+pub fn sys_spawn(
+    path_va: usize,
+    path_len: usize,
+    argv_va: usize,
+    argc: usize,
+    envp_va: usize,
+    envc: usize,
+    tf: &mut TrapFrame,
+) {
+    let result = load_user_program(path_va, path_len, argv_va, argc, envp_va, envc, tf)
+        .and_then(|child| SCHEDULER.critical(|scheduler| scheduler.spawn(child, tf)));
+    set_result(result, tf);
+}
+
+/// Returns the version of the system call table the kernel implements.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: `ABI_VERSION`.
+pub fn sys_abi_version(tf: &mut TrapFrame) {
+    set_result(Ok(ABI_VERSION), tf);
+}
+
+/// Defines `dispatch()` from the entries of `syscall_table!`.
+macro_rules! define_dispatch {
+    ($($nr_name:ident = $nr:literal, since $since:literal
+       => $name:ident($($arg:ident: $ty:ty),*) => $handler:ident;)*) => {
+        /// Calls the handler of system call `num` with its arguments decoded
+        /// from `tf`. An unknown `num` fails with `OsError::Unknown`.
+        fn dispatch(num: u16, tf: &mut TrapFrame) {
+            let regs = tf.x;
+            #[allow(unused_variables, unused_mut)]
+            let mut regs = regs.iter();
+            match num as usize {
+                $($nr => $handler($(*regs.next().unwrap() as $ty,)* tf),)*
+                _ => tf.x[7] = OsError::Unknown as u64,
+            }
+        }
+    };
+}
+
+kernel_api::syscall_table!(define_dispatch);
+
+/// Handles system call `num` issued with `tf`. If the calling thread is
+/// traced, the call is logged with its arguments, result and status.
+pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
+    let traced = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).traced);
+    if !traced {
+        return dispatch(num, tf);
+    }
+    let (tid, elr) = (tf.tpidr_el0, tf.elr_el1);
+    let args = tf.x;
+    dispatch(num, tf);
+    let mut call = match syscall_info(num as usize) {
+        Some(info) => {
+            let args: Vec<String> = args[..info.args.len()]
+                .iter()
+                .map(|arg| format!("{:#x}", arg))
+                .collect();
+            format!("{}({})", info.name, args.join(", "))
+        }
+        None => format!("unknown({})", num),
+    };
+    // A call that blocked, or never returns, left `tf` to another process or
+    // moved `elr`; a blocked call is logged again when it is reissued.
+    if tf.tpidr_el0 != tid || tf.elr_el1 != elr {
+        call.push_str(" = ? <unfinished>");
+    } else {
+        call.push_str(&format!(" = {:#x} {:?}", tf.x[0], OsError::from(tf.x[7])));
+    }
+    kprintln!("[{}] {}", tid, call);
+}
+
+/// Creates a socket and saves the socket handle in the current process's
+/// descriptor table.
+///
+/// This function does neither take any parameter nor return anything,
+/// except the usual return code that indicates successful syscall execution.
+pub fn sys_sock_create(tf: &mut TrapFrame) {
+    // Lab 5 2.D
+    let handle = ETHERNET.add_socket();
+    let sock_idx = SCHEDULER.critical(|scheduler| {
+        let socket = Descriptor::Socket(UserSocket::Tcp(handle));
+        scheduler.find_process(tf).fds.lock().insert(socket)
+    });
+    tf.x[0] = sock_idx as u64;
+    tf.x[7] = OsError::Ok as u64;
+}
+
+/// Returns the socket that descriptor `sock_idx` of the current process
+/// refers to.
+fn user_socket(sock_idx: usize, tf: &TrapFrame) -> OsResult<UserSocket> {
+    SCHEDULER.critical(|scheduler| {
+        match scheduler.find_process(tf).fds.lock().get(sock_idx) {
+            Some(Descriptor::Socket(socket)) => Ok(*socket),
+            _ => Err(OsError::InvalidSocket),
+        }
+    })
+}
+
+/// Returns the handle of the TCP socket that corresponds to `sock_idx`.
+fn socket_handle(sock_idx: usize, tf: &TrapFrame) -> OsResult<SocketHandle> {
+    match user_socket(sock_idx, tf)? {
+        UserSocket::Tcp(handle) => Ok(handle),
+        UserSocket::Udp(_) => Err(OsError::IllegalSocketOperation),
+    }
+}
+
+/// Returns the handle of the UDP socket that corresponds to `sock_idx`.
+fn udp_handle(sock_idx: usize, tf: &TrapFrame) -> OsResult<SocketHandle> {
+    match user_socket(sock_idx, tf)? {
+        UserSocket::Udp(handle) => Ok(handle),
+        UserSocket::Tcp(_) => Err(OsError::IllegalSocketOperation),
+    }
+}
+
+/// Returns the endpoint of the big-endian IPv4 address `ip` and `port`.
+fn ip_endpoint(ip: u32, port: u16) -> IpEndpoint {
+    let ip = ip.to_be_bytes();
+    IpEndpoint::new(IpAddress::v4(ip[0], ip[1], ip[2], ip[3]), port)
+}
+
+/// Converts a smoltcp error to the `OsError` reported to user space.
+fn socket_error(e: smoltcp::Error) -> OsError {
+    match e {
+        smoltcp::Error::Illegal => OsError::IllegalSocketOperation,
+        smoltcp::Error::Unaddressable => OsError::BadAddress,
+        _ => OsError::Unknown,
+    }
+}
+
+/// Stores `result` in the usual return registers: the value in `x0` and the
+/// status in `x7`.
+fn set_result(result: OsResult<u64>, tf: &mut TrapFrame) {
+    match result {
+        Ok(value) => {
+            tf.x[0] = value;
             tf.x[7] = OsError::Ok as u64;
         }
         Err(e) => {
@@ -270,3 +774,1287 @@ pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
     }
 }
 
+/// Returns the status of a socket.
+///
+/// This system call takes a socket descriptor as the first parameter.
+///
+/// In addition to the usual status value, this system call returns four boolean
+/// values that describes the status of the queried socket.
+///
+/// - x0: is_active
+/// - x1: is_listening
+/// - x2: can_send
+/// - x3: can_recv
+///
+/// # Errors
+/// This function returns `OsError::InvalidSocket` if a socket that corresponds
+/// to the provided descriptor is not found.
+pub fn sys_sock_status(sock_idx: usize, tf: &mut TrapFrame) {
+    // Lab 5 2.D
+    match socket_handle(sock_idx, tf) {
+        Ok(handle) => {
+            let status = ETHERNET.with_socket(handle, |socket| {
+                [socket.is_active(), socket.is_listening(), socket.can_send(), socket.can_recv()]
+            });
+            for (i, flag) in status.iter().enumerate() {
+                tf.x[i] = *flag as u64;
+            }
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Connects a local ephemeral port to a remote IP endpoint with a socket.
+///
+/// This system call takes a socket descriptor as the first parameter, the IP
+/// of the remote endpoint as the second paramter in big endian, and the port
+/// number of the remote endpoint as the third parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: Fails to allocate an ephemeral port
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::IllegalSocketOperation`: `connect()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::BadAddress`: `connect()` returned `smoltcp::Error::Unaddressable`.
+/// - `OsError::Unknown`: All the other errors from calling `connect()`.
+pub fn sys_sock_connect(sock_idx: usize, ip: u32, port: u16, tf: &mut TrapFrame) {
+    // Lab 5 2.D
+    let remote_endpoint = ip_endpoint(ip, port);
+    let result = socket_handle(sock_idx, tf).and_then(|handle| {
+        let port = ETHERNET
+            .critical(|driver| driver.get_ephemeral_port().and_then(|port| driver.mark_port(port)))
+            .ok_or(OsError::NoEntry)?;
+        ETHERNET
+            .with_socket(handle, |socket| socket.connect(remote_endpoint, port))
+            .map_err(|e| {
+                ETHERNET.erase_port(port);
+                socket_error(e)
+            })
+    });
+    set_result(result.map(|_| 0), tf);
+}
+
+/// Listens on a local port for an inbound connection.
+///
+/// This system call takes a socket descriptor as the first parameter and the
+/// local ports to listen on as the second parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::NoAccess`: The port is one the kernel listens on.
+/// - `OsError::FileExists`: The port is in use by a socket that is not listening.
+/// - `OsError::IllegalSocketOperation`: `listen()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::BadAddress`: `listen()` returned `smoltcp::Error::Unaddressable`.
+/// - `OsError::Unknown`: All the other errors from calling `listen()`.
+pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
+    // Lab 5 2.D
+    let result = socket_handle(sock_idx, tf).and_then(|handle| {
+        if KERNEL_PORTS.contains(&local_port) {
+            return Err(OsError::NoAccess);
+        }
+        ETHERNET.critical(|driver| {
+            // Several sockets may listen on the same port; it is freed when
+            // the last of them is closed.
+            driver.share_port(local_port).ok_or(OsError::FileExists)?;
+            let listened = driver.get_socket(handle).listen(local_port);
+            if listened.is_err() {
+                driver.release_port(local_port);
+            }
+            listened.map_err(socket_error)
+        })
+    });
+    set_result(result.map(|_| 0), tf);
+}
+
+/// Closes a socket. This is `sys_close` restricted to socket descriptors.
+///
+/// This system call takes a socket descriptor as the first parameter. The
+/// connection is shut down gracefully in the background; the descriptor is
+/// invalid once this call returns.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function returns `OsError::InvalidSocket` if the descriptor is not
+/// an open socket.
+pub fn sys_sock_close(sock_idx: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| {
+        let mut fds = scheduler.find_process(tf).fds.lock();
+        match fds.get(sock_idx) {
+            Some(Descriptor::Socket(_)) => Ok(fds.remove(sock_idx)),
+            _ => Err(OsError::InvalidSocket),
+        }
+    });
+    let result = result.map(|descriptor| {
+        if let Some(Descriptor::Socket(socket)) = descriptor {
+            ETHERNET.critical(|driver| driver.close_user_socket(socket));
+        }
+        0
+    });
+    set_result(result, tf);
+}
+
+/// Creates a UDP socket bound to a local port and saves it in the current
+/// process's descriptor table.
+///
+/// This system call takes the local port as the first parameter. Port 0 binds
+/// the socket to a free ephemeral port.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the socket descriptor of the new socket.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: Fails to allocate an ephemeral port.
+/// - `OsError::FileExists`: The requested port is already in use.
+pub fn sys_udp_bind(local_port: u16, tf: &mut TrapFrame) {
+    let port = ETHERNET.critical(|driver| match local_port {
+        0 => driver
+            .get_ephemeral_port()
+            .and_then(|port| driver.mark_port(port))
+            .ok_or(OsError::NoEntry),
+        port => driver.mark_port(port).ok_or(OsError::FileExists),
+    });
+    let result = port.map(|port| {
+        let handle = ETHERNET.add_udp_socket();
+        ETHERNET
+            .with_udp_socket(handle, |socket| socket.bind(port))
+            .expect("binding a fresh UDP socket to a free port");
+        SCHEDULER.critical(|scheduler| {
+            let socket = Descriptor::Socket(UserSocket::Udp(handle));
+            scheduler.find_process(tf).fds.lock().insert(socket) as u64
+        })
+    });
+    set_result(result, tf);
+}
+
+/// Sends a datagram with a UDP socket.
+///
+/// This system call takes a socket descriptor as the first parameter, the
+/// address of the buffer as the second parameter, the length of the buffer as
+/// the third parameter, the IP of the remote endpoint in big endian as the
+/// fourth parameter, and its port as the fifth parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes sent.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::IllegalSocketOperation`: The descriptor is not a UDP socket.
+/// - `OsError::BadAddress`: The buffer is not entirely mapped in the process,
+///   or the remote endpoint is unaddressable.
+/// - `OsError::NoMemory`: The transmit buffer is full.
+pub fn sys_udp_send_to(sock_idx: usize, va: usize, len: usize, ip: u32, port: u16, tf: &mut TrapFrame) {
+    let remote_endpoint = ip_endpoint(ip, port);
+    let result = udp_handle(sock_idx, tf).and_then(|handle| {
+        let buf = copy_from_user(va, len, tf)?;
+        ETHERNET
+            .with_udp_socket(handle, |socket| socket.send_slice(&buf, remote_endpoint))
+            .map_err(|e| match e {
+                smoltcp::Error::Exhausted | smoltcp::Error::Truncated => OsError::NoMemory,
+                e => socket_error(e),
+            })
+    });
+    set_result(result.map(|_| len as u64), tf);
+}
+
+/// Receives a datagram with a UDP socket without blocking.
+///
+/// This system call takes a socket descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the buffer
+/// as the third parameter. A datagram longer than the buffer is truncated.
+///
+/// In addition to the usual status value, this system call returns three
+/// parameters:
+///
+/// - x0: the number of bytes received
+/// - x1: the IP of the sender in big endian
+/// - x2: the port of the sender, or 0 if no datagram was queued
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::IllegalSocketOperation`: The descriptor is not a UDP socket.
+/// - `OsError::BadAddress`: The buffer is not entirely mapped writable in the process.
+pub fn sys_udp_recv_from(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = udp_handle(sock_idx, tf).and_then(|handle| {
+        check_user(va, len, true, tf)?;
+        let datagram = ETHERNET.with_udp_socket(handle, |socket| match socket.recv() {
+            Ok((data, endpoint)) => {
+                let size = core::cmp::min(data.len(), len);
+                Some((data[..size].to_vec(), endpoint))
+            }
+            Err(_) => None,
+        });
+        match datagram {
+            Some((data, endpoint)) => {
+                copy_to_user(va, &data, tf)?;
+                Ok(Some((data.len(), endpoint)))
+            }
+            None => Ok(None),
+        }
+    });
+    match result {
+        Ok(Some((size, endpoint))) => {
+            let ip = match endpoint.addr {
+                IpAddress::Ipv4(addr) => u32::from_be_bytes(addr.0),
+                _ => 0,
+            };
+            tf.x[0] = size as u64;
+            tf.x[1] = ip as u64;
+            tf.x[2] = endpoint.port as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Ok(None) => {
+            tf.x[0] = 0;
+            tf.x[1] = 0;
+            tf.x[2] = 0;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Returns the DNS server configured in the kernel.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the IP of the server in big endian.
+///
+/// # Errors
+/// This function returns `OsError::NoEntry` if no server is configured.
+pub fn sys_dns_server(tf: &mut TrapFrame) {
+    let result = crate::net::dns_server()
+        .map(|server| u32::from_be_bytes(server.0) as u64)
+        .ok_or(OsError::NoEntry);
+    set_result(result, tf);
+}
+
+/// Checks that the `len` bytes at `va` are mapped in the current process,
+/// and writable if `write` is set. Pages of the stack the range reaches are
+/// mapped first, as if user code had touched them.
+///
+/// # Errors
+/// This functions returns `Err(OsError::BadAddress)` if a page of the range is
+/// not mapped, or is read-only and `write` is set.
+/// `Err(OsError::NoMemory)` is returned if a page of the stack could not be
+/// mapped.
+fn check_user(va: usize, len: usize, write: bool, tf: &TrapFrame) -> OsResult<()> {
+    SCHEDULER.critical(|scheduler| {
+        let mut mem = scheduler.find_process(tf).mem.lock();
+        mem.grow_stack(va, len)?;
+        mem.vmap.validate(va, len, write)
+    })
+}
+
+/// Copies the `len` bytes at `va` in the current process into a new buffer.
+///
+/// # Errors
+/// This functions returns `Err(OsError::BadAddress)` if a page of the range is
+/// not mapped.
+/// `Err(OsError::NoMemory)` is returned if a page of the stack could not be
+/// mapped.
+fn copy_from_user(va: usize, len: usize, tf: &TrapFrame) -> OsResult<Vec<u8>> {
+    SCHEDULER.critical(|scheduler| {
+        let mut mem = scheduler.find_process(tf).mem.lock();
+        mem.grow_stack(va, len)?;
+        mem.vmap.copy_from_user(va, len)
+    })
+}
+
+/// Copies `buf` to `va` in the current process.
+///
+/// # Errors
+/// This functions returns `Err(OsError::BadAddress)`, writing nothing, if a
+/// page of the range is not mapped or is read-only.
+/// `Err(OsError::NoMemory)` is returned if a page of the stack could not be
+/// mapped.
+fn copy_to_user(va: usize, buf: &[u8], tf: &TrapFrame) -> OsResult<()> {
+    SCHEDULER.critical(|scheduler| {
+        let mut mem = scheduler.find_process(tf).mem.lock();
+        mem.grow_stack(va, buf.len())?;
+        mem.vmap.copy_to_user(va, buf)
+    })
+}
+
+/// Reads the `count` values of type `T` at `va` in the current process.
+fn read_user<T: Copy>(va: usize, count: usize, tf: &TrapFrame) -> OsResult<Vec<T>> {
+    let size = count.checked_mul(core::mem::size_of::<T>()).ok_or(OsError::BadAddress)?;
+    let bytes = copy_from_user(va, size, tf)?;
+    let values = bytes.as_ptr() as *const T;
+    Ok((0..count).map(|i| unsafe { values.add(i).read_unaligned() }).collect())
+}
+
+/// Returns the bytes of `values`, to be copied to userspace.
+fn as_bytes<T: Copy>(values: &[T]) -> &[u8] {
+    let size = values.len() * core::mem::size_of::<T>();
+    unsafe { core::slice::from_raw_parts(values.as_ptr() as *const u8, size) }
+}
+
+/// Sends data with a connected socket.
+///
+/// This system call takes a socket descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the buffer
+/// as the third parameter. At most `USER_IO_MAX` bytes are sent per call.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes sent.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::BadAddress`: The buffer is not entirely mapped in the process.
+/// - `OsError::IllegalSocketOperation`: `send_slice()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::Unknown`: All the other errors from smoltcp.
+pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
+    // Lab 5 2.D
+    let result = socket_handle(sock_idx, tf).and_then(|handle| {
+        let buf = copy_from_user(va, core::cmp::min(len, USER_IO_MAX), tf)?;
+        ETHERNET
+            .with_socket(handle, |socket| socket.send_slice(&buf))
+            .map_err(socket_error)
+    });
+    set_result(result.map(|sent| sent as u64), tf);
+}
+
+/// Receives data from a connected socket.
+///
+/// This system call takes a socket descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the buffer
+/// as the third parameter. At most `USER_IO_MAX` bytes are received per call.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::BadAddress`: The buffer is not entirely mapped writable in the process.
+/// - `OsError::IllegalSocketOperation`: `recv_slice()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::Unknown`: All the other errors from smoltcp.
+pub fn sys_sock_recv(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
+    // Lab 5 2.D
+    let result = socket_handle(sock_idx, tf).and_then(|handle| {
+        check_user(va, len, true, tf)?;
+        let mut buf = vec![0; core::cmp::min(len, USER_IO_MAX)];
+        let received = ETHERNET
+            .with_socket(handle, |socket| socket.recv_slice(&mut buf))
+            .map_err(socket_error)?;
+        copy_to_user(va, &buf[..received], tf)?;
+        Ok(received)
+    });
+    set_result(result.map(|received| received as u64), tf);
+}
+
+/// Writes a UTF-8 string to the console.
+///
+/// This system call takes the address of the buffer as the first parameter and
+/// the length of the buffer as the second parameter.
+///
+/// In addition to the usual status value, this system call returns the length
+/// of the UTF-8 message. At most `USER_IO_MAX` bytes, cut at a character
+/// boundary, are written.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The buffer is not entirely mapped in the process.
+/// - `OsError::InvalidArgument`: The provided buffer is not UTF-8 encoded.
+pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
+    let clamped = len > USER_IO_MAX;
+    let result = copy_from_user(va, len.min(USER_IO_MAX), tf).and_then(|mut bytes| {
+        match core::str::from_utf8(&bytes) {
+            Ok(_) => {}
+            // A character split by the clamp is left for the next write.
+            Err(e) if clamped && e.error_len().is_none() => bytes.truncate(e.valid_up_to()),
+            Err(_) => return Err(OsError::InvalidArgument),
+        }
+        String::from_utf8(bytes).map_err(|_| OsError::InvalidArgument)
+    });
+    match result {
+        Ok(msg) => {
+            kprint!("{}", msg);
+
+            tf.x[0] = msg.len() as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.x[7] = e as u64;
+        }
+    }
+}
+
+/// What a descriptor operation that cannot make progress yet waits for.
+enum WaitFor {
+    ConsoleInput,
+    SocketRecv(SocketHandle),
+    SocketSend(SocketHandle),
+    PipeRead(Pipe),
+    PipeWrite(Pipe),
+    ChannelRecv(Channel),
+    ChannelSend(Channel),
+    /// One of the descriptors of a `poll` is ready, or the deadline passes.
+    Poll(Vec<PollFd>, Option<Duration>),
+    /// A child of the waiting process exits.
+    ChildExit,
+    /// Another thread of the waiting process exits.
+    ThreadExit,
+}
+
+/// Returns the descriptor table of the current process.
+///
+/// Only the `Arc` is cloned under the scheduler's lock: descriptors are used
+/// holding just the table's lock, so that file system I/O on them does not
+/// run with interrupts masked.
+fn descriptors(tf: &TrapFrame) -> Arc<Mutex<DescriptorTable>> {
+    SCHEDULER.critical(|scheduler| scheduler.find_process(tf).fds.clone())
+}
+
+/// Outcome of a read or write on a descriptor.
+enum Progress {
+    Done(OsResult<u64>),
+    Blocked(WaitFor),
+}
+
+/// Puts the current process to sleep until `event` happens and then restarts
+/// the system call, which runs again with the same arguments.
+fn block_on(event: WaitFor, tf: &mut TrapFrame) {
+    let ready = Box::new(move |process: &mut Process| -> bool {
+        match &event {
+            WaitFor::ConsoleInput => CONSOLE.lock().has_byte(),
+            WaitFor::SocketRecv(handle) => {
+                ETHERNET.with_socket(*handle, |socket| socket.can_recv() || !socket.may_recv())
+            }
+            WaitFor::SocketSend(handle) => {
+                ETHERNET.with_socket(*handle, |socket| socket.can_send() || !socket.may_send())
+            }
+            WaitFor::PipeRead(pipe) => pipe.is_readable(),
+            WaitFor::PipeWrite(pipe) => pipe.is_writable(),
+            WaitFor::ChannelRecv(channel) => channel.can_recv(),
+            WaitFor::ChannelSend(channel) => channel.can_send(),
+            WaitFor::Poll(polls, deadline) => {
+                let ready = {
+                    let fds = process.fds.lock();
+                    polls.iter().any(|poll| poll_revents(&fds, poll) != 0)
+                };
+                match deadline {
+                    Some(deadline) if !ready && pi::timer::current_time() >= *deadline => {
+                        // The restarted call only reports what is ready now.
+                        process.context.x[2] = 0;
+                        true
+                    }
+                    _ => ready,
+                }
+            }
+            WaitFor::ChildExit => core::mem::replace(&mut process.child_exited, false),
+            WaitFor::ThreadExit => core::mem::replace(&mut process.thread_exited, false),
+        }
+    });
+    // `elr` points past the `svc`; step back so that it is issued again.
+    tf.elr_el1 -= 4;
+    SCHEDULER.switch(State::Waiting(ready), tf);
+}
+
+/// Reads a UTF-8 path from userspace and resolves it against the current
+/// process's working directory.
+fn user_path(va: usize, len: usize, tf: &TrapFrame) -> OsResult<PathBuf> {
+    let path = copy_from_user(va, len, tf)
+        .and_then(|bytes| String::from_utf8(bytes).map_err(|_| OsError::InvalidArgument))?;
+    let mut absolute = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).cwd.clone());
+    merge_paths(&mut absolute, &PathBuf::from(path.as_str()));
+    Ok(absolute)
+}
+
+/// Returns the `Stat` of a directory entry.
+fn entry_stat(entry: &fat32::vfat::Entry<PiVFatHandle>) -> Stat {
+    use fat32::traits::{Entry, File, Metadata};
+
+    let metadata = entry.metadata();
+    Stat {
+        size: entry.as_file().map(|file| file.size()).unwrap_or(0),
+        is_dir: entry.is_dir(),
+        read_only: metadata.read_only(),
+        hidden: metadata.hidden(),
+    }
+}
+
+/// Opens a file or a directory.
+///
+/// This system call takes the address of a UTF-8 path as the first parameter
+/// and the length of the path as the second parameter. A relative path is
+/// resolved against the current working directory.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new descriptor.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The path is not entirely mapped in the process.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded.
+/// - `OsError::NoEntry`: There is no entry at the path.
+/// - `OsError::IoError`: The file system failed.
+pub fn sys_open(va: usize, len: usize, tf: &mut TrapFrame) {
+    use fat32::traits::{Entry, FileSystem};
+
+    let result = user_path(va, len, tf)
+        .and_then(|path| crate::FILESYSTEM.open(path).map_err(OsError::from))
+        .map(|entry| {
+            let descriptor = if entry.is_dir() {
+                Descriptor::Dir {
+                    dir: entry.into_dir().unwrap(),
+                    next: 0,
+                }
+            } else {
+                Descriptor::File(entry.into_file().unwrap())
+            };
+            SCHEDULER.critical(|scheduler| scheduler.find_process(tf).fds.lock().insert(descriptor) as u64)
+        });
+    set_result(result, tf);
+}
+
+/// Reads from a descriptor.
+///
+/// This system call takes a descriptor as the first parameter, the address of
+/// the buffer as the second parameter, and the length of the buffer as the
+/// third parameter.
+///
+/// Reading from the console or a TCP socket blocks until at least one byte is
+/// available. Zero bytes are read at end-of-file and once the peer of a socket
+/// has closed its side of the connection. At most `USER_IO_MAX` bytes are read
+/// per call.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The descriptor is not open, or refers to a directory.
+/// - `OsError::BadAddress`: The buffer is not entirely mapped writable in the process.
+/// - `OsError::IllegalSocketOperation`: The descriptor refers to a UDP socket.
+/// - `OsError::IoError`: The file system failed to read the file.
+pub fn sys_read(fd: usize, va: usize, len: usize, tf: &mut TrapFrame) {
+    use shim::io::Read;
+
+    // Check the buffer first so that no input is consumed and then lost.
+    if let Err(e) = check_user(va, len, true, tf) {
+        return set_result(Err(e), tf);
+    }
+    let mut buf = vec![0; core::cmp::min(len, USER_IO_MAX)];
+    let fds = descriptors(tf);
+    let progress = match fds.lock().get_mut(fd) {
+        Some(Descriptor::Console) => {
+            let mut console = CONSOLE.lock();
+            if buf.is_empty() {
+                Progress::Done(Ok(0))
+            } else if !console.has_byte() {
+                Progress::Blocked(WaitFor::ConsoleInput)
+            } else {
+                let mut read = 0;
+                while read < buf.len() && console.has_byte() {
+                    buf[read] = console.read_byte();
+                    read += 1;
+                }
+                Progress::Done(Ok(read as u64))
+            }
+        }
+        Some(Descriptor::File(file)) => {
+            Progress::Done(file.read(&mut buf).map(|n| n as u64).map_err(OsError::from))
+        }
+        Some(Descriptor::Socket(UserSocket::Tcp(handle))) => {
+            let handle = *handle;
+            ETHERNET.with_socket(handle, |socket| {
+                if buf.is_empty() || (!socket.can_recv() && !socket.may_recv()) {
+                    Progress::Done(Ok(0))
+                } else if !socket.can_recv() {
+                    Progress::Blocked(WaitFor::SocketRecv(handle))
+                } else {
+                    Progress::Done(socket.recv_slice(&mut buf).map(|n| n as u64).map_err(socket_error))
+                }
+            })
+        }
+        Some(Descriptor::Socket(UserSocket::Udp(_))) => {
+            Progress::Done(Err(OsError::IllegalSocketOperation))
+        }
+        Some(Descriptor::PipeRead(reader)) => match reader.read(&mut buf) {
+            Some(read) => Progress::Done(Ok(read as u64)),
+            None => Progress::Blocked(WaitFor::PipeRead(reader.pipe().clone())),
+        },
+        Some(Descriptor::Dir { .. })
+        | Some(Descriptor::PipeWrite(_))
+        | Some(Descriptor::Shm(_))
+        | Some(Descriptor::Channel(_))
+        | None => {
+            Progress::Done(Err(OsError::InvalidArgument))
+        }
+    };
+    match progress {
+        Progress::Done(Ok(read)) => {
+            let result = copy_to_user(va, &buf[..read as usize], tf).map(|_| read);
+            set_result(result, tf)
+        }
+        Progress::Done(result) => set_result(result, tf),
+        Progress::Blocked(event) => block_on(event, tf),
+    }
+}
+
+/// Writes to a descriptor.
+///
+/// This system call takes a descriptor as the first parameter, the address of
+/// the buffer as the second parameter, and the length of the buffer as the
+/// third parameter.
+///
+/// Writing to a TCP socket or a pipe blocks while its buffer is full and may
+/// write fewer bytes than requested. At most `USER_IO_MAX` bytes are written
+/// per call.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The descriptor is not open, or refers to a directory.
+/// - `OsError::BadAddress`: The buffer is not entirely mapped in the process.
+/// - `OsError::NoAccess`: The descriptor refers to a file on the read-only file system.
+/// - `OsError::IllegalSocketOperation`: The descriptor refers to a UDP socket, or the
+///   connection no longer accepts data.
+/// - `OsError::BrokenPipe`: The descriptor refers to a pipe whose read ends are all
+///   closed. `SIGPIPE` is raised as well.
+pub fn sys_write_fd(fd: usize, va: usize, len: usize, tf: &mut TrapFrame) {
+    use shim::io::Write;
+
+    let buf = match copy_from_user(va, core::cmp::min(len, USER_IO_MAX), tf) {
+        Ok(buf) => buf,
+        Err(e) => return set_result(Err(e), tf),
+    };
+    let fds = descriptors(tf);
+    let mut broken_pipe = false;
+    let progress = match fds.lock().get_mut(fd) {
+        Some(Descriptor::Console) => {
+            let mut console = CONSOLE.lock();
+            for &byte in buf.iter() {
+                console.write_byte(byte);
+            }
+            Progress::Done(Ok(buf.len() as u64))
+        }
+        Some(Descriptor::File(file)) => {
+            Progress::Done(file.write(&buf).map(|n| n as u64).map_err(OsError::from))
+        }
+        Some(Descriptor::Socket(UserSocket::Tcp(handle))) => {
+            let handle = *handle;
+            ETHERNET.with_socket(handle, |socket| {
+                if buf.is_empty() {
+                    Progress::Done(Ok(0))
+                } else if !socket.may_send() {
+                    Progress::Done(Err(OsError::IllegalSocketOperation))
+                } else if !socket.can_send() {
+                    Progress::Blocked(WaitFor::SocketSend(handle))
+                } else {
+                    Progress::Done(socket.send_slice(&buf).map(|n| n as u64).map_err(socket_error))
+                }
+            })
+        }
+        Some(Descriptor::Socket(UserSocket::Udp(_))) => {
+            Progress::Done(Err(OsError::IllegalSocketOperation))
+        }
+        Some(Descriptor::PipeWrite(writer)) => match writer.write(&buf) {
+            Ok(Some(written)) => Progress::Done(Ok(written as u64)),
+            Ok(None) => Progress::Blocked(WaitFor::PipeWrite(writer.pipe().clone())),
+            Err(e) => {
+                broken_pipe = true;
+                Progress::Done(Err(e))
+            }
+        },
+        Some(Descriptor::Dir { .. })
+        | Some(Descriptor::PipeRead(_))
+        | Some(Descriptor::Shm(_))
+        | Some(Descriptor::Channel(_))
+        | None => {
+            Progress::Done(Err(OsError::InvalidArgument))
+        }
+    };
+    if broken_pipe {
+        SCHEDULER.critical(|scheduler| scheduler.find_process(tf).signals.raise(SIGPIPE));
+    }
+    match progress {
+        Progress::Done(result) => set_result(result, tf),
+        Progress::Blocked(event) => block_on(event, tf),
+    }
+}
+
+/// Closes a descriptor. A socket is shut down gracefully in the background.
+///
+/// This system call takes a descriptor as the first parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function returns `OsError::InvalidArgument` if the descriptor is not
+/// open.
+pub fn sys_close(fd: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER
+        .critical(|scheduler| scheduler.find_process(tf).fds.lock().remove(fd))
+        .ok_or(OsError::InvalidArgument)
+        .map(|descriptor| {
+            if let Descriptor::Socket(socket) = descriptor {
+                ETHERNET.critical(|driver| driver.close_user_socket(socket));
+            }
+            0
+        });
+    set_result(result, tf);
+}
+
+/// Creates a pipe.
+///
+/// This system call does not take parameter. Bytes written to the write end
+/// can be read from the read end in order; a read blocks while the pipe is
+/// empty, and returns 0 once every write end is closed.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the descriptor of the read end and that of the write end.
+pub fn sys_pipe(tf: &mut TrapFrame) {
+    let (reader, writer) = Pipe::new();
+    let (read_fd, write_fd) = SCHEDULER.critical(|scheduler| {
+        let mut fds = scheduler.find_process(tf).fds.lock();
+        (fds.insert(Descriptor::PipeRead(reader)), fds.insert(Descriptor::PipeWrite(writer)))
+    });
+    tf.x[0] = read_fd as u64;
+    tf.x[1] = write_fd as u64;
+    tf.x[7] = OsError::Ok as u64;
+}
+
+/// Creates a message channel.
+///
+/// This system call does not take parameter. A channel has two ends; each
+/// receives, in order, the messages sent on the other. A message holds up to
+/// `CHAN_MSG_MAX` bytes and up to `CHAN_FDS_MAX` descriptors. Each direction
+/// holds `CHANNEL_CAPACITY` messages before senders block.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the descriptors of the two ends.
+pub fn sys_chan_create(tf: &mut TrapFrame) {
+    let (first, second) = Channel::new();
+    let (first_fd, second_fd) = SCHEDULER.critical(|scheduler| {
+        let mut fds = scheduler.find_process(tf).fds.lock();
+        (fds.insert(Descriptor::Channel(first)), fds.insert(Descriptor::Channel(second)))
+    });
+    tf.x[0] = first_fd as u64;
+    tf.x[1] = second_fd as u64;
+    tf.x[7] = OsError::Ok as u64;
+}
+
+/// Reads the `count` descriptor numbers, stored as `u64`s, at `va`.
+fn user_fds(va: usize, count: usize, tf: &TrapFrame) -> OsResult<Vec<usize>> {
+    Ok(read_user::<u64>(va, count, tf)?.iter().map(|&fd| fd as usize).collect())
+}
+
+/// Sends a message on a channel.
+///
+/// This system call takes five parameters: the descriptor of a channel end,
+/// the address and length of the message's bytes, and the address and number
+/// of descriptor numbers, stored as `u64`s, to send along. The receiver gets
+/// copies of those descriptors, as `dup` makes. The call blocks while the
+/// other end's queue is full.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The descriptor is not a channel end, the
+///   message is longer than `CHAN_MSG_MAX` bytes or carries more than
+///   `CHAN_FDS_MAX` descriptors, or a descriptor to send is not open, is a
+///   socket, or is an end of the same channel.
+/// - `OsError::BadAddress`: The bytes or the descriptor numbers are not entirely mapped in the process.
+/// - `OsError::BrokenPipe`: Every end of the other side is closed.
+pub fn sys_chan_send(fd: usize, va: usize, len: usize, fds_va: usize, nfds: usize, tf: &mut TrapFrame) {
+    if len > CHAN_MSG_MAX || nfds > CHAN_FDS_MAX {
+        return set_result(Err(OsError::InvalidArgument), tf);
+    }
+    let data = copy_from_user(va, len, tf);
+    let (data, raw_fds) = match data.and_then(|data| Ok((data, user_fds(fds_va, nfds, tf)?))) {
+        Ok(args) => args,
+        Err(e) => return set_result(Err(e), tf),
+    };
+    let progress = SCHEDULER.critical(|scheduler| {
+        let fds = scheduler.find_process(tf).fds.lock();
+        let end = match fds.get(fd) {
+            Some(Descriptor::Channel(end)) => end,
+            _ => return Progress::Done(Err(OsError::InvalidArgument)),
+        };
+        if !end.channel().can_send() {
+            return Progress::Blocked(WaitFor::ChannelSend(end.channel().clone()));
+        }
+        let mut sent = Vec::with_capacity(raw_fds.len());
+        for &raw_fd in raw_fds.iter() {
+            match fds.get(raw_fd).and_then(Descriptor::try_clone) {
+                Some(descriptor) => sent.push(descriptor),
+                None => return Progress::Done(Err(OsError::InvalidArgument)),
+            }
+        }
+        let message = Message { data, fds: sent };
+        match end.send(message) {
+            Ok(None) => Progress::Done(Ok(0)),
+            Ok(Some(_)) => Progress::Blocked(WaitFor::ChannelSend(end.channel().clone())),
+            Err(e) => Progress::Done(Err(e)),
+        }
+    });
+    match progress {
+        Progress::Done(result) => set_result(result, tf),
+        Progress::Blocked(event) => block_on(event, tf),
+    }
+}
+
+/// Receives a message from a channel.
+///
+/// This system call takes five parameters: the descriptor of a channel end,
+/// the address and length of a buffer for the message's bytes, and the address
+/// and number of `u64` slots for the descriptors sent with it. The call blocks
+/// until a message arrives. Received descriptors are opened in the current
+/// process and their numbers stored in the slots.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the length of the message and the number of descriptors
+/// received.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The descriptor is not a channel end, or the
+///   next message does not fit in the buffer or the slots. The message stays
+///   queued.
+/// - `OsError::BadAddress`: The buffer or the slots are not entirely mapped writable in the process.
+/// - `OsError::BrokenPipe`: No message is queued and every end of the other
+///   side is closed.
+pub fn sys_chan_recv(fd: usize, va: usize, len: usize, fds_va: usize, max_fds: usize, tf: &mut TrapFrame) {
+    let max_fds = core::cmp::min(max_fds, CHAN_FDS_MAX);
+    // Check both buffers first so that no message is dequeued and then lost.
+    let checked = check_user(va, len, true, tf)
+        .and_then(|_| check_user(fds_va, max_fds * core::mem::size_of::<u64>(), true, tf));
+    if let Err(e) = checked {
+        return set_result(Err(e), tf);
+    }
+    let mut data = Vec::new();
+    let mut new_fds = Vec::new();
+    let progress = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        let mut fds = process.fds.lock();
+        let message = match fds.get(fd) {
+            Some(Descriptor::Channel(end)) => match end.recv(len, max_fds) {
+                Ok(Some(message)) => message,
+                Ok(None) => return Progress::Blocked(WaitFor::ChannelRecv(end.channel().clone())),
+                Err(e) => return Progress::Done(Err(e)),
+            },
+            _ => return Progress::Done(Err(OsError::InvalidArgument)),
+        };
+        new_fds = message
+            .fds
+            .into_iter()
+            .map(|descriptor| fds.insert(descriptor) as u64)
+            .collect();
+        data = message.data;
+        Progress::Done(Ok(data.len() as u64))
+    });
+    match progress {
+        Progress::Done(result) => {
+            let result = result
+                .and_then(|len| copy_to_user(va, &data, tf).map(|_| len))
+                .and_then(|len| copy_to_user(fds_va, as_bytes(&new_fds), tf).map(|_| len));
+            tf.x[1] = new_fds.len() as u64;
+            set_result(result, tf)
+        }
+        Progress::Blocked(event) => block_on(event, tf),
+    }
+}
+
+/// Closes an end of a channel. Messages still queued for it are dropped once
+/// no copy of the end is open.
+///
+/// This system call takes the descriptor of a channel end as the first
+/// parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function returns `OsError::InvalidArgument` if the descriptor is not
+/// a channel end.
+pub fn sys_chan_close(fd: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| {
+        let mut fds = scheduler.find_process(tf).fds.lock();
+        match fds.get(fd) {
+            Some(Descriptor::Channel(_)) => Ok(fds.remove(fd)),
+            _ => Err(OsError::InvalidArgument),
+        }
+    });
+    // The end is dropped here, outside the scheduler's lock.
+    set_result(result.map(|_| 0), tf);
+}
+
+/// Returns the events `poll` asked for that the descriptor `poll.fd` is
+/// ready for, plus `POLLHUP` and `POLLNVAL`.
+fn poll_revents(fds: &DescriptorTable, poll: &PollFd) -> u16 {
+    let ready = match fds.get(poll.fd as usize) {
+        None => POLLNVAL,
+        Some(Descriptor::Console) => {
+            let input = if CONSOLE.lock().has_byte() { POLLIN } else { 0 };
+            input | POLLOUT
+        }
+        Some(Descriptor::File(_)) | Some(Descriptor::Dir { .. }) => POLLIN | POLLOUT,
+        Some(Descriptor::Socket(UserSocket::Tcp(handle))) => ETHERNET.with_socket(*handle, |socket| {
+            let mut ready = 0;
+            if socket.can_recv() || !socket.may_recv() {
+                ready |= POLLIN;
+            }
+            if socket.can_send() {
+                ready |= POLLOUT;
+            }
+            if socket.is_active() && !socket.may_recv() {
+                ready |= POLLHUP;
+            }
+            ready
+        }),
+        Some(Descriptor::Socket(UserSocket::Udp(handle))) => {
+            ETHERNET.with_udp_socket(*handle, |socket| {
+                let input = if socket.can_recv() { POLLIN } else { 0 };
+                let output = if socket.can_send() { POLLOUT } else { 0 };
+                input | output
+            })
+        }
+        Some(Descriptor::PipeRead(reader)) => {
+            if reader.pipe().is_readable() { POLLIN } else { 0 }
+        }
+        Some(Descriptor::PipeWrite(writer)) => {
+            if writer.pipe().is_writable() { POLLOUT } else { 0 }
+        }
+        Some(Descriptor::Channel(end)) => {
+            let input = if end.channel().can_recv() { POLLIN } else { 0 };
+            let output = if end.channel().can_send() { POLLOUT } else { 0 };
+            input | output
+        }
+        Some(Descriptor::Shm(_)) => 0,
+    };
+    ready & (poll.events | POLLHUP | POLLNVAL)
+}
+
+/// Waits for descriptors to become ready.
+///
+/// This system call takes three parameters: the address and number of
+/// `PollFd`s, each naming a descriptor and the events (`POLLIN`, `POLLOUT`)
+/// to wait for, and a timeout in milliseconds: negative to wait indefinitely,
+/// 0 to only check. The call blocks until a descriptor is ready or the timeout
+/// expires. The `revents` of every `PollFd` is set to its ready events;
+/// `POLLHUP` and `POLLNVAL` are reported even if not asked for. A signal may
+/// end the wait early.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of `PollFd`s with nonzero `revents`, which is 0 if
+/// the timeout expired.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: More than `POLL_FDS_MAX` descriptors are given.
+/// - `OsError::BadAddress`: The `PollFd`s are not entirely mapped writable in the process.
+pub fn sys_poll(va: usize, nfds: usize, timeout: i64, tf: &mut TrapFrame) {
+    use pi::timer::current_time;
+
+    if nfds > POLL_FDS_MAX {
+        return set_result(Err(OsError::InvalidArgument), tf);
+    }
+    let mut polls: Vec<PollFd> = match read_user(va, nfds, tf) {
+        Ok(polls) => polls,
+        Err(e) => return set_result(Err(e), tf),
+    };
+    let ready = SCHEDULER.critical(|scheduler| {
+        let fds = scheduler.find_process(tf).fds.lock();
+        let mut ready = 0;
+        for poll in polls.iter_mut() {
+            poll.revents = poll_revents(&fds, poll);
+            if poll.revents != 0 {
+                ready += 1;
+            }
+        }
+        ready
+    });
+    if ready == 0 && timeout != 0 {
+        let deadline = if timeout > 0 {
+            Some(current_time() + Duration::from_millis(timeout as u64))
+        } else {
+            None
+        };
+        // The call restarts once a descriptor is ready; if the deadline passes
+        // first, `block_on()` zeroes the timeout of the restarted call.
+        return block_on(WaitFor::Poll(polls, deadline), tf);
+    }
+    set_result(copy_to_user(va, as_bytes(&polls), tf).map(|_| ready), tf);
+}
+
+/// Duplicates a descriptor.
+///
+/// This system call takes a descriptor as the first parameter. The copy
+/// refers to the same console or pipe; a file or directory copy has its own
+/// position.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the lowest free descriptor, which now holds the copy.
+///
+/// # Errors
+/// This function returns `OsError::InvalidArgument` if the descriptor is not
+/// open or refers to a socket.
+pub fn sys_dup(fd: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| {
+        let mut fds = scheduler.find_process(tf).fds.lock();
+        let copy = fds
+            .get(fd)
+            .and_then(Descriptor::try_clone)
+            .ok_or(OsError::InvalidArgument)?;
+        Ok(fds.insert(copy) as u64)
+    });
+    set_result(result, tf);
+}
+
+/// Duplicates a descriptor into a given descriptor number.
+///
+/// This system call takes the descriptor to copy as the first parameter and
+/// the descriptor number of the copy as the second parameter. A descriptor
+/// already open at that number is closed first, unless both are the same.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the descriptor number of the copy.
+///
+/// # Errors
+/// This function returns `OsError::InvalidArgument` if the descriptor is not
+/// open or refers to a socket, or if the new descriptor number is not below
+/// `OPEN_MAX`.
+pub fn sys_dup2(fd: usize, new_fd: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| {
+        let mut fds = scheduler.find_process(tf).fds.lock();
+        let copy = fds
+            .get(fd)
+            .and_then(Descriptor::try_clone)
+            .ok_or(OsError::InvalidArgument)?;
+        if fd == new_fd {
+            return Ok(None);
+        }
+        fds.set(new_fd, copy)
+    });
+    let result = result.map(|replaced| {
+        if let Some(Descriptor::Socket(socket)) = replaced {
+            ETHERNET.critical(|driver| driver.close_user_socket(socket));
+        }
+        new_fd as u64
+    });
+    set_result(result, tf);
+}
+
+/// Moves the read position of a file.
+///
+/// This system call takes a descriptor as the first parameter, a signed offset
+/// as the second parameter, and where the offset is relative to as the third
+/// parameter: `SEEK_SET`, `SEEK_CUR` or `SEEK_END`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new position from the start of the file.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The descriptor does not refer to a file, or `whence` is unknown.
+/// - `OsError::IoErrorInvalidInput`: The new position is before the start or past the end of the file.
+pub fn sys_lseek(fd: usize, offset: i64, whence: usize, tf: &mut TrapFrame) {
+    use shim::io::{Seek, SeekFrom};
+
+    let pos = match whence {
+        SEEK_SET => Ok(SeekFrom::Start(offset as u64)),
+        SEEK_CUR => Ok(SeekFrom::Current(offset)),
+        SEEK_END => Ok(SeekFrom::End(offset)),
+        _ => Err(OsError::InvalidArgument),
+    };
+    let result = pos.and_then(|pos| match descriptors(tf).lock().get_mut(fd) {
+        Some(Descriptor::File(file)) => file.seek(pos).map_err(OsError::from),
+        _ => Err(OsError::InvalidArgument),
+    });
+    set_result(result, tf);
+}
+
+/// Returns the metadata of an open file or directory.
+///
+/// This system call takes a descriptor as the first parameter and the address
+/// of a `Stat` structure to fill as the second parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The descriptor does not refer to a file or a directory.
+/// - `OsError::BadAddress`: The address does not point to a `Stat` mapped writable in the process.
+pub fn sys_fstat(fd: usize, va: usize, tf: &mut TrapFrame) {
+    use fat32::traits::{File, Metadata};
+
+    let result = SCHEDULER
+        .critical(|scheduler| {
+            match scheduler.find_process(tf).fds.lock().get(fd) {
+                Some(Descriptor::File(file)) => Ok(Stat {
+                    size: file.size(),
+                    is_dir: false,
+                    read_only: file.metadata.read_only(),
+                    hidden: file.metadata.hidden(),
+                }),
+                Some(Descriptor::Dir { dir, .. }) => Ok(Stat {
+                    size: 0,
+                    is_dir: true,
+                    read_only: dir.metadata.read_only(),
+                    hidden: dir.metadata.hidden(),
+                }),
+                _ => Err(OsError::InvalidArgument),
+            }
+        })
+        .and_then(|stat| copy_to_user(va, as_bytes(&[stat]), tf))
+        .map(|_| 0);
+    set_result(result, tf);
+}
+
+/// Reads entries from an open directory.
+///
+/// This system call takes a directory descriptor as the first parameter, the
+/// address of an array of `DirEntry` as the second parameter, and the length
+/// of the array as the third parameter. Each call continues where the previous
+/// one stopped.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of entries written, which is 0 once every entry has
+/// been read.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The descriptor does not refer to a directory.
+/// - `OsError::BadAddress`: The array is not entirely mapped writable in the process.
+/// - `OsError::IoError`: The file system failed to read the directory.
+pub fn sys_readdir(fd: usize, va: usize, count: usize, tf: &mut TrapFrame) {
+    use fat32::traits::{Dir, Entry};
+
+    let result = count
+        .checked_mul(core::mem::size_of::<DirEntry>())
+        .ok_or(OsError::BadAddress)
+        .and_then(|size| {
+            // Check the array first so that no entry is skipped unread.
+            check_user(va, size, true, tf)?;
+            let dirents = match descriptors(tf).lock().get_mut(fd) {
+                Some(Descriptor::Dir { dir, next }) => {
+                    let entries = dir.entries().map_err(OsError::from)?;
+                    let mut dirents = Vec::new();
+                    for entry in entries.skip(*next).take(count) {
+                        let mut dirent = DirEntry::default();
+                        let name = entry.name().as_bytes();
+                        let name_len = core::cmp::min(name.len(), NAME_MAX);
+                        dirent.name[..name_len].copy_from_slice(&name[..name_len]);
+                        dirent.name_len = name_len as u8;
+                        dirent.stat = entry_stat(&entry);
+                        dirents.push(dirent);
+                    }
+                    *next += dirents.len();
+                    Ok(dirents)
+                }
+                _ => Err(OsError::InvalidArgument),
+            }?;
+            copy_to_user(va, as_bytes(&dirents), tf)?;
+            Ok(dirents.len() as u64)
+        });
+    set_result(result, tf);
+}
+
+/// Returns the metadata of the file or directory at a path.
+///
+/// This system call takes the address of a UTF-8 path as the first parameter,
+/// the length of the path as the second parameter, and the address of a
+/// `Stat` structure to fill as the third parameter. A relative path is
+/// resolved against the current working directory.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The path is not entirely mapped in the process, or the `Stat` is not mapped writable.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded.
+/// - `OsError::NoEntry`: There is no entry at the path.
+pub fn sys_stat(va: usize, len: usize, stat_va: usize, tf: &mut TrapFrame) {
+    use fat32::traits::FileSystem;
+
+    let result = user_path(va, len, tf)
+        .and_then(|path| crate::FILESYSTEM.open(path).map_err(OsError::from))
+        .and_then(|entry| copy_to_user(stat_va, as_bytes(&[entry_stat(&entry)]), tf))
+        .map(|_| 0);
+    set_result(result, tf);
+}
+
+/// Changes the current working directory.
+///
+/// This system call takes the address of a UTF-8 path as the first parameter
+/// and the length of the path as the second parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The path is not entirely mapped in the process.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded or is not a directory.
+/// - `OsError::NoEntry`: There is no entry at the path.
+pub fn sys_chdir(va: usize, len: usize, tf: &mut TrapFrame) {
+    use fat32::traits::{Entry, FileSystem};
+
+    let result = user_path(va, len, tf).and_then(|path| {
+        let entry = crate::FILESYSTEM.open(&path).map_err(OsError::from)?;
+        if !entry.is_dir() {
+            return Err(OsError::InvalidArgument);
+        }
+        SCHEDULER.critical(|scheduler| scheduler.find_process(tf).cwd = path);
+        Ok(0)
+    });
+    set_result(result, tf);
+}
+
+/// Returns the current working directory.
+///
+/// This system call takes the address of a buffer as the first parameter and
+/// the length of the buffer as the second parameter. The path is written
+/// without a terminating NUL.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the length of the path.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The buffer is not entirely mapped writable in the process.
+/// - `OsError::InvalidArgument`: The buffer is too small for the path.
+pub fn sys_getcwd(va: usize, len: usize, tf: &mut TrapFrame) {
+    let cwd = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        String::from(process.cwd.to_str().unwrap_or("/"))
+    });
+    let result = if cwd.len() > len {
+        Err(OsError::InvalidArgument)
+    } else {
+        copy_to_user(va, cwd.as_bytes(), tf).map(|_| cwd.len() as u64)
+    };
+    set_result(result, tf);
+}
@@ -1,8 +1,11 @@
 mod address;
 mod pagetable;
+mod refcount;
+mod shm;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
 pub use self::pagetable::*;
+pub use self::shm::SharedMemory;
 
 use aarch64::*;
 use core::sync::atomic::{AtomicUsize, Ordering};
@@ -4,13 +4,17 @@ use core::slice::Iter;
 
 use alloc::boxed::Box;
 use alloc::fmt;
+use alloc::vec::Vec;
 use core::alloc::{GlobalAlloc, Layout};
 
 use crate::allocator;
 use crate::param::*;
+use crate::vm::refcount;
 use crate::vm::{PhysicalAddr, VirtualAddr};
 use crate::ALLOCATOR;
 
+use kernel_api::{OsError, OsResult};
+
 use aarch64::vmsa::*;
 use shim::const_assert_size;
 
@@ -22,7 +26,7 @@ impl Page {
     pub const SIZE: usize = PAGE_SIZE;
     pub const ALIGN: usize = PAGE_SIZE;
 
-    fn layout() -> Layout {
+    pub fn layout() -> Layout {
         unsafe { Layout::from_size_align_unchecked(Self::SIZE, Self::ALIGN) }
     }
 }
@@ -48,6 +52,12 @@ impl L2PageTable {
     }
 }
 
+/// Value of `RawL3Entry::SW` marking a read-only page shared copy-on-write.
+const SW_COW: u64 = 0b0001;
+/// Value of `RawL3Entry::SW` marking a page of a `SharedMemory` region, which
+/// stays shared, not copied, across `fork()`.
+const SW_SHARED: u64 = 0b0010;
+
 #[derive(Copy, Clone)]
 pub struct L3Entry(RawL3Entry);
 
@@ -192,6 +202,36 @@ impl PageTable {
     pub fn get_baddr(&self) -> PhysicalAddr {
         self.l2.as_ptr()
     }
+
+    /// Resolves a write fault on the copy-on-write page at `va`. If another
+    /// page table still maps the page, the entry is pointed at a private copy;
+    /// either way it becomes writable again.
+    ///
+    /// Returns `false` if `va` is not mapped copy-on-write, in which case the
+    /// fault is a genuine permission violation.
+    pub fn handle_cow_fault(&mut self, va: VirtualAddr) -> bool {
+        let (l2_index, l3_index) = PageTable::locate(va);
+        let entry = &mut self.l3[l2_index].entries[l3_index];
+        if !entry.is_valid() || entry.0.get_value(RawL3Entry::SW) & SW_COW == 0 {
+            return false;
+        }
+        let paddr = entry.get_page_addr().unwrap();
+        if refcount::is_shared(paddr) {
+            let addr = unsafe { ALLOCATOR.alloc(Page::layout()) };
+            if addr == core::ptr::null_mut() {
+                return false;
+            }
+            unsafe { core::ptr::copy_nonoverlapping(paddr.as_usize() as *const u8, addr, PAGE_SIZE) };
+            // The other owners may have gone away since `is_shared()`.
+            if refcount::release(paddr) {
+                unsafe { ALLOCATOR.dealloc(paddr.as_usize() as *mut u8, Page::layout()) };
+            }
+            entry.0.set_value((addr as u64) >> 16, RawL3Entry::ADDR);
+        }
+        entry.0.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        entry.0.set_value(0, RawL3Entry::SW);
+        true
+    }
 }
 
 // Implement `IntoIterator` for `&PageTable`.
@@ -251,10 +291,33 @@ impl KernPageTable {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PagePerm {
     RW,
     RO,
     RWX,
+    RX,
+}
+
+impl PagePerm {
+    /// Returns `true` if user code may write to pages with this permission.
+    pub fn is_writable(self) -> bool {
+        self == PagePerm::RW || self == PagePerm::RWX
+    }
+
+    /// Returns `true` if user code may execute pages with this permission.
+    pub fn is_executable(self) -> bool {
+        self == PagePerm::RWX || self == PagePerm::RX
+    }
+}
+
+/// Sets the access permission and execute-never bits of the user page entry
+/// `entry` for `perm`. The kernel never executes user pages.
+fn set_perm(entry: &mut RawL3Entry, perm: PagePerm) {
+    let ap = if perm.is_writable() { EntryPerm::USER_RW } else { EntryPerm::USER_RO };
+    entry.set_value(ap, RawL3Entry::AP);
+    entry.set_value(!perm.is_executable() as u64, RawL3Entry::UXN);
+    entry.set_value(1, RawL3Entry::PXN);
 }
 
 pub struct UserPageTable(Box<PageTable>);
@@ -268,41 +331,342 @@ impl UserPageTable {
     }
 
     /// Allocates a page and set an L3 entry translates given virtual address to the
-    /// physical address of the allocated page. Returns the allocated page.
+    /// physical address of the allocated page with permission `perm`. Returns
+    /// the allocated page, which the kernel can write whatever `perm` is.
     ///
     /// # Panics
-    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
-    /// Panics if the virtual address has already been allocated.
-    /// Panics if allocator fails to allocate a page.
+    /// Panics if `try_alloc()` fails.
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> &mut [u8] {
+        match self.try_alloc(va, perm) {
+            Ok(page) => page,
+            Err(e) => panic!("Allocating Virtual Address {:?} failed: {:?}", va, e),
+        }
+    }
+
+    /// Allocates a page and maps it at the page-aligned virtual address `va`
+    /// like `alloc()`, but reports failures instead of panicking.
+    ///
+    /// # Errors
     ///
-    /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    /// - `OsError::BadAddress`: `va` is lower than `USER_IMG_BASE`.
+    /// - `OsError::NoVmSpace`: `va` is already mapped.
+    /// - `OsError::NoMemory`: The allocator could not allocate a page.
+    pub fn try_alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> OsResult<&mut [u8]> {
         use core::ops::Sub;
         if va.as_usize() < USER_IMG_BASE {
-            panic!("Virtual Address {:?} is lower than USER_IMG_BASE {:?}", va, USER_IMG_BASE);
+            return Err(OsError::BadAddress);
         }
         if self.is_valid(va.sub(VirtualAddr::from(USER_IMG_BASE))) {
-            panic!("Virtual Address {:?} is already allocated", va);
+            return Err(OsError::NoVmSpace);
         }
         let addr = unsafe {ALLOCATOR.alloc(Page::layout()) };
         if addr == core::ptr::null_mut() {
-            panic!("Allocation failed");
+            return Err(OsError::NoMemory);
         }
         let saddr = (addr as u64)>>16;
         let mut l3_entry = RawL3Entry::new(0);
         l3_entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
         l3_entry.set_value(PageType::Page, RawL3Entry::TYPE);
         l3_entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
-        l3_entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        set_perm(&mut l3_entry, perm);
         l3_entry.set_value(EntrySh::ISh, RawL3Entry::SH);
         l3_entry.set_value(1, RawL3Entry::AF);
         l3_entry.set_value(saddr, RawL3Entry::ADDR);
         self.set_entry(va.sub(VirtualAddr::from(USER_IMG_BASE)), l3_entry);
 
-        unsafe { core::slice::from_raw_parts_mut(addr, PAGE_SIZE) }
+        Ok(unsafe { core::slice::from_raw_parts_mut(addr, PAGE_SIZE) })
+    }
+
+    /// Unmaps the page at the page-aligned virtual address `va` and frees it
+    /// unless a forked page table still maps it. The caller must flush the TLB
+    /// with `aarch64::invalidate_tlb()` before the address space is used again.
+    ///
+    /// Returns `false` if `va` is outside the user space or not mapped.
+    pub fn dealloc(&mut self, va: VirtualAddr) -> bool {
+        if va.as_usize() < USER_IMG_BASE {
+            return false;
+        }
+        let (l2_index, l3_index) = PageTable::locate(va - VirtualAddr::from(USER_IMG_BASE));
+        let entry = &mut self.l3[l2_index].entries[l3_index];
+        let paddr = match entry.get_page_addr() {
+            Some(paddr) => paddr,
+            None => return false,
+        };
+        *entry = L3Entry::new();
+        if refcount::release(paddr) {
+            unsafe { ALLOCATOR.dealloc(paddr.as_usize() as *mut u8, Page::layout()) };
+        }
+        true
+    }
+
+    /// Returns `true` if the page-aligned virtual address `va` is in the user
+    /// space and mapped.
+    pub fn is_mapped(&self, va: VirtualAddr) -> bool {
+        va.as_usize() >= USER_IMG_BASE && self.is_valid(va - VirtualAddr::from(USER_IMG_BASE))
+    }
+
+    /// Maps zeroed pages with permission `perm` over the page-aligned range
+    /// `start..end`.
+    ///
+    /// Nothing is mapped if the range overlaps a mapped page (`NoVmSpace`) or
+    /// a page could not be allocated (`NoMemory`).
+    pub fn map_range(&mut self, start: usize, end: usize, perm: PagePerm) -> OsResult<()> {
+        if start < USER_IMG_BASE || start > end {
+            return Err(OsError::BadAddress);
+        }
+        if (start..end).step_by(PAGE_SIZE).any(|va| self.is_mapped(VirtualAddr::from(va))) {
+            return Err(OsError::NoVmSpace);
+        }
+        for va in (start..end).step_by(PAGE_SIZE) {
+            match self.try_alloc(VirtualAddr::from(va), perm) {
+                Ok(page) => {
+                    for byte in page.iter_mut() {
+                        *byte = 0;
+                    }
+                }
+                Err(e) => {
+                    self.unmap_range(start, va);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps `pages`, the pages of a `SharedMemory` region, at consecutive
+    /// addresses from the page-aligned address `start` with permission `perm`.
+    /// Each mapping holds a reference to its page, which `dealloc()` and
+    /// dropping the table release.
+    ///
+    /// Nothing is mapped if the range is outside the user space (`BadAddress`)
+    /// or overlaps a mapped page (`NoVmSpace`).
+    pub fn map_shared(&mut self, start: usize, pages: &[PhysicalAddr], perm: PagePerm) -> OsResult<()> {
+        let end = pages
+            .len()
+            .checked_mul(PAGE_SIZE)
+            .and_then(|size| start.checked_add(size))
+            .ok_or(OsError::BadAddress)?;
+        if start < USER_IMG_BASE || end > USER_STACK_GUARD {
+            return Err(OsError::BadAddress);
+        }
+        if (start..end).step_by(PAGE_SIZE).any(|va| self.is_mapped(VirtualAddr::from(va))) {
+            return Err(OsError::NoVmSpace);
+        }
+        for (va, &paddr) in (start..end).step_by(PAGE_SIZE).zip(pages.iter()) {
+            let mut l3_entry = RawL3Entry::new(0);
+            l3_entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
+            l3_entry.set_value(PageType::Page, RawL3Entry::TYPE);
+            l3_entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
+            set_perm(&mut l3_entry, perm);
+            l3_entry.set_value(EntrySh::ISh, RawL3Entry::SH);
+            l3_entry.set_value(1, RawL3Entry::AF);
+            l3_entry.set_value(SW_SHARED, RawL3Entry::SW);
+            l3_entry.set_value(paddr.as_u64() >> 16, RawL3Entry::ADDR);
+            refcount::share(paddr);
+            self.set_entry(VirtualAddr::from(va) - VirtualAddr::from(USER_IMG_BASE), l3_entry);
+        }
+        Ok(())
+    }
+
+    /// Changes the permission of the page mapped at the page-aligned virtual
+    /// address `va` to `perm`. A page that another page table also maps stays
+    /// read-only and copy-on-write if `perm` is writable, unless it belongs to
+    /// a `SharedMemory` region. The caller must flush the TLB with
+    /// `aarch64::invalidate_tlb()` before the address space is used again.
+    ///
+    /// Returns `false` if `va` is outside the user space or not mapped.
+    pub fn protect(&mut self, va: VirtualAddr, perm: PagePerm) -> bool {
+        if va.as_usize() < USER_IMG_BASE {
+            return false;
+        }
+        let (l2_index, l3_index) = PageTable::locate(va - VirtualAddr::from(USER_IMG_BASE));
+        let entry = &mut self.l3[l2_index].entries[l3_index];
+        let paddr = match entry.get_page_addr() {
+            Some(paddr) => paddr,
+            None => return false,
+        };
+        set_perm(&mut entry.0, perm);
+        if entry.0.get_value(RawL3Entry::SW) != SW_SHARED {
+            if perm.is_writable() && refcount::is_shared(paddr) {
+                entry.0.set_value(EntryPerm::USER_RO, RawL3Entry::AP);
+                entry.0.set_value(SW_COW, RawL3Entry::SW);
+            } else {
+                entry.0.set_value(0, RawL3Entry::SW);
+            }
+        }
+        true
+    }
 
+    /// Unmaps every mapped page in the page-aligned range `start..end` with
+    /// `dealloc()`. The caller must flush the TLB afterwards.
+    pub fn unmap_range(&mut self, start: usize, end: usize) {
+        for va in (start..end).step_by(PAGE_SIZE) {
+            self.dealloc(VirtualAddr::from(va));
+        }
+    }
+
+    /// Returns the start of the highest run of `size` bytes of unmapped pages
+    /// within the page-aligned range `low..high`, or `None` if there is none.
+    pub fn find_free(&self, size: usize, low: usize, high: usize) -> Option<usize> {
+        let mut end = high;
+        let mut va = high;
+        while va > low {
+            va -= PAGE_SIZE;
+            if self.is_mapped(VirtualAddr::from(va)) {
+                end = va;
+            } else if end - va == size {
+                return Some(va);
+            }
+        }
+        None
+    }
 
+    /// Returns a new `UserPageTable` mapping the same pages as this one.
+    ///
+    /// Writable pages are not copied: they become read-only copy-on-write
+    /// pages in both tables, and the first write to one gets a private copy
+    /// in `handle_cow_fault()`, which keeps its execute permission. Pages of
+    /// a `SharedMemory` region stay writable and shared. The caller must flush
+    /// the TLB before this table is used again.
+    pub fn fork(&mut self) -> UserPageTable {
+        let mut child = UserPageTable::new();
+        for (l2_index, table) in self.l3.iter_mut().enumerate() {
+            for (l3_index, entry) in table.entries.iter_mut().enumerate() {
+                let paddr = match entry.get_page_addr() {
+                    Some(paddr) => paddr,
+                    None => continue,
+                };
+                if entry.0.get_value(RawL3Entry::AP) == EntryPerm::USER_RW
+                    && entry.0.get_value(RawL3Entry::SW) != SW_SHARED
+                {
+                    entry.0.set_value(EntryPerm::USER_RO, RawL3Entry::AP);
+                    entry.0.set_value(SW_COW, RawL3Entry::SW);
+                }
+                refcount::share(paddr);
+                child.l3[l2_index].entries[l3_index] = *entry;
+            }
+        }
+        child
+    }
+
+    /// Returns the page mapped at the page-aligned virtual address `va`, or
+    /// `None` if `va` is outside the user space or not mapped.
+    pub fn get_page(&mut self, va: VirtualAddr) -> Option<&mut [u8]> {
+        if va.as_usize() < USER_IMG_BASE {
+            return None;
+        }
+        let (l2_index, l3_index) = PageTable::locate(va - VirtualAddr::from(USER_IMG_BASE));
+        let paddr = self.l3[l2_index].entries[l3_index].get_page_addr()?;
+        Some(unsafe { core::slice::from_raw_parts_mut(paddr.as_usize() as *mut u8, PAGE_SIZE) })
+    }
+
+    /// Returns the valid entry mapping the page that contains the user address
+    /// `va`, or `None` if `va` is outside the user space or not mapped.
+    fn entry(&self, va: usize) -> Option<&L3Entry> {
+        if va < USER_IMG_BASE {
+            return None;
+        }
+        let (l2_index, l3_index) = PageTable::locate(VirtualAddr::from((va & PAGE_MASK) - USER_IMG_BASE));
+        let entry = &self.l3[l2_index].entries[l3_index];
+        if entry.is_valid() {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    /// Translates the user address `va` to the physical address it maps to,
+    /// and returns it with the permission of its page. A copy-on-write page
+    /// reports `RO`, or `RX` if it is executable. Returns `None` if `va` is
+    /// outside the user space or not mapped.
+    pub fn translate(&self, va: VirtualAddr) -> Option<(PhysicalAddr, PagePerm)> {
+        let entry = self.entry(va.as_usize())?;
+        let page = entry.get_page_addr()?;
+        let writable = entry.0.get_value(RawL3Entry::AP) == EntryPerm::USER_RW;
+        let perm = match (writable, entry.0.get_value(RawL3Entry::UXN) == 0) {
+            (true, true) => PagePerm::RWX,
+            (true, false) => PagePerm::RW,
+            (false, true) => PagePerm::RX,
+            (false, false) => PagePerm::RO,
+        };
+        Some((PhysicalAddr::from(page.as_usize() + va.as_usize() % PAGE_SIZE), perm))
+    }
+
+    /// Checks that every page overlapping `len` bytes at the user address
+    /// `va` is mapped, and writable or copy-on-write if `write` is set. An
+    /// empty range is always valid.
+    ///
+    /// Returns `BadAddress` otherwise.
+    pub fn validate(&self, va: usize, len: usize, write: bool) -> OsResult<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let end = va.checked_add(len).ok_or(OsError::BadAddress)?;
+        let mut page = va & PAGE_MASK;
+        while page < end {
+            let entry = self.entry(page).ok_or(OsError::BadAddress)?;
+            let writable = entry.0.get_value(RawL3Entry::AP) == EntryPerm::USER_RW
+                || entry.0.get_value(RawL3Entry::SW) & SW_COW != 0;
+            if write && !writable {
+                return Err(OsError::BadAddress);
+            }
+            page = match page.checked_add(PAGE_SIZE) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        Ok(())
+    }
+
+    /// Copies the `len` bytes at the user address `va` into a new buffer.
+    /// The bytes are read through the kernel's mapping of their pages, so a
+    /// bad address never faults in the kernel.
+    ///
+    /// Returns `BadAddress` if a page of the range is not mapped.
+    pub fn copy_from_user(&self, va: usize, len: usize) -> OsResult<Vec<u8>> {
+        self.validate(va, len, false)?;
+        let mut buf = Vec::with_capacity(len);
+        let mut addr = va;
+        while buf.len() < len {
+            let (paddr, _) = self.translate(VirtualAddr::from(addr)).ok_or(OsError::BadAddress)?;
+            let count = core::cmp::min(PAGE_SIZE - addr % PAGE_SIZE, len - buf.len());
+            let bytes = unsafe { core::slice::from_raw_parts(paddr.as_usize() as *const u8, count) };
+            buf.extend_from_slice(bytes);
+            addr = addr.wrapping_add(count);
+        }
+        Ok(buf)
+    }
+
+    /// Copies `buf` to the user address `va` through the kernel's mapping of
+    /// the pages. Copy-on-write pages in the range get their private copy
+    /// first.
+    ///
+    /// Returns `BadAddress`, writing nothing, if a page of the range is not
+    /// mapped or is read-only, and `NoMemory` if a private copy could not be
+    /// made.
+    pub fn copy_to_user(&mut self, va: usize, buf: &[u8]) -> OsResult<()> {
+        self.validate(va, buf.len(), true)?;
+        let mut done = 0;
+        let mut addr = va;
+        while done < buf.len() {
+            let paddr = match self.translate(VirtualAddr::from(addr)) {
+                Some((paddr, perm)) if perm.is_writable() => paddr,
+                _ => {
+                    let page = VirtualAddr::from((addr & PAGE_MASK) - USER_IMG_BASE);
+                    if !self.handle_cow_fault(page) {
+                        return Err(OsError::NoMemory);
+                    }
+                    self.translate(VirtualAddr::from(addr)).ok_or(OsError::BadAddress)?.0
+                }
+            };
+            let count = core::cmp::min(PAGE_SIZE - addr % PAGE_SIZE, buf.len() - done);
+            unsafe {
+                core::ptr::copy_nonoverlapping(buf[done..].as_ptr(), paddr.as_usize() as *mut u8, count);
+            }
+            done += count;
+            addr = addr.wrapping_add(count);
+        }
+        Ok(())
     }
 }
 
@@ -338,11 +702,11 @@ impl DerefMut for UserPageTable {
 impl Drop for UserPageTable {
     fn drop(&mut self) {
         for l3_entry in self.into_iter() {
-            if l3_entry.0.get_value(EntryValid::Valid) == RawL3Entry::VALID {
-                let addr = l3_entry.0.get_value(RawL3Entry::ADDR) ;
-                let addr = addr<<16;
-                let addr = addr as *mut u8;
-                unsafe {ALLOCATOR.dealloc(addr, Page::layout()) };
+            if let Some(paddr) = l3_entry.get_page_addr() {
+                // Pages shared with a forked process stay until their last owner drops them.
+                if refcount::release(paddr) {
+                    unsafe {ALLOCATOR.dealloc(paddr.as_usize() as *mut u8, Page::layout()) };
+                }
             }
         }
     }
@@ -0,0 +1,89 @@
+use alloc::vec::Vec;
+
+use crate::mutex::Mutex;
+use crate::vm::PhysicalAddr;
+
+/// Number of page tables mapping each shared physical page, sorted by
+/// address. A page that is not listed has exactly one owner, so only pages
+/// shared by `fork()` cost an entry.
+static SHARED_PAGES: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+/// Records one more page table mapping the page at `paddr`.
+pub fn share(paddr: PhysicalAddr) {
+    let mut pages = SHARED_PAGES.lock();
+    match pages.binary_search_by_key(&paddr.as_usize(), |&(addr, _)| addr) {
+        Ok(idx) => pages[idx].1 += 1,
+        Err(idx) => pages.insert(idx, (paddr.as_usize(), 2)),
+    }
+}
+
+/// Drops one page table's reference to the page at `paddr`. Returns `true`
+/// if that was the last reference and the page should be freed.
+pub fn release(paddr: PhysicalAddr) -> bool {
+    let mut pages = SHARED_PAGES.lock();
+    match pages.binary_search_by_key(&paddr.as_usize(), |&(addr, _)| addr) {
+        Ok(idx) => {
+            pages[idx].1 -= 1;
+            if pages[idx].1 == 1 {
+                pages.remove(idx);
+            }
+            false
+        }
+        Err(_) => true,
+    }
+}
+
+/// Returns `true` if more than one page table maps the page at `paddr`.
+pub fn is_shared(paddr: PhysicalAddr) -> bool {
+    let pages = SHARED_PAGES.lock();
+    pages
+        .binary_search_by_key(&paddr.as_usize(), |&(addr, _)| addr)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_shared, release, share};
+    use crate::vm::PhysicalAddr;
+
+    // The tests share `SHARED_PAGES`, so each uses its own pages.
+
+    #[test]
+    fn test_unshared_page() {
+        let page = PhysicalAddr::from(0x10_0000);
+        assert!(!is_shared(page));
+        assert!(release(page));
+    }
+
+    #[test]
+    fn test_last_owner_frees() {
+        let page = PhysicalAddr::from(0x20_0000);
+        share(page);
+        share(page);
+        assert!(is_shared(page));
+        assert!(!release(page));
+        assert!(is_shared(page));
+        assert!(!release(page));
+        assert!(!is_shared(page));
+        assert!(release(page));
+    }
+
+    #[test]
+    fn test_pages_counted_separately() {
+        const PAGES: [usize; 3] = [0x32_0000, 0x30_0000, 0x31_0000];
+        for &page in PAGES.iter() {
+            share(PhysicalAddr::from(page));
+        }
+        share(PhysicalAddr::from(PAGES[1]));
+
+        assert!(!release(PhysicalAddr::from(PAGES[0])));
+        assert!(!is_shared(PhysicalAddr::from(PAGES[0])));
+        assert!(is_shared(PhysicalAddr::from(PAGES[1])));
+        assert!(is_shared(PhysicalAddr::from(PAGES[2])));
+        assert!(!release(PhysicalAddr::from(PAGES[1])));
+        assert!(!release(PhysicalAddr::from(PAGES[1])));
+        assert!(!is_shared(PhysicalAddr::from(PAGES[1])));
+        assert!(!release(PhysicalAddr::from(PAGES[2])));
+        assert!(release(PhysicalAddr::from(PAGES[2])));
+    }
+}
@@ -0,0 +1,66 @@
+use alloc::vec::Vec;
+use core::alloc::GlobalAlloc;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::param::*;
+use crate::vm::{refcount, Page, PhysicalAddr};
+use crate::ALLOCATOR;
+
+/// Zeroed physical pages that several user page tables can map at once.
+///
+/// The region holds one reference to each of its pages and every mapping made
+/// with `UserPageTable::map_shared()` holds another, so a page is freed only
+/// when the region and all its mappings are gone.
+#[derive(Debug)]
+pub struct SharedMemory {
+    pages: Vec<PhysicalAddr>,
+}
+
+impl SharedMemory {
+    /// Allocates a region of `size` bytes rounded up to whole pages.
+    ///
+    /// Returns `InvalidArgument` for an empty region or one larger than the
+    /// user address space, and `NoMemory` if the pages could not be
+    /// allocated.
+    pub fn new(size: usize) -> OsResult<SharedMemory> {
+        if size == 0 || size > USER_MAX_VM_SIZE {
+            return Err(OsError::InvalidArgument);
+        }
+        let count = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut shm = SharedMemory {
+            pages: Vec::with_capacity(count),
+        };
+        for _ in 0..count {
+            let addr = unsafe { ALLOCATOR.alloc(Page::layout()) };
+            if addr == core::ptr::null_mut() {
+                // Dropping `shm` frees the pages allocated so far.
+                return Err(OsError::NoMemory);
+            }
+            unsafe { core::ptr::write_bytes(addr, 0, PAGE_SIZE) };
+            shm.pages.push(PhysicalAddr::from(addr as u64));
+        }
+        Ok(shm)
+    }
+
+    /// Returns the size of the region in bytes.
+    pub fn size(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+
+    /// Returns the physical pages of the region in order.
+    pub fn pages(&self) -> &[PhysicalAddr] {
+        &self.pages
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        for &paddr in self.pages.iter() {
+            // Pages still mapped by a process stay until it unmaps them.
+            if refcount::release(paddr) {
+                unsafe { ALLOCATOR.dealloc(paddr.as_usize() as *mut u8, Page::layout()) };
+            }
+        }
+    }
+}
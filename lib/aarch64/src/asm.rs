@@ -28,6 +28,18 @@ pub fn isb() {
     unsafe { asm!("isb" :::: "volatile") };
 }
 
+/// Invalidates the EL1&0 TLB entries of every core in the inner shareable
+/// domain, after the page table writes before it are visible to the walkers.
+#[inline(always)]
+pub fn invalidate_tlb() {
+    unsafe {
+        asm!("dsb ishst
+              tlbi vmalle1is
+              dsb ish
+              isb" :::: "volatile");
+    }
+}
+
 /// Set Event
 #[inline(always)]
 pub fn sev() {
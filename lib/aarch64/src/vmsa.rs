@@ -61,6 +61,9 @@ defbit!(
 defbit!(
     RawL3Entry,
     [
+        SW[58 - 55],
+        UXN[54 - 54],
+        PXN[53 - 53],
         ADDR[47 - 16],
         AF[10 - 10],
         SH[09 - 08],
@@ -202,6 +205,19 @@ impl fmt::Debug for RawL3Entry {
             }
         )?;
 
+        write!(
+            f,
+            "|{}{}",
+            match self.get_value(RawL3Entry::UXN) {
+                0 => "UX",
+                _ => "--",
+            },
+            match self.get_value(RawL3Entry::PXN) {
+                0 => "PX",
+                _ => "--",
+            }
+        )?;
+
         // NS    [05-05],
 
         write!(
@@ -6,7 +6,7 @@ use shim::io::{self, SeekFrom};
 use crate::traits;
 use crate::vfat::{Cluster, Metadata, VFatHandle,VFat};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct File<HANDLE: VFatHandle> {
     pub vfat: HANDLE,
     pub first_cluster: Cluster,
@@ -32,10 +32,10 @@ impl<HANDLE:VFatHandle> traits::File for File<HANDLE> {
 
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE> {
     fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-        panic!("Dummy")
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only file system"))
     }
     fn flush(&mut self) -> io::Result<()> {
-        panic!("Dummy")
+        Ok(())
     }
 }
 
@@ -76,17 +76,16 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     ///
     /// Seeking before the start of a file or beyond the end of the file results
     /// in an `InvalidInput` error.
-    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
-        match _pos {
-            SeekFrom::Start(s) => {
-                if s< self.file_size {
-                    return Ok(s);
-                } else {
-                    return  Err(io::Error::new(io::ErrorKind::InvalidInput,"seek beyond end "));
-                }
-            },
-            _ =>  return  Err(io::Error::new(io::ErrorKind::InvalidInput,"seek beyond end ")),
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(s) => s as i64,
+            SeekFrom::End(o) => self.file_size as i64 + o,
+            SeekFrom::Current(o) => self.file_offset as i64 + o,
+        };
+        if offset < 0 || offset as u64 > self.file_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek beyond end"));
         }
-
+        self.file_offset = offset as usize;
+        Ok(offset as u64)
     }
 }
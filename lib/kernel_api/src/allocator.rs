@@ -0,0 +1,126 @@
+//! The global allocator of user programs, so they can use `alloc::vec::Vec`,
+//! `alloc::string::String` and the other `alloc` collections.
+//!
+//! Blocks of up to `MAX_BIN_SIZE` bytes come from power-of-two size classes
+//! carved out of heap pages taken with `brk`; a freed block goes back on the
+//! free list of its class. Larger blocks are mapped with `mmap` and returned
+//! to the kernel with `munmap`.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::syscall::{brk, exit, mmap, munmap};
+use crate::{PAGE_SIZE, PERM_RW};
+
+const MIN_BIN_SIZE: usize = 16;
+const NUM_BINS: usize = 12;
+/// Largest block served from a size class (32 KiB).
+const MAX_BIN_SIZE: usize = MIN_BIN_SIZE << (NUM_BINS - 1);
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
+
+#[alloc_error_handler]
+fn oom(layout: Layout) -> ! {
+    crate::println!("out of memory allocating {} bytes", layout.size());
+    exit(1);
+}
+
+/// Returns the size class of `layout`, or `None` if it is mapped directly.
+/// Blocks of a class are naturally aligned to their size.
+fn bin_index(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(MIN_BIN_SIZE);
+    if size > MAX_BIN_SIZE {
+        return None;
+    }
+    let bin_size = size.next_power_of_two();
+    Some((bin_size / MIN_BIN_SIZE).trailing_zeros() as usize)
+}
+
+/// Returns the size of the mapping that holds `layout` outside the size
+/// classes.
+fn mapped_size(layout: Layout) -> usize {
+    (layout.size() + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// A size class allocator over the process heap, locked with a spinlock.
+pub struct Allocator {
+    locked: AtomicBool,
+    /// Head of the free list of each size class. A free block holds the
+    /// address of the next one.
+    bins: UnsafeCell<[*mut usize; NUM_BINS]>,
+}
+
+unsafe impl Sync for Allocator {}
+
+impl Allocator {
+    const fn new() -> Allocator {
+        Allocator {
+            locked: AtomicBool::new(false),
+            bins: UnsafeCell::new([ptr::null_mut(); NUM_BINS]),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the free lists.
+    fn critical<R, F: FnOnce(&mut [*mut usize; NUM_BINS]) -> R>(&self, f: F) -> R {
+        while self.locked.compare_and_swap(false, true, Ordering::Acquire) {}
+        let result = f(unsafe { &mut *self.bins.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+/// Takes a page from the heap and splits it into blocks of size class `bin`,
+/// which become the class's free list. Returns `false` if the heap cannot
+/// grow.
+unsafe fn refill(bins: &mut [*mut usize; NUM_BINS], bin: usize) -> bool {
+    // `sbrk()` may have been called with sizes that are not whole pages.
+    let page = match brk(0) {
+        Ok(end) => (end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+        Err(_) => return false,
+    };
+    if brk(page + PAGE_SIZE).is_err() {
+        return false;
+    }
+    let bin_size = MIN_BIN_SIZE << bin;
+    for block in (page..page + PAGE_SIZE).step_by(bin_size) {
+        let block = block as *mut usize;
+        *block = bins[bin] as usize;
+        bins[bin] = block;
+    }
+    true
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match bin_index(layout) {
+            Some(bin) => self.critical(|bins| {
+                if bins[bin].is_null() && !refill(bins, bin) {
+                    return ptr::null_mut();
+                }
+                let block = bins[bin];
+                bins[bin] = *block as *mut usize;
+                block as *mut u8
+            }),
+            None if layout.align() <= PAGE_SIZE => {
+                mmap(mapped_size(layout), PERM_RW).unwrap_or(ptr::null_mut())
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match bin_index(layout) {
+            Some(bin) => self.critical(|bins| {
+                let block = ptr as *mut usize;
+                *block = bins[bin] as usize;
+                bins[bin] = block;
+            }),
+            None => {
+                let _ = munmap(ptr, mapped_size(layout));
+            }
+        }
+    }
+}
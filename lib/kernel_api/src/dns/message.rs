@@ -0,0 +1,231 @@
+//! Building DNS queries and parsing the answers.
+
+// Only the resolver uses these outside of tests.
+#![cfg_attr(not(feature = "user-space"), allow(dead_code))]
+
+use crate::{IpAddr, OsError, OsResult};
+
+/// Longest name that can be resolved.
+pub(super) const NAME_MAX: usize = 253;
+
+/// Largest DNS message over UDP.
+pub(super) const MESSAGE_MAX: usize = 512;
+
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+/// Writes a recursive `A` query for `name` into `buf` and returns its length.
+pub(super) fn build_query(name: &str, id: u16, buf: &mut [u8; MESSAGE_MAX]) -> OsResult<usize> {
+    // Header: ID, flags with RD set, one question, no other records.
+    buf[0..2].copy_from_slice(&id.to_be_bytes());
+    buf[2..4].copy_from_slice(&0x0100u16.to_be_bytes());
+    buf[4..6].copy_from_slice(&1u16.to_be_bytes());
+    for byte in buf[6..12].iter_mut() {
+        *byte = 0;
+    }
+
+    let mut pos = 12;
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(OsError::InvalidArgument);
+        }
+        buf[pos] = label.len() as u8;
+        buf[pos + 1..pos + 1 + label.len()].copy_from_slice(label.as_bytes());
+        pos += 1 + label.len();
+    }
+    buf[pos] = 0;
+    pos += 1;
+
+    buf[pos..pos + 2].copy_from_slice(&TYPE_A.to_be_bytes());
+    buf[pos + 2..pos + 4].copy_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(pos + 4)
+}
+
+/// Parses a response to the query with ID `id`. Returns `Ok(None)` if the
+/// message answers some other query.
+pub(super) fn parse_response(msg: &[u8], id: u16) -> OsResult<Option<(IpAddr, u32)>> {
+    if msg.len() < 12 || read_u16(msg, 0)? != id {
+        return Ok(None);
+    }
+    let flags = read_u16(msg, 2)?;
+    if flags & 0x8000 == 0 {
+        return Ok(None);
+    }
+    match flags & 0x000f {
+        0 => {}
+        3 => return Err(OsError::NoEntry),
+        _ => return Err(OsError::IoError),
+    }
+
+    let questions = read_u16(msg, 4)?;
+    let answers = read_u16(msg, 6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(msg, pos)? + 4;
+    }
+
+    // CNAME records come before the address they point to, so the first A
+    // record is the answer.
+    for _ in 0..answers {
+        pos = skip_name(msg, pos)?;
+        let rtype = read_u16(msg, pos)?;
+        let class = read_u16(msg, pos + 2)?;
+        let ttl = (read_u16(msg, pos + 4)? as u32) << 16 | read_u16(msg, pos + 6)? as u32;
+        let rdlen = read_u16(msg, pos + 8)? as usize;
+        let rdata = msg
+            .get(pos + 10..pos + 10 + rdlen)
+            .ok_or(OsError::IoErrorInvalidData)?;
+        if rtype == TYPE_A && class == CLASS_IN && rdlen == 4 {
+            let addr = IpAddr::new((rdata[0], rdata[1], rdata[2], rdata[3]), 0);
+            return Ok(Some((addr, ttl)));
+        }
+        pos += 10 + rdlen;
+    }
+    Err(OsError::NoEntry)
+}
+
+/// Returns the position just past the (possibly compressed) name at `pos`.
+fn skip_name(msg: &[u8], mut pos: usize) -> OsResult<usize> {
+    loop {
+        let len = *msg.get(pos).ok_or(OsError::IoErrorInvalidData)? as usize;
+        match len {
+            0 => return Ok(pos + 1),
+            // A compression pointer ends the name.
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len if len & 0xc0 == 0 => pos += 1 + len,
+            _ => return Err(OsError::IoErrorInvalidData),
+        }
+    }
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> OsResult<u16> {
+    match msg.get(pos..pos + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(OsError::IoErrorInvalidData),
+    }
+}
+
+/// Parses a dotted-quad address such as `10.0.0.1`.
+pub(super) fn parse_ipv4(s: &str) -> Option<IpAddr> {
+    let mut octets = [0u8; 4];
+    let mut parts = s.split('.');
+    for octet in octets.iter_mut() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(IpAddr::new((octets[0], octets[1], octets[2], octets[3]), 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_query, parse_ipv4, parse_response, skip_name, MESSAGE_MAX};
+    use crate::{IpAddr, OsError};
+
+    /// An `A` query for `example.com` with ID 0xbeef.
+    #[rustfmt::skip]
+    const QUERY: [u8; 29] = [
+        0xbe, 0xef, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0,
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+        0, 1, 0, 1,
+    ];
+
+    /// The answer to query 7 for `example.com`: a CNAME to `www` plus the
+    /// question's name, then the `A` record of that name. Both answers name
+    /// their owner with a compression pointer.
+    #[rustfmt::skip]
+    const RESPONSE: [u8; 63] = [
+        0, 7, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0,
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+        0, 1, 0, 1,
+        0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6, 3, b'w', b'w', b'w', 0xc0, 12,
+        0xc0, 41, 0, 1, 0, 1, 0, 1, 0, 0, 0, 4, 93, 184, 216, 34,
+    ];
+
+    #[test]
+    fn test_build_query() {
+        let mut buf = [0u8; MESSAGE_MAX];
+        assert_eq!(
+            build_query("example.com", 0xbeef, &mut buf),
+            Ok(QUERY.len())
+        );
+        assert_eq!(&buf[..QUERY.len()], &QUERY[..]);
+
+        assert_eq!(
+            build_query("a..b", 1, &mut buf),
+            Err(OsError::InvalidArgument)
+        );
+        let long = core::str::from_utf8(&[b'a'; 64]).unwrap();
+        assert_eq!(
+            build_query(long, 1, &mut buf),
+            Err(OsError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(
+            parse_response(&RESPONSE, 7),
+            Ok(Some((IpAddr::new((93, 184, 216, 34), 0), 65536)))
+        );
+
+        // Other IDs, short messages and queries answer something else.
+        assert_eq!(parse_response(&RESPONSE, 8), Ok(None));
+        assert_eq!(parse_response(&RESPONSE[..11], 7), Ok(None));
+        let mut msg = RESPONSE;
+        msg[2] = 0x01;
+        assert_eq!(parse_response(&msg, 7), Ok(None));
+
+        let mut msg = RESPONSE;
+        msg[3] = 0x83;
+        assert_eq!(parse_response(&msg, 7), Err(OsError::NoEntry));
+        msg[3] = 0x82;
+        assert_eq!(parse_response(&msg, 7), Err(OsError::IoError));
+
+        assert_eq!(
+            parse_response(&RESPONSE[..61], 7),
+            Err(OsError::IoErrorInvalidData)
+        );
+        assert_eq!(
+            parse_response(&RESPONSE[..20], 7),
+            Err(OsError::IoErrorInvalidData)
+        );
+    }
+
+    #[test]
+    fn test_compression_pointers_are_not_followed() {
+        // A pointer to itself and a pointer loop end the name at once.
+        assert_eq!(skip_name(&[0xc0, 0], 0), Ok(2));
+        assert_eq!(skip_name(&[1, b'a', 0xc0, 0, 0xc0, 2], 0), Ok(4));
+        // Reserved label types and names running off the end are malformed.
+        assert_eq!(skip_name(&[0x40, 0], 0), Err(OsError::IoErrorInvalidData));
+        assert_eq!(
+            skip_name(&[3, b'a', b'b'], 0),
+            Err(OsError::IoErrorInvalidData)
+        );
+
+        // An answer whose name points at itself parses without looping.
+        #[rustfmt::skip]
+        let msg = [
+            0, 9, 0x81, 0x80, 0, 0, 0, 1, 0, 0, 0, 0,
+            0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 5, 0, 4, 10, 0, 0, 1,
+        ];
+        assert_eq!(
+            parse_response(&msg, 9),
+            Ok(Some((IpAddr::new((10, 0, 0, 1), 0), 5)))
+        );
+    }
+
+    #[test]
+    fn test_parse_ipv4() {
+        assert_eq!(
+            parse_ipv4("10.0.2.15"),
+            Some(IpAddr::new((10, 0, 2, 15), 0))
+        );
+        assert_eq!(parse_ipv4("10.0.2"), None);
+        assert_eq!(parse_ipv4("10.0.2.15.1"), None);
+        assert_eq!(parse_ipv4("10.0.2.256"), None);
+        assert_eq!(parse_ipv4("example.com"), None);
+    }
+}
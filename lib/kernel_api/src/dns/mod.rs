@@ -0,0 +1,18 @@
+//! A stub DNS resolver (RFC 1035) for user programs.
+//!
+//! Names are resolved with recursive `A` queries over UDP to the server
+//! configured in the kernel, or to the one set with `set_server()`. Answers
+//! are cached until their TTL expires.
+//!
+//! The message format lives in `message`, which does not make system calls
+//! and so is built, and tested, without the `user-space` feature.
+
+mod message;
+#[cfg(feature = "user-space")]
+mod resolver;
+
+#[cfg(feature = "user-space")]
+pub use self::resolver::{resolve, set_server};
+
+/// The well-known DNS server port.
+pub const DNS_PORT: u16 = 53;
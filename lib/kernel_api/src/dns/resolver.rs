@@ -0,0 +1,172 @@
+//! Resolving names over UDP, with a cache of the answers.
+
+use core::time::Duration;
+
+use super::message::{build_query, parse_ipv4, parse_response, MESSAGE_MAX, NAME_MAX};
+use super::DNS_PORT;
+use crate::sync::Mutex;
+use crate::syscall::{dns_server, sleep, sock_close, time, udp_bind, udp_recv_from, udp_send_to};
+use crate::{IpAddr, OsError, OsResult, SocketDescriptor};
+
+/// Number of names kept in the cache.
+const CACHE_SIZE: usize = 8;
+
+/// How long to wait for an answer before asking again.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How many times a query is sent before giving up.
+const ATTEMPTS: usize = 3;
+
+#[derive(Clone, Copy)]
+struct CacheEntry {
+    name: [u8; NAME_MAX],
+    name_len: usize,
+    addr: IpAddr,
+    expires: Duration,
+}
+
+struct Resolver {
+    server: Option<IpAddr>,
+    cache: [Option<CacheEntry>; CACHE_SIZE],
+    next_id: u16,
+}
+
+static RESOLVER: Mutex<Resolver> = Mutex::new(Resolver {
+    server: None,
+    cache: [None; CACHE_SIZE],
+    next_id: 0,
+});
+
+/// Uses `server` instead of the kernel's DNS server. A port of 0 means
+/// `DNS_PORT`.
+pub fn set_server(server: IpAddr) {
+    let server = match server.port {
+        0 => server.with_port(DNS_PORT),
+        _ => server,
+    };
+    RESOLVER.lock().server = Some(server);
+}
+
+/// Resolves `name` to an IPv4 address. The port of the returned address is 0.
+///
+/// A dotted-quad `name` is returned as is without a query.
+///
+/// # Errors
+///
+/// - `OsError::InvalidArgument`: `name` is not a valid host name.
+/// - `OsError::NoEntry`: No DNS server is configured, or the name does not exist.
+/// - `OsError::IoErrorTimedOut`: The server did not answer.
+/// - `OsError::IoErrorInvalidData`: The server sent a malformed answer.
+/// - `OsError::IoError`: The server failed to answer the query.
+pub fn resolve(name: &str) -> OsResult<IpAddr> {
+    if let Some(addr) = parse_ipv4(name) {
+        return Ok(addr);
+    }
+    let name = name.trim_end_matches('.');
+    if name.is_empty() || name.len() > NAME_MAX {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let now = time();
+    let (server, id) = {
+        let mut resolver = RESOLVER.lock();
+        if let Some(addr) = resolver.lookup(name, now) {
+            return Ok(addr);
+        }
+        let server = match resolver.server {
+            Some(server) => server,
+            None => dns_server()?.with_port(DNS_PORT),
+        };
+        resolver.next_id = resolver.next_id.wrapping_add(1) ^ (now.as_micros() as u16);
+        (server, resolver.next_id)
+    };
+
+    // The resolver is not locked while waiting for the answer, so other
+    // threads can still use the cache.
+    let (addr, ttl) = query(server, name, id)?;
+    RESOLVER
+        .lock()
+        .insert(name, addr, now + Duration::from_secs(ttl as u64));
+    Ok(addr)
+}
+
+impl Resolver {
+    fn lookup(&mut self, name: &str, now: Duration) -> Option<IpAddr> {
+        for slot in self.cache.iter_mut() {
+            if let Some(entry) = slot {
+                if entry.expires <= now {
+                    *slot = None;
+                } else if name
+                    .as_bytes()
+                    .eq_ignore_ascii_case(&entry.name[..entry.name_len])
+                {
+                    return Some(entry.addr);
+                }
+            }
+        }
+        None
+    }
+
+    /// Caches `addr`, evicting the entry that expires first if the cache is
+    /// full.
+    fn insert(&mut self, name: &str, addr: IpAddr, expires: Duration) {
+        let mut entry = CacheEntry {
+            name: [0; NAME_MAX],
+            name_len: name.len(),
+            addr,
+            expires,
+        };
+        entry.name[..name.len()].copy_from_slice(name.as_bytes());
+
+        let victim = match self.cache.iter().position(|slot| slot.is_none()) {
+            Some(idx) => idx,
+            None => self
+                .cache
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, slot)| slot.map(|entry| entry.expires))
+                .map(|(idx, _)| idx)
+                .unwrap_or(0),
+        };
+        self.cache[victim] = Some(entry);
+    }
+}
+
+/// Sends an `A` query for `name` to `server` and waits for the answer.
+/// Returns the first address in the answer and its TTL in seconds.
+fn query(server: IpAddr, name: &str, id: u16) -> OsResult<(IpAddr, u32)> {
+    let mut request = [0u8; MESSAGE_MAX];
+    let request_len = build_query(name, id, &mut request)?;
+
+    let socket = udp_bind(0)?;
+    let result = exchange(socket, server, &request[..request_len], id);
+    let _ = sock_close(socket);
+    result
+}
+
+fn exchange(
+    socket: SocketDescriptor,
+    server: IpAddr,
+    request: &[u8],
+    id: u16,
+) -> OsResult<(IpAddr, u32)> {
+    let mut response = [0u8; MESSAGE_MAX];
+    for _ in 0..ATTEMPTS {
+        udp_send_to(socket, request, server)?;
+        let start = time();
+        while time() - start < TIMEOUT {
+            match udp_recv_from(socket, &mut response)? {
+                Some((len, from)) if from == server => {
+                    if let Some(answer) = parse_response(&response[..len], id)? {
+                        return Ok(answer);
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    sleep(Duration::from_millis(10))?;
+                }
+            }
+        }
+    }
+    Err(OsError::IoErrorTimedOut)
+}
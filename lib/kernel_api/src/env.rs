@@ -0,0 +1,114 @@
+//! Arguments and environment of the running program, modelled after `std::env`.
+//!
+//! The kernel places `argc`, `argv` and `envp` on the initial stack and passes
+//! them to `_start`, which hands them to `init()` before calling `main`.
+
+use core::ptr;
+
+static mut ARGC: usize = 0;
+static mut ARGV: *const *const u8 = ptr::null();
+static mut ENVP: *const *const u8 = ptr::null();
+
+/// Records the arguments and environment passed to the program's entry point.
+///
+/// # Safety
+///
+/// `argv` must point to `argc` valid NUL-terminated strings and `envp` to a
+/// NULL-terminated array of them, all living for the rest of the program.
+#[doc(hidden)]
+pub unsafe fn init(argc: usize, argv: *const *const u8, envp: *const *const u8) {
+    ARGC = argc;
+    ARGV = argv;
+    ENVP = envp;
+}
+
+/// Returns the string at `ptr`, or `""` if it is not valid UTF-8.
+unsafe fn c_str(ptr: *const u8) -> &'static str {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap_or("")
+}
+
+/// Iterator over the program's arguments, returned by `args()`.
+pub struct Args {
+    pos: usize,
+}
+
+/// Returns the arguments the program was started with. The first one is the
+/// program name.
+pub fn args() -> Args {
+    Args { pos: 0 }
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        unsafe {
+            if self.pos >= ARGC || ARGV.is_null() {
+                return None;
+            }
+            self.pos += 1;
+            Some(c_str(*ARGV.add(self.pos - 1)))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = unsafe { ARGC }.saturating_sub(self.pos);
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Args {}
+
+/// Iterator over the program's environment, returned by `env()`.
+pub struct Vars {
+    pos: usize,
+}
+
+/// Returns the `(name, value)` pairs of the program's environment.
+pub fn env() -> Vars {
+    Vars { pos: 0 }
+}
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<(&'static str, &'static str)> {
+        unsafe {
+            if ENVP.is_null() {
+                return None;
+            }
+            let entry = *ENVP.add(self.pos);
+            if entry.is_null() {
+                return None;
+            }
+            self.pos += 1;
+            let var = c_str(entry);
+            Some(match var.find('=') {
+                Some(idx) => (&var[..idx], &var[idx + 1..]),
+                None => (var, ""),
+            })
+        }
+    }
+}
+
+/// Returns the program's environment as `name=value` strings, as passed on to
+/// `exec` and `spawn`.
+pub(crate) fn raw_vars() -> impl Iterator<Item = &'static str> {
+    let mut pos = 0;
+    core::iter::from_fn(move || unsafe {
+        if ENVP.is_null() || (*ENVP.add(pos)).is_null() {
+            return None;
+        }
+        pos += 1;
+        Some(c_str(*ENVP.add(pos - 1)))
+    })
+}
+
+/// Returns the value of the environment variable `name`.
+pub fn var(name: &str) -> Option<&'static str> {
+    env().find(|(key, _)| *key == name).map(|(_, value)| value)
+}
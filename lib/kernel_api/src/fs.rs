@@ -0,0 +1,127 @@
+//! File and directory access for user programs, modelled after `std::fs`.
+
+use shim::io::{self, SeekFrom};
+
+use crate::syscall::{close, fstat, lseek, open, read, readdir, write_fd};
+use crate::{DirEntry, FileDescriptor, OsResult, Stat, SEEK_CUR, SEEK_END, SEEK_SET};
+
+/// An open file. The descriptor is closed when the `File` is dropped.
+#[derive(Debug)]
+pub struct File {
+    fd: FileDescriptor,
+}
+
+impl File {
+    /// Opens the file or directory at the absolute path `path`.
+    pub fn open(path: &str) -> OsResult<File> {
+        Ok(File { fd: open(path)? })
+    }
+
+    /// Takes ownership of an open descriptor, such as `FileDescriptor::STDIN`.
+    pub fn from_raw(fd: FileDescriptor) -> File {
+        File { fd }
+    }
+
+    /// Returns the descriptor without closing it.
+    pub fn into_raw(self) -> FileDescriptor {
+        let fd = self.fd;
+        core::mem::forget(self);
+        fd
+    }
+
+    pub fn as_raw(&self) -> FileDescriptor {
+        self.fd
+    }
+
+    /// Queries the size and attributes of the file.
+    pub fn metadata(&self) -> OsResult<Stat> {
+        fstat(self.fd)
+    }
+
+    /// Reads the rest of the file into `buf`, stopping early if `buf` fills
+    /// up. Returns the number of bytes read.
+    pub fn read_all(&mut self, buf: &mut [u8]) -> OsResult<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match read(self.fd, &mut buf[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        Ok(filled)
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(read(self.fd, buf)?)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(write_fd(self.fd, buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (offset as i64, SEEK_SET),
+            SeekFrom::Current(offset) => (offset, SEEK_CUR),
+            SeekFrom::End(offset) => (offset, SEEK_END),
+        };
+        Ok(lseek(self.fd, offset, whence)?)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+/// Number of entries `ReadDir` fetches per system call.
+const READ_DIR_BATCH: usize = 4;
+
+/// Iterator over the entries of a directory, returned by `read_dir()`.
+pub struct ReadDir {
+    dir: File,
+    buf: [DirEntry; READ_DIR_BATCH],
+    pos: usize,
+    len: usize,
+}
+
+/// Returns an iterator over the entries of the directory at `path`.
+pub fn read_dir(path: &str) -> OsResult<ReadDir> {
+    let dir = File::open(path)?;
+    Ok(ReadDir {
+        dir,
+        buf: [DirEntry::default(); READ_DIR_BATCH],
+        pos: 0,
+        len: 0,
+    })
+}
+
+impl Iterator for ReadDir {
+    type Item = OsResult<DirEntry>;
+
+    fn next(&mut self) -> Option<OsResult<DirEntry>> {
+        if self.pos == self.len {
+            match readdir(self.dir.as_raw(), &mut self.buf) {
+                Ok(0) => return None,
+                Ok(len) => {
+                    self.pos = 0;
+                    self.len = len;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.pos += 1;
+        Some(Ok(self.buf[self.pos - 1]))
+    }
+}
@@ -1,12 +1,33 @@
 #![feature(asm)]
+#![cfg_attr(feature = "user-space", feature(alloc_error_handler))]
 #![no_std]
 
+#[cfg(feature = "user-space")]
+extern crate alloc;
+
 use core::fmt;
 
-use shim::io;
+pub use shim::io;
+
+#[macro_use]
+mod table;
+pub use self::table::*;
+
+pub mod raw;
 
+#[cfg(feature = "user-space")]
+pub mod allocator;
+pub mod dns;
+#[cfg(feature = "user-space")]
+pub mod env;
+#[cfg(feature = "user-space")]
+pub mod fs;
+#[cfg(feature = "user-space")]
+pub mod sync;
 #[cfg(feature = "user-space")]
 pub mod syscall;
+#[cfg(feature = "user-space")]
+pub mod thread;
 
 pub type OsResult<T> = core::result::Result<T, OsError>;
 
@@ -22,6 +43,9 @@ pub enum OsError {
     BadAddress = 50,
     FileExists = 60,
     InvalidArgument = 70,
+    InvalidExecutable = 80,
+    UnsupportedArchitecture = 81,
+    BrokenPipe = 90,
 
     IoError = 101,
     IoErrorEof = 102,
@@ -45,11 +69,15 @@ impl core::convert::From<u64> for OsError {
             50 => OsError::BadAddress,
             60 => OsError::FileExists,
             70 => OsError::InvalidArgument,
+            80 => OsError::InvalidExecutable,
+            81 => OsError::UnsupportedArchitecture,
+            90 => OsError::BrokenPipe,
 
             101 => OsError::IoError,
             102 => OsError::IoErrorEof,
             103 => OsError::IoErrorInvalidData,
             104 => OsError::IoErrorInvalidInput,
+            105 => OsError::IoErrorTimedOut,
 
             200 => OsError::InvalidSocket,
             201 => OsError::IllegalSocketOperation,
@@ -67,17 +95,30 @@ impl core::convert::From<io::Error> for OsError {
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
             io::ErrorKind::NotFound => OsError::NoEntry,
+            io::ErrorKind::PermissionDenied => OsError::NoAccess,
+            io::ErrorKind::AlreadyExists => OsError::FileExists,
+            io::ErrorKind::BrokenPipe => OsError::BrokenPipe,
             _ => OsError::IoError,
         }
     }
 }
 
-pub const NR_SLEEP: usize = 1;
-pub const NR_TIME: usize = 2;
-pub const NR_EXIT: usize = 3;
-pub const NR_WRITE: usize = 4;
-pub const NR_GETPID: usize = 5;
-pub const NR_WRITE_STR: usize = 6;
+impl core::convert::From<OsError> for io::Error {
+    fn from(e: OsError) -> Self {
+        let kind = match e {
+            OsError::NoEntry => io::ErrorKind::NotFound,
+            OsError::NoAccess => io::ErrorKind::PermissionDenied,
+            OsError::FileExists => io::ErrorKind::AlreadyExists,
+            OsError::InvalidArgument | OsError::IoErrorInvalidInput => io::ErrorKind::InvalidInput,
+            OsError::IoErrorEof => io::ErrorKind::UnexpectedEof,
+            OsError::IoErrorInvalidData => io::ErrorKind::InvalidData,
+            OsError::IoErrorTimedOut => io::ErrorKind::TimedOut,
+            OsError::BrokenPipe => io::ErrorKind::BrokenPipe,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, "system call failed")
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 pub struct SocketDescriptor(u64);
@@ -96,6 +137,7 @@ pub struct SocketStatus {
     pub can_recv: bool,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 pub struct IpAddr {
     pub ip: u32,
     pub port: u16,
@@ -108,6 +150,11 @@ impl IpAddr {
             port,
         }
     }
+
+    /// Returns the same address with the port replaced by `port`.
+    pub fn with_port(self, port: u16) -> Self {
+        IpAddr { ip: self.ip, port }
+    }
 }
 
 impl fmt::Debug for IpAddr {
@@ -121,9 +168,160 @@ impl fmt::Debug for IpAddr {
     }
 }
 
-pub const NR_SOCK_CREATE: usize = 20;
-pub const NR_SOCK_STATUS: usize = 21;
-pub const NR_SOCK_CONNECT: usize = 22;
-pub const NR_SOCK_LISTEN: usize = 23;
-pub const NR_SOCK_SEND: usize = 24;
-pub const NR_SOCK_RECV: usize = 25;
+#[derive(Clone, Copy, Debug)]
+pub struct FileDescriptor(u64);
+
+impl FileDescriptor {
+    /// Standard input, open on the console when a process starts.
+    pub const STDIN: FileDescriptor = FileDescriptor(0);
+    /// Standard output, open on the console when a process starts.
+    pub const STDOUT: FileDescriptor = FileDescriptor(1);
+    /// Standard error, open on the console when a process starts.
+    pub const STDERR: FileDescriptor = FileDescriptor(2);
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Metadata of an open file or directory as reported by `fstat`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stat {
+    pub size: u64,
+    pub is_dir: bool,
+    pub read_only: bool,
+    pub hidden: bool,
+}
+
+/// Longest entry name `readdir` reports.
+pub const NAME_MAX: usize = 255;
+
+/// A directory entry as reported by `readdir`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DirEntry {
+    pub name: [u8; NAME_MAX],
+    pub name_len: u8,
+    pub stat: Stat,
+}
+
+impl DirEntry {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len as usize]).unwrap_or("")
+    }
+}
+
+impl Default for DirEntry {
+    fn default() -> Self {
+        DirEntry {
+            name: [0; NAME_MAX],
+            name_len: 0,
+            stat: Stat::default(),
+        }
+    }
+}
+
+impl fmt::Debug for DirEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirEntry")
+            .field("name", &self.name())
+            .field("stat", &self.stat)
+            .finish()
+    }
+}
+
+/// `NR_WAITPID` flag: return at once if no child has exited.
+pub const WNOHANG: u64 = 1;
+
+/// Number of signals; valid signal numbers are `1..NSIG`.
+pub const NSIG: usize = 32;
+
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGILL: u32 = 4;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGTERM: u32 = 15;
+pub const SIGCHLD: u32 = 17;
+
+/// `NR_SIGNAL` handler values: take the default action, or ignore the signal.
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+/// Largest message `NR_CHAN_SEND` takes, in bytes.
+pub const CHAN_MSG_MAX: usize = 4096;
+/// Most descriptors sent with one channel message.
+pub const CHAN_FDS_MAX: usize = 8;
+
+/// Most descriptors one `NR_POLL` call watches.
+pub const POLL_FDS_MAX: usize = 64;
+
+/// `PollFd` events: data can be read without blocking.
+pub const POLLIN: u16 = 0x1;
+/// `PollFd` events: data can be written without blocking.
+pub const POLLOUT: u16 = 0x4;
+/// `PollFd` result only: the peer has closed the connection.
+pub const POLLHUP: u16 = 0x10;
+/// `PollFd` result only: the descriptor is not open.
+pub const POLLNVAL: u16 = 0x20;
+
+/// A descriptor watched by `NR_POLL`, the events the caller waits for, and
+/// the events that are ready, which the kernel fills in.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PollFd {
+    pub fd: u64,
+    pub events: u16,
+    pub revents: u16,
+}
+
+impl PollFd {
+    pub fn new(descriptor: FileDescriptor, events: u16) -> PollFd {
+        PollFd {
+            fd: descriptor.raw(),
+            events,
+            revents: 0,
+        }
+    }
+}
+
+/// Size of a page; `NR_MMAP` and `NR_MUNMAP` work in whole pages.
+pub const PAGE_SIZE: usize = 64 * 1024;
+
+/// `NR_MMAP` page permissions: read-only, read/write, read/write/execute, and
+/// read/execute.
+pub const PERM_RO: u64 = 0;
+pub const PERM_RW: u64 = 1;
+pub const PERM_RWX: u64 = 2;
+pub const PERM_RX: u64 = 3;
+
+/// Most arguments, and most environment variables, `NR_EXEC` and `NR_SPAWN`
+/// accept.
+pub const ARGV_MAX: usize = 32;
+
+/// A string passed to `NR_EXEC` and `NR_SPAWN`: the address and length of its
+/// UTF-8 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ArgRef {
+    pub ptr: u64,
+    pub len: u64,
+}
+
+impl ArgRef {
+    pub fn new(s: &str) -> ArgRef {
+        ArgRef {
+            ptr: s.as_ptr() as u64,
+            len: s.len() as u64,
+        }
+    }
+}
+
+/// `whence` values of `NR_LSEEK`.
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
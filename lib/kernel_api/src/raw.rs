@@ -0,0 +1,57 @@
+//! Raw system call stubs, one per entry of `syscall_table!`.
+//!
+//! Each stub takes the arguments of its call as declared in the table and
+//! returns `x0` to `x3`, or the error in `x7`. The stubs issue every call the
+//! same way, so they cannot disagree with the kernel about which registers a
+//! call uses. They are unsafe because the kernel acts on the addresses passed
+//! to it; the wrappers in `syscall` are the safe interface.
+
+use crate::*;
+
+/// Issues system call `$nr` with the six argument registers in `$x` and
+/// evaluates to the returned registers.
+macro_rules! svc {
+    ($nr:literal, $x:expr) => {{
+        let x: [u64; 6] = $x;
+        let (r0, r1, r2, r3, ecode): (u64, u64, u64, u64, u64);
+        asm!("mov x0, $5
+              mov x1, $6
+              mov x2, $7
+              mov x3, $8
+              mov x4, $9
+              mov x5, $10
+              svc $11
+              mov $0, x0
+              mov $1, x1
+              mov $2, x2
+              mov $3, x3
+              mov $4, x7"
+             : "=r"(r0), "=r"(r1), "=r"(r2), "=r"(r3), "=r"(ecode)
+             : "r"(x[0]), "r"(x[1]), "r"(x[2]), "r"(x[3]), "r"(x[4]), "r"(x[5]), "i"($nr)
+             : "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "memory"
+             : "volatile");
+        match OsError::from(ecode) {
+            OsError::Ok => Ok([r0, r1, r2, r3]),
+            e => Err(e),
+        }
+    }};
+}
+
+/// Defines a stub for every system call.
+macro_rules! define_stubs {
+    ($($nr_name:ident = $nr:literal, since $since:literal
+       => $name:ident($($arg:ident: $ty:ty),*) => $handler:ident;)*) => {
+        $(
+            /// Issues the system call of the same name.
+            #[inline(always)]
+            pub unsafe fn $name($($arg: $ty),*) -> OsResult<[u64; 4]> {
+                let args: &[u64] = &[$($arg as u64),*];
+                let mut x = [0u64; 6];
+                x[..args.len()].copy_from_slice(args);
+                svc!($nr, x)
+            }
+        )*
+    };
+}
+
+syscall_table!(define_stubs);
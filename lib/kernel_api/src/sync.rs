@@ -0,0 +1,138 @@
+//! Blocking synchronization primitives for user programs, built on
+//! `futex_wait` and `futex_wake`. Uncontended operations stay in user space.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+
+use crate::syscall::{futex_wait, futex_wake};
+use crate::OsError;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and another thread may be waiting in `futex_wait`.
+const CONTENDED: u32 = 2;
+
+/// A mutual exclusion lock. Threads that find it locked sleep in the kernel
+/// until it is released.
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+/// Access to the data of a locked `Mutex`; the lock is released when it is
+/// dropped.
+pub struct MutexGuard<'a, T> {
+    lock: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(val: T) -> Mutex<T> {
+        Mutex {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    /// Acquires the lock, sleeping while another thread holds it.
+    pub fn lock(&self) -> MutexGuard<T> {
+        if self.state.compare_and_swap(UNLOCKED, LOCKED, Ordering::Acquire) != UNLOCKED {
+            // Mark the lock contended so that `unlock()` wakes a sleeper.
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                let _ = futex_wait(&self.state, CONTENDED, None);
+            }
+        }
+        MutexGuard { lock: self }
+    }
+
+    /// Acquires the lock if no other thread holds it.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        match self.state.compare_and_swap(UNLOCKED, LOCKED, Ordering::Acquire) {
+            UNLOCKED => Some(MutexGuard { lock: self }),
+            _ => None,
+        }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            let _ = futex_wake(&self.state, 1);
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// A condition variable for waiting on a `Mutex` until another thread
+/// changes the data it protects.
+pub struct Condvar {
+    /// Incremented by every notification, so that a notification between
+    /// releasing the mutex and sleeping is not lost.
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar {
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    /// Releases the mutex of `guard`, sleeps until notified, and acquires
+    /// the mutex again. Wakeups may be spurious, so callers re-check their
+    /// condition in a loop.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_timeout(guard, None).0
+    }
+
+    /// Like `wait()`, but gives up after `timeout`, if one is given. Also
+    /// returns `true` if the timeout expired.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<Duration>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let lock = guard.lock;
+        drop(guard);
+        let timed_out = futex_wait(&self.seq, seq, timeout) == Err(OsError::IoErrorTimedOut);
+        (lock.lock(), timed_out)
+    }
+
+    /// Wakes one thread waiting on this condition variable.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        let _ = futex_wake(&self.seq, 1);
+    }
+
+    /// Wakes every thread waiting on this condition variable.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        let _ = futex_wake(&self.seq, core::usize::MAX);
+    }
+}
@@ -1,150 +1,557 @@
 use core::fmt;
 use core::fmt::Write;
+use core::sync::atomic::AtomicU32;
 use core::time::Duration;
 
 use crate::*;
 
-macro_rules! err_or {
-    ($ecode:expr, $rtn:expr) => {{
-        let e = OsError::from($ecode);
-        if let OsError::Ok = e {
-            Ok($rtn)
-        } else {
-            Err(e)
-        }
-    }};
-}
-
 pub fn sleep(span: Duration) -> OsResult<Duration> {
-    if span.as_millis() > core::u64::MAX as u128 {
+    if span.as_millis() > core::u32::MAX as u128 {
         panic!("too big!");
     }
 
-    let ms = span.as_millis() as u64;
-    let mut ecode: u64;
-    let mut elapsed_ms: u64;
-
-    unsafe {
-        asm!("mov x0, $2
-              svc $3
-              mov $0, x0
-              mov $1, x7"
-             : "=r"(elapsed_ms), "=r"(ecode)
-             : "r"(ms), "i"(NR_SLEEP)
-             : "x0", "x7"
-             : "volatile");
-    }
-
-    err_or!(ecode, Duration::from_millis(elapsed_ms))
+    let x = unsafe { raw::sleep(span.as_millis() as u32) }?;
+    Ok(Duration::from_millis(x[0]))
 }
 
 pub fn time() -> Duration {
-    let mut ecode: u64;
-    let mut curr_sec: u64;
-    let mut curr_subsec: u64;
-    unsafe {
-        asm!("svc $3
-              mov $0, x0
-              mov $1, x1
-              mov $2, x7"
-             : "=r"(curr_sec), "=r"(curr_subsec), "=r"(ecode)
-             : "i"(NR_TIME)
-             : "x0", "x1", "x7"
-             : "volatile");
-    }
-
-    Duration::new(curr_sec, curr_subsec as u32)
-
+    let x = unsafe { raw::time() }.unwrap_or([0; 4]);
+    Duration::new(x[0], x[1] as u32)
 }
 
-pub fn exit() -> ! {
-    unsafe {
-        asm!("svc $0"::"i"(NR_EXIT)::"volatile");
-    }
+/// Terminates the calling process with `status`, which its parent collects
+/// with `waitpid`.
+pub fn exit(status: i32) -> ! {
+    let _ = unsafe { raw::exit(status) };
     loop {}
 }
 
 pub fn write(b: u8) {
-    let mut ecode: u64;
-    unsafe {
-        asm!("mov x0, $1
-              svc $2
-              mov $0, x7"
-              : "=r"(ecode)
-              : "r"(b), "i"(NR_WRITE)
-              : "x0", "x7"
-              : "volatile");
-    }
+    let _ = unsafe { raw::write(b) };
 }
 
 pub fn write_str(msg: &str) {
-    let msg_addr = msg.as_ptr();
-    let msg_len = msg.len();
-    let rmsg_len:u64;
-    let mut ecode: u64;
-    unsafe {
-        asm!("mov x0, $2
-              mov x1, $3
-              svc $4
-              mov $0, x0
-              mov $1, x7"
-              : "=r"(rmsg_len), "=r"(ecode)
-              : "r"(msg_addr), "r"(msg_len), "i"(NR_WRITE_STR)
-              : "x0", "x7"
-              : "volatile");
-    }
+    let _ = unsafe { raw::write_str(msg.as_ptr() as usize, msg.len()) };
 }
 
 pub fn getpid() -> u64 {
-    let mut ecode: u64;
-    let mut pid: u64;
-    unsafe {
-        asm!("svc $2
-              mov $0, x0
-              mov $1, x7"
-              : "=r"(pid), "=r"(ecode)
-              : "i"(NR_GETPID)
-              : "x0", "x7"
-              : "volatile");
-    }
-    pid
+    unsafe { raw::getpid() }.map_or(0, |x| x[0])
+}
+
+/// Returns the ID of the calling thread. The first thread of a process has
+/// the process's ID.
+pub fn gettid() -> u64 {
+    unsafe { raw::gettid() }.map_or(0, |x| x[0])
+}
+
+/// Returns the `ABI_VERSION` of the running kernel. Kernels older than version
+/// 2 fail it, as they do any call they do not have.
+pub fn abi_version() -> OsResult<u64> {
+    let x = unsafe { raw::abi_version() }?;
+    Ok(x[0])
 }
 
 pub fn sock_create() -> SocketDescriptor {
     // Lab 5 2.D
-    unimplemented!("sock_create")
+    SocketDescriptor(unsafe { raw::sock_create() }.map_or(0, |x| x[0]))
 }
 
 pub fn sock_status(descriptor: SocketDescriptor) -> OsResult<SocketStatus> {
     // Lab 5 2.D
-    unimplemented!("sock_status")
+    let x = unsafe { raw::sock_status(descriptor.raw() as usize) }?;
+    Ok(SocketStatus {
+        is_active: x[0] != 0,
+        is_listening: x[1] != 0,
+        can_send: x[2] != 0,
+        can_recv: x[3] != 0,
+    })
 }
 
 pub fn sock_connect(descriptor: SocketDescriptor, addr: IpAddr) -> OsResult<()> {
     // Lab 5 2.D
-    unimplemented!("sock_connect")
+    unsafe { raw::sock_connect(descriptor.raw() as usize, addr.ip, addr.port) }?;
+    Ok(())
 }
 
 pub fn sock_listen(descriptor: SocketDescriptor, local_port: u16) -> OsResult<()> {
     // Lab 5 2.D
-    unimplemented!("sock_listen")
+    unsafe { raw::sock_listen(descriptor.raw() as usize, local_port) }?;
+    Ok(())
 }
 
 pub fn sock_send(descriptor: SocketDescriptor, buf: &[u8]) -> OsResult<usize> {
     // Lab 5 2.D
-    unimplemented!("sock_send")
+    let x = unsafe { raw::sock_send(descriptor.raw() as usize, buf.as_ptr() as usize, buf.len()) }?;
+    Ok(x[0] as usize)
 }
 
 pub fn sock_recv(descriptor: SocketDescriptor, buf: &mut [u8]) -> OsResult<usize> {
     // Lab 5 2.D
-    unimplemented!("sock_recv")
+    let x = unsafe {
+        raw::sock_recv(descriptor.raw() as usize, buf.as_mut_ptr() as usize, buf.len())
+    }?;
+    Ok(x[0] as usize)
+}
+
+/// Closes a socket. This is `close()` for socket descriptors.
+pub fn sock_close(descriptor: SocketDescriptor) -> OsResult<()> {
+    unsafe { raw::sock_close(descriptor.raw() as usize) }?;
+    Ok(())
+}
+
+/// Creates a UDP socket bound to `local_port`, or to a free ephemeral port if
+/// `local_port` is 0.
+pub fn udp_bind(local_port: u16) -> OsResult<SocketDescriptor> {
+    let x = unsafe { raw::udp_bind(local_port) }?;
+    Ok(SocketDescriptor(x[0]))
+}
+
+/// Sends `buf` as one datagram to `addr`.
+pub fn udp_send_to(descriptor: SocketDescriptor, buf: &[u8], addr: IpAddr) -> OsResult<usize> {
+    let x = unsafe {
+        raw::udp_send_to(
+            descriptor.raw() as usize,
+            buf.as_ptr() as usize,
+            buf.len(),
+            addr.ip,
+            addr.port,
+        )
+    }?;
+    Ok(x[0] as usize)
+}
+
+/// Receives one queued datagram into `buf` without blocking. Returns the
+/// length of the datagram and its sender, or `None` if nothing is queued.
+pub fn udp_recv_from(
+    descriptor: SocketDescriptor,
+    buf: &mut [u8],
+) -> OsResult<Option<(usize, IpAddr)>> {
+    let x = unsafe {
+        raw::udp_recv_from(descriptor.raw() as usize, buf.as_mut_ptr() as usize, buf.len())
+    }?;
+    Ok(match x[2] {
+        0 => None,
+        port => Some((x[0] as usize, IpAddr { ip: x[1] as u32, port: port as u16 })),
+    })
+}
+
+pub fn open(path: &str) -> OsResult<FileDescriptor> {
+    let x = unsafe { raw::open(path.as_ptr() as usize, path.len()) }?;
+    Ok(FileDescriptor(x[0]))
+}
+
+pub fn read(descriptor: FileDescriptor, buf: &mut [u8]) -> OsResult<usize> {
+    let x = unsafe { raw::read(descriptor.raw() as usize, buf.as_mut_ptr() as usize, buf.len()) }?;
+    Ok(x[0] as usize)
+}
+
+/// Writes `buf` to a descriptor and returns the number of bytes written.
+pub fn write_fd(descriptor: FileDescriptor, buf: &[u8]) -> OsResult<usize> {
+    let x = unsafe { raw::write_fd(descriptor.raw() as usize, buf.as_ptr() as usize, buf.len()) }?;
+    Ok(x[0] as usize)
+}
+
+/// Moves the read position of a file by `offset` relative to `whence` (one of
+/// `SEEK_SET`, `SEEK_CUR` and `SEEK_END`) and returns the new position.
+pub fn lseek(descriptor: FileDescriptor, offset: i64, whence: usize) -> OsResult<u64> {
+    let x = unsafe { raw::lseek(descriptor.raw() as usize, offset, whence) }?;
+    Ok(x[0])
+}
+
+pub fn close(descriptor: FileDescriptor) -> OsResult<()> {
+    unsafe { raw::close(descriptor.raw() as usize) }?;
+    Ok(())
+}
+
+pub fn fstat(descriptor: FileDescriptor) -> OsResult<Stat> {
+    let mut stat = Stat::default();
+    unsafe { raw::fstat(descriptor.raw() as usize, &mut stat as *mut Stat as usize) }?;
+    Ok(stat)
+}
+
+/// Reads the next entries of an open directory into `entries` and returns how
+/// many were read; 0 means every entry has been read.
+pub fn readdir(descriptor: FileDescriptor, entries: &mut [DirEntry]) -> OsResult<usize> {
+    let x = unsafe {
+        raw::readdir(descriptor.raw() as usize, entries.as_mut_ptr() as usize, entries.len())
+    }?;
+    Ok(x[0] as usize)
+}
+
+/// Returns the metadata of the file or directory at `path`.
+pub fn stat(path: &str) -> OsResult<Stat> {
+    let mut stat = Stat::default();
+    unsafe { raw::stat(path.as_ptr() as usize, path.len(), &mut stat as *mut Stat as usize) }?;
+    Ok(stat)
+}
+
+/// Changes the current working directory to `path`.
+pub fn chdir(path: &str) -> OsResult<()> {
+    unsafe { raw::chdir(path.as_ptr() as usize, path.len()) }?;
+    Ok(())
+}
+
+/// Writes the current working directory into `buf` and returns it.
+pub fn getcwd(buf: &mut [u8]) -> OsResult<&str> {
+    let x = unsafe { raw::getcwd(buf.as_mut_ptr() as usize, buf.len()) }?;
+    core::str::from_utf8(&buf[..x[0] as usize]).map_err(|_| OsError::IoErrorInvalidData)
+}
+
+/// Creates a copy of the calling process. Returns the child's process ID in
+/// the parent and 0 in the child.
+pub fn fork() -> OsResult<u64> {
+    let x = unsafe { raw::fork() }?;
+    Ok(x[0])
+}
+
+/// Argument and environment arrays for `NR_EXEC` and `NR_SPAWN`.
+struct ExecArgs {
+    argv: [ArgRef; ARGV_MAX],
+    argc: usize,
+    envp: [ArgRef; ARGV_MAX],
+    envc: usize,
+}
+
+impl ExecArgs {
+    /// Collects `args` and the caller's environment.
+    fn new(args: &[&str]) -> OsResult<ExecArgs> {
+        let mut exec_args = ExecArgs {
+            argv: [ArgRef::default(); ARGV_MAX],
+            argc: 0,
+            envp: [ArgRef::default(); ARGV_MAX],
+            envc: 0,
+        };
+        for arg in args.iter() {
+            if exec_args.argc == ARGV_MAX {
+                return Err(OsError::InvalidArgument);
+            }
+            exec_args.argv[exec_args.argc] = ArgRef::new(arg);
+            exec_args.argc += 1;
+        }
+        for var in crate::env::raw_vars() {
+            if exec_args.envc == ARGV_MAX {
+                return Err(OsError::InvalidArgument);
+            }
+            exec_args.envp[exec_args.envc] = ArgRef::new(var);
+            exec_args.envc += 1;
+        }
+        Ok(exec_args)
+    }
+}
+
+/// Replaces the calling process with the program at `path`, started with
+/// `args` (conventionally the program name first) and the caller's
+/// environment. Open descriptors and the working directory are kept.
+///
+/// Only returns if the program could not be started.
+pub fn exec(path: &str, args: &[&str]) -> OsError {
+    let exec_args = match ExecArgs::new(args) {
+        Ok(exec_args) => exec_args,
+        Err(e) => return e,
+    };
+    let result = unsafe {
+        raw::exec(
+            path.as_ptr() as usize,
+            path.len(),
+            exec_args.argv.as_ptr() as usize,
+            exec_args.argc,
+            exec_args.envp.as_ptr() as usize,
+            exec_args.envc,
+        )
+    };
+    match result {
+        Ok(_) => OsError::Ok,
+        Err(e) => e,
+    }
 }
 
+/// Starts the program at `path` as a new process with `args` and the caller's
+/// environment, and returns its process ID. The new process gets a copy of
+/// the caller's descriptors and working directory.
+pub fn spawn(path: &str, args: &[&str]) -> OsResult<u64> {
+    let exec_args = ExecArgs::new(args)?;
+    let x = unsafe {
+        raw::spawn(
+            path.as_ptr() as usize,
+            path.len(),
+            exec_args.argv.as_ptr() as usize,
+            exec_args.argc,
+            exec_args.envp.as_ptr() as usize,
+            exec_args.envc,
+        )
+    }?;
+    Ok(x[0])
+}
+
+/// Waits for the child `pid` to exit, or for any child if `pid` is 0, and
+/// returns its ID and exit status. With `WNOHANG` in `flags`, returns `None`
+/// instead of waiting if no matching child has exited.
+pub fn waitpid(pid: u64, flags: u64) -> OsResult<Option<(u64, i32)>> {
+    let x = unsafe { raw::waitpid(pid, flags) }?;
+    Ok(match x[0] {
+        0 => None,
+        child => Some((child, x[1] as i32)),
+    })
+}
+
+/// Waits for any child to exit and returns its ID and exit status.
+pub fn wait() -> OsResult<(u64, i32)> {
+    waitpid(0, 0).map(|child| child.expect("waitpid without WNOHANG returned no child"))
+}
+
+/// Sends `signal` to the process `pid`. Signal 0 only checks that the
+/// process exists.
+pub fn kill(pid: u64, signal: u32) -> OsResult<()> {
+    unsafe { raw::kill(pid, signal as u64) }?;
+    Ok(())
+}
+
+/// How a process handles a signal, as set by `signal()`.
+#[derive(Clone, Copy, Debug)]
+pub enum SigHandler {
+    /// Take the default action: terminate, or ignore `SIGCHLD`.
+    Default,
+    /// Discard the signal.
+    Ignore,
+    /// Call the function with the signal number. Other caught signals are
+    /// held until it returns.
+    Handler(extern "C" fn(u32)),
+}
+
+/// Signal handlers return here, and the kernel resumes the interrupted code.
+extern "C" fn sigreturn() -> ! {
+    let _ = unsafe { raw::sigreturn() };
+    unreachable!("sigreturn returned");
+}
+
+/// Sets how the calling process handles `sig`. `SIGKILL` cannot be caught or
+/// ignored.
+pub fn signal(sig: u32, handler: SigHandler) -> OsResult<()> {
+    let handler = match handler {
+        SigHandler::Default => SIG_DFL,
+        SigHandler::Ignore => SIG_IGN,
+        SigHandler::Handler(f) => f as u64,
+    };
+    unsafe { raw::signal(sig as u64, handler, sigreturn as u64) }?;
+    Ok(())
+}
+
+/// Sets the end of the heap to `addr` and returns the new end. An `addr` of 0
+/// returns the current end.
+pub fn brk(addr: usize) -> OsResult<usize> {
+    let x = unsafe { raw::brk(addr) }?;
+    Ok(x[0] as usize)
+}
+
+/// Grows the heap by `increment` bytes, or shrinks it if `increment` is
+/// negative, and returns the previous end of the heap.
+pub fn sbrk(increment: isize) -> OsResult<usize> {
+    let end = brk(0)?;
+    if increment != 0 {
+        brk((end as isize).wrapping_add(increment) as usize)?;
+    }
+    Ok(end)
+}
+
+/// Maps `len` bytes of zeroed memory, rounded up to whole pages, with
+/// permission `perm` (`PERM_RO`, `PERM_RW`, `PERM_RWX` or `PERM_RX`) and
+/// returns its page-aligned address.
+pub fn mmap(len: usize, perm: u64) -> OsResult<*mut u8> {
+    let x = unsafe { raw::mmap(len, perm) }?;
+    Ok(x[0] as *mut u8)
+}
+
+/// Unmaps the pages overlapping `len` bytes at the page-aligned `addr`.
+pub fn munmap(addr: *mut u8, len: usize) -> OsResult<()> {
+    unsafe { raw::munmap(addr as usize, len) }?;
+    Ok(())
+}
+
+/// Sets the permission of the pages overlapping `len` bytes at the
+/// page-aligned `addr` to `perm` (`PERM_RO`, `PERM_RW`, `PERM_RWX` or
+/// `PERM_RX`). Every page in the range must be mapped. Accesses the new
+/// permission does not allow raise `SIGSEGV`.
+pub fn mprotect(addr: *mut u8, len: usize, perm: u64) -> OsResult<()> {
+    unsafe { raw::mprotect(addr as usize, len, perm) }?;
+    Ok(())
+}
+
+/// Creates a pipe and returns its read end and its write end.
+pub fn pipe() -> OsResult<(FileDescriptor, FileDescriptor)> {
+    let x = unsafe { raw::pipe() }?;
+    Ok((FileDescriptor(x[0]), FileDescriptor(x[1])))
+}
+
+/// Duplicates `descriptor` into the lowest free descriptor and returns it.
+pub fn dup(descriptor: FileDescriptor) -> OsResult<FileDescriptor> {
+    let x = unsafe { raw::dup(descriptor.raw() as usize) }?;
+    Ok(FileDescriptor(x[0]))
+}
+
+/// Duplicates `descriptor` into `new`, closing what `new` referred to.
+pub fn dup2(descriptor: FileDescriptor, new: FileDescriptor) -> OsResult<FileDescriptor> {
+    let x = unsafe { raw::dup2(descriptor.raw() as usize, new.raw() as usize) }?;
+    Ok(FileDescriptor(x[0]))
+}
+
+/// Sleeps until `futex_wake()` is called on `word`, if `word` still holds
+/// `expected`; otherwise returns at once. Gives up with `IoErrorTimedOut`
+/// after `timeout`, if one is given. The wait may end early, so callers
+/// re-check the word.
+pub fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) -> OsResult<()> {
+    let ms = match timeout {
+        // 0 would mean no timeout at all.
+        Some(timeout) => core::cmp::max(timeout.as_millis() as u64, 1),
+        None => 0,
+    };
+    unsafe { raw::futex_wait(word as *const AtomicU32 as usize, expected, ms) }?;
+    Ok(())
+}
+
+/// Wakes up to `n` processes waiting on `word` in `futex_wait()` and returns
+/// how many were woken.
+pub fn futex_wake(word: &AtomicU32, n: usize) -> OsResult<usize> {
+    let x = unsafe { raw::futex_wake(word as *const AtomicU32 as usize, n) }?;
+    Ok(x[0] as usize)
+}
+
+/// Creates a zeroed shared memory region of `size` bytes, rounded up to whole
+/// pages, and returns its descriptor. Children inherit the descriptor, so a
+/// process can share the region with the programs it starts.
+pub fn shm_create(size: usize) -> OsResult<FileDescriptor> {
+    let x = unsafe { raw::shm_create(size) }?;
+    Ok(FileDescriptor(x[0]))
+}
+
+/// Maps the shared memory region `descriptor` with permission `perm`
+/// (`PERM_RO`, `PERM_RW`, `PERM_RWX` or `PERM_RX`) at the page-aligned
+/// `addr`, or where the kernel chooses if `addr` is null, and returns the
+/// address of the mapping. Remove it with `munmap()`.
+pub fn shm_map(descriptor: FileDescriptor, addr: *mut u8, perm: u64) -> OsResult<*mut u8> {
+    let x = unsafe { raw::shm_map(descriptor.raw() as usize, addr as usize, perm) }?;
+    Ok(x[0] as *mut u8)
+}
+
+/// Creates a message channel and returns its two ends. Each end receives the
+/// messages sent on the other.
+pub fn chan_create() -> OsResult<(FileDescriptor, FileDescriptor)> {
+    let x = unsafe { raw::chan_create() }?;
+    Ok((FileDescriptor(x[0]), FileDescriptor(x[1])))
+}
+
+/// Sends `data`, at most `CHAN_MSG_MAX` bytes, and copies of up to
+/// `CHAN_FDS_MAX` descriptors `fds` on the channel end `descriptor`. Blocks
+/// while the other end's queue is full.
+pub fn chan_send(descriptor: FileDescriptor, data: &[u8], fds: &[FileDescriptor]) -> OsResult<()> {
+    if fds.len() > CHAN_FDS_MAX {
+        return Err(OsError::InvalidArgument);
+    }
+    let mut raw_fds = [0u64; CHAN_FDS_MAX];
+    for (raw, fd) in raw_fds.iter_mut().zip(fds.iter()) {
+        *raw = fd.raw();
+    }
+    unsafe {
+        raw::chan_send(
+            descriptor.raw() as usize,
+            data.as_ptr() as usize,
+            data.len(),
+            raw_fds.as_ptr() as usize,
+            fds.len(),
+        )
+    }?;
+    Ok(())
+}
+
+/// Waits for a message on the channel end `descriptor` and receives it into
+/// `buf`, and the descriptors sent with it into `fds`. Returns the length of
+/// the message and the number of descriptors received. A message that does
+/// not fit stays queued and `InvalidArgument` is returned; a `buf` of
+/// `CHAN_MSG_MAX` bytes and `CHAN_FDS_MAX` descriptors always suffices.
+pub fn chan_recv(
+    descriptor: FileDescriptor,
+    buf: &mut [u8],
+    fds: &mut [FileDescriptor],
+) -> OsResult<(usize, usize)> {
+    let mut raw_fds = [0u64; CHAN_FDS_MAX];
+    let max_fds = core::cmp::min(fds.len(), CHAN_FDS_MAX);
+    let x = unsafe {
+        raw::chan_recv(
+            descriptor.raw() as usize,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+            raw_fds.as_mut_ptr() as usize,
+            max_fds,
+        )
+    }?;
+    let (len, count) = (x[0] as usize, x[1] as usize);
+    for (fd, &raw) in fds.iter_mut().zip(raw_fds[..count].iter()) {
+        *fd = FileDescriptor(raw);
+    }
+    Ok((len, count))
+}
+
+/// Closes the channel end `descriptor`.
+pub fn chan_close(descriptor: FileDescriptor) -> OsResult<()> {
+    unsafe { raw::chan_close(descriptor.raw() as usize) }?;
+    Ok(())
+}
+
+/// Waits until one of the descriptors in `fds` is ready for the events it
+/// asks for, or `timeout` expires, and returns how many are ready. Each
+/// `revents` is set to the ready events; `POLLHUP` and `POLLNVAL` are reported
+/// even if not asked for. A `timeout` of `None` waits indefinitely and a zero
+/// one only checks.
+pub fn poll(fds: &mut [PollFd], timeout: Option<Duration>) -> OsResult<usize> {
+    let timeout_ms = match timeout {
+        Some(timeout) => core::cmp::min(timeout.as_millis(), core::i64::MAX as u128) as i64,
+        None => -1,
+    };
+    let x = unsafe { raw::poll(fds.as_mut_ptr() as usize, fds.len(), timeout_ms) }?;
+    Ok(x[0] as usize)
+}
+
+/// Starts a thread of the current process at `entry`, which is passed `arg`
+/// and must end with `thread_exit()`, and returns the thread's ID. The thread
+/// gets a stack of its own and shares the process's memory and descriptors.
+/// `thread::spawn()` is the safe way to start a thread.
+pub fn thread_create(entry: extern "C" fn(u64) -> !, arg: u64) -> OsResult<u64> {
+    let x = unsafe { raw::thread_create(entry as u64, arg) }?;
+    Ok(x[0])
+}
+
+/// Ends the calling thread with `status`, which `thread_join()` returns. In the
+/// first thread of a process it ends the process, like `exit()`.
+pub fn thread_exit(status: i32) -> ! {
+    let _ = unsafe { raw::thread_exit(status) };
+    loop {}
+}
+
+/// Waits for the thread `tid` of the current process to end and returns its
+/// exit status. A thread can be joined once.
+pub fn thread_join(tid: u64) -> OsResult<i32> {
+    let x = unsafe { raw::thread_join(tid) }?;
+    Ok(x[0] as i32)
+}
+
+/// Returns the DNS server configured in the kernel. The port is always 0.
+pub fn dns_server() -> OsResult<IpAddr> {
+    let x = unsafe { raw::dns_server() }?;
+    Ok(IpAddr { ip: x[0] as u32, port: 0 })
+}
+
+/// Writes `print!` output to standard output, which may be redirected into a
+/// pipe.
 struct Console;
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        write_str(s);
+        let mut buf = s.as_bytes();
+        while !buf.is_empty() {
+            match write_fd(FileDescriptor::STDOUT, buf) {
+                Ok(0) | Err(_) => return Err(fmt::Error),
+                Ok(written) => buf = &buf[written..],
+            }
+        }
         Ok(())
     }
 }
@@ -165,5 +572,6 @@ macro_rules! println {
 
 pub fn vprint(args: fmt::Arguments) {
     let mut c = Console;
-    c.write_fmt(args).unwrap();
+    // There is nowhere to report a closed standard output.
+    let _ = c.write_fmt(args);
 }
@@ -0,0 +1,125 @@
+//! The system call table.
+//!
+//! `syscall_table!` is the one place a system call is declared. Each entry
+//! names the constant and number of the call, the `ABI_VERSION` that added it,
+//! its name and arguments, and the kernel function that handles it:
+//!
+//! ```text
+//! NR_READ = 31, since 1 => read(fd: usize, va: usize, len: usize) => sys_read;
+//! ```
+//!
+//! Arguments are passed in `x0` to `x5` as integers of the given types. Every
+//! call may return values in `x0` to `x3` and returns its status in `x7`.
+//!
+//! The table is passed to a callback macro. This crate builds the `NR_*`
+//! constants, `SYSCALLS` and the stubs in `raw` from it, and the kernel its
+//! dispatcher, so a new system call is one new entry. Numbers are never
+//! reused: a call that changes incompatibly gets a new number, and
+//! `ABI_VERSION` is bumped whenever a call is added.
+
+/// Version of the system call table. It is bumped whenever a call is added,
+/// and a program can compare it with `abi_version()` to find the calls the
+/// running kernel has.
+pub const ABI_VERSION: u64 = 3;
+
+/// Expands `$callback!` with the entries of the system call table.
+#[macro_export]
+macro_rules! syscall_table {
+    ($callback:ident) => {
+        $callback! {
+            NR_SLEEP = 1, since 1 => sleep(ms: u32) => sys_sleep;
+            NR_TIME = 2, since 1 => time() => sys_time;
+            NR_EXIT = 3, since 1 => exit(status: i32) => sys_exit;
+            NR_WRITE = 4, since 1 => write(b: u8) => sys_write;
+            NR_GETPID = 5, since 1 => getpid() => sys_getpid;
+            NR_WRITE_STR = 6, since 1 => write_str(va: usize, len: usize) => sys_write_str;
+            NR_SOCK_CREATE = 20, since 1 => sock_create() => sys_sock_create;
+            NR_SOCK_STATUS = 21, since 1 => sock_status(sock_idx: usize) => sys_sock_status;
+            NR_SOCK_CONNECT = 22, since 1 => sock_connect(sock_idx: usize, ip: u32, port: u16) => sys_sock_connect;
+            NR_SOCK_LISTEN = 23, since 1 => sock_listen(sock_idx: usize, port: u16) => sys_sock_listen;
+            NR_SOCK_SEND = 24, since 1 => sock_send(sock_idx: usize, va: usize, len: usize) => sys_sock_send;
+            NR_SOCK_RECV = 25, since 1 => sock_recv(sock_idx: usize, va: usize, len: usize) => sys_sock_recv;
+            NR_SOCK_CLOSE = 26, since 1 => sock_close(sock_idx: usize) => sys_sock_close;
+            NR_UDP_BIND = 27, since 1 => udp_bind(port: u16) => sys_udp_bind;
+            NR_UDP_SEND_TO = 28, since 1 => udp_send_to(sock_idx: usize, va: usize, len: usize, ip: u32, port: u16) => sys_udp_send_to;
+            NR_UDP_RECV_FROM = 29, since 1 => udp_recv_from(sock_idx: usize, va: usize, len: usize) => sys_udp_recv_from;
+            NR_OPEN = 30, since 1 => open(va: usize, len: usize) => sys_open;
+            NR_READ = 31, since 1 => read(fd: usize, va: usize, len: usize) => sys_read;
+            NR_CLOSE = 32, since 1 => close(fd: usize) => sys_close;
+            NR_FSTAT = 33, since 1 => fstat(fd: usize, va: usize) => sys_fstat;
+            NR_DNS_SERVER = 34, since 1 => dns_server() => sys_dns_server;
+            NR_WRITE_FD = 35, since 1 => write_fd(fd: usize, va: usize, len: usize) => sys_write_fd;
+            NR_LSEEK = 36, since 1 => lseek(fd: usize, offset: i64, whence: usize) => sys_lseek;
+            NR_READDIR = 37, since 1 => readdir(fd: usize, va: usize, count: usize) => sys_readdir;
+            NR_STAT = 38, since 1 => stat(va: usize, len: usize, stat_va: usize) => sys_stat;
+            NR_CHDIR = 39, since 1 => chdir(va: usize, len: usize) => sys_chdir;
+            NR_GETCWD = 40, since 1 => getcwd(va: usize, len: usize) => sys_getcwd;
+            NR_FORK = 41, since 1 => fork() => sys_fork;
+            NR_EXEC = 42, since 1 => exec(path_va: usize, path_len: usize, argv_va: usize, argc: usize, envp_va: usize, envc: usize) => sys_exec;
+            NR_SPAWN = 43, since 1 => spawn(path_va: usize, path_len: usize, argv_va: usize, argc: usize, envp_va: usize, envc: usize) => sys_spawn;
+            NR_WAITPID = 44, since 1 => waitpid(pid: u64, flags: u64) => sys_waitpid;
+            NR_KILL = 45, since 1 => kill(pid: u64, signal: u64) => sys_kill;
+            NR_SIGNAL = 46, since 1 => signal(signal: u64, handler: u64, restorer: u64) => sys_signal;
+            NR_SIGRETURN = 47, since 1 => sigreturn() => sys_sigreturn;
+            NR_BRK = 48, since 1 => brk(brk: usize) => sys_brk;
+            NR_MMAP = 49, since 1 => mmap(len: usize, perm: u64) => sys_mmap;
+            NR_MUNMAP = 50, since 1 => munmap(va: usize, len: usize) => sys_munmap;
+            NR_PIPE = 51, since 1 => pipe() => sys_pipe;
+            NR_DUP = 52, since 1 => dup(fd: usize) => sys_dup;
+            NR_DUP2 = 53, since 1 => dup2(fd: usize, new_fd: usize) => sys_dup2;
+            NR_FUTEX_WAIT = 54, since 1 => futex_wait(va: usize, expected: u32, timeout: u64) => sys_futex_wait;
+            NR_FUTEX_WAKE = 55, since 1 => futex_wake(va: usize, n: usize) => sys_futex_wake;
+            NR_THREAD_CREATE = 56, since 1 => thread_create(entry: u64, arg: u64) => sys_thread_create;
+            NR_THREAD_EXIT = 57, since 1 => thread_exit(status: i32) => sys_thread_exit;
+            NR_THREAD_JOIN = 58, since 1 => thread_join(tid: u64) => sys_thread_join;
+            NR_GETTID = 59, since 1 => gettid() => sys_gettid;
+            NR_SHM_CREATE = 60, since 1 => shm_create(size: usize) => sys_shm_create;
+            NR_SHM_MAP = 61, since 1 => shm_map(fd: usize, va: usize, perm: u64) => sys_shm_map;
+            NR_CHAN_CREATE = 62, since 1 => chan_create() => sys_chan_create;
+            NR_CHAN_SEND = 63, since 1 => chan_send(fd: usize, va: usize, len: usize, fds_va: usize, nfds: usize) => sys_chan_send;
+            NR_CHAN_RECV = 64, since 1 => chan_recv(fd: usize, va: usize, len: usize, fds_va: usize, max_fds: usize) => sys_chan_recv;
+            NR_CHAN_CLOSE = 65, since 1 => chan_close(fd: usize) => sys_chan_close;
+            NR_POLL = 66, since 1 => poll(va: usize, nfds: usize, timeout: i64) => sys_poll;
+            NR_ABI_VERSION = 67, since 2 => abi_version() => sys_abi_version;
+            NR_MPROTECT = 68, since 3 => mprotect(va: usize, len: usize, perm: u64) => sys_mprotect;
+        }
+    };
+}
+
+/// A system call as declared in `syscall_table!`.
+#[derive(Debug)]
+pub struct SyscallInfo {
+    /// The number of the call.
+    pub nr: usize,
+    /// The `ABI_VERSION` that added the call.
+    pub since: u64,
+    /// The name of the call.
+    pub name: &'static str,
+    /// The names of the arguments, in register order.
+    pub args: &'static [&'static str],
+}
+
+/// Defines the `NR_*` constants and `SYSCALLS`.
+macro_rules! define_numbers {
+    ($($nr_name:ident = $nr:literal, since $since:literal
+       => $name:ident($($arg:ident: $ty:ty),*) => $handler:ident;)*) => {
+        $(pub const $nr_name: usize = $nr;)*
+
+        /// Every system call, in table order.
+        pub const SYSCALLS: &[SyscallInfo] = &[
+            $(SyscallInfo {
+                nr: $nr,
+                since: $since,
+                name: stringify!($name),
+                args: &[$(stringify!($arg)),*],
+            },)*
+        ];
+    };
+}
+
+syscall_table!(define_numbers);
+
+/// Returns the table entry of system call `nr`.
+pub fn syscall_info(nr: usize) -> Option<&'static SyscallInfo> {
+    SYSCALLS.iter().find(|info| info.nr == nr)
+}
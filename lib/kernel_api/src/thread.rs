@@ -0,0 +1,48 @@
+//! Threads of a user program, which share its memory and descriptors.
+
+use alloc::boxed::Box;
+
+use crate::syscall::{thread_create, thread_exit, thread_join};
+use crate::OsResult;
+
+/// A started thread, which can be waited for with `join()`. Dropping the
+/// handle does not stop the thread.
+pub struct JoinHandle {
+    tid: u64,
+}
+
+impl JoinHandle {
+    /// Returns the ID of the thread.
+    pub fn tid(&self) -> u64 {
+        self.tid
+    }
+
+    /// Waits for the thread to end and returns its exit status: 0 if its
+    /// closure returned.
+    pub fn join(self) -> OsResult<i32> {
+        thread_join(self.tid)
+    }
+}
+
+type Start = Box<dyn FnOnce() + Send + 'static>;
+
+/// Entry point of threads started by `spawn()`. `arg` is a leaked
+/// `Box<Start>`.
+extern "C" fn start(arg: u64) -> ! {
+    let f = unsafe { Box::from_raw(arg as *mut Start) };
+    f();
+    thread_exit(0);
+}
+
+/// Runs `f` in a new thread of the current process.
+pub fn spawn<F: FnOnce() + Send + 'static>(f: F) -> OsResult<JoinHandle> {
+    let f: Box<Start> = Box::new(Box::new(f));
+    let arg = Box::into_raw(f);
+    match thread_create(start, arg as u64) {
+        Ok(tid) => Ok(JoinHandle { tid }),
+        Err(e) => {
+            drop(unsafe { Box::from_raw(arg) });
+            Err(e)
+        }
+    }
+}
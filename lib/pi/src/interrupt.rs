@@ -119,6 +119,6 @@ impl Controller {
     /// Enables the interrupt as FIQ interrupt
     pub fn enable_fiq(&mut self, int: Interrupt) {
         // Lab 5 2.B
-        unimplemented!("enable_fiq")
+        self.registers.FIQ_control.write((int as u32) | (1 << 7));
     }
 }
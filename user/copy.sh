@@ -3,7 +3,7 @@
 IMG=fs.img
 MNT=mnt
 
-PROGS=(sleep fib echo)
+PROGS=(sleep fib echo httpd ls init wc stack)
 
 if [ -z "$CS3210_COPY" ]; then
     echo "[!] please set CS3210_COPY environment variable"
@@ -17,7 +17,9 @@ done
 (cd ../kern5; make)
 
 for d in ${PROGS[@]}; do
-    cp $d/build/$d.bin $CS3210_COPY/$d
+    cp $d/build/$d.elf $CS3210_COPY/$d
 done
 
+cp init/init.rc $CS3210_COPY/init.rc
+
 cp ../kern5/build/kernel.bin $CS3210_COPY/kernel.bin 
@@ -0,0 +1,280 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+mod cr0;
+
+use core::fmt::{self, Write};
+use core::time::Duration;
+
+use kernel_api::syscall::*;
+use kernel_api::{println, FileDescriptor, OsResult, SocketDescriptor};
+
+/// The port the server listens on.
+const PORT: u16 = 80;
+
+/// Largest request head (request line and headers) that is accepted.
+const REQUEST_MAX: usize = 1024;
+
+/// Size of the buffer a response is sent from.
+const CHUNK_SIZE: usize = 512;
+
+/// Where a connection is in its request/response cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Waiting for a client to connect.
+    Listening,
+    /// Receiving the request head.
+    Reading,
+    /// Sending the response head and then the file, if any.
+    Sending,
+}
+
+/// One listening socket and the request it is serving.
+struct Connection {
+    socket: SocketDescriptor,
+    state: State,
+    request: [u8; REQUEST_MAX],
+    request_len: usize,
+    file: Option<FileDescriptor>,
+    buf: [u8; CHUNK_SIZE],
+    buf_pos: usize,
+    buf_len: usize,
+}
+
+/// A `fmt::Write` sink over a fixed byte buffer.
+struct BufWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Write for BufWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+impl Connection {
+    fn new() -> OsResult<Connection> {
+        let socket = sock_create();
+        sock_listen(socket, PORT)?;
+        Ok(Connection {
+            socket,
+            state: State::Listening,
+            request: [0; REQUEST_MAX],
+            request_len: 0,
+            file: None,
+            buf: [0; CHUNK_SIZE],
+            buf_pos: 0,
+            buf_len: 0,
+        })
+    }
+
+    /// Advances the connection as far as it can go without blocking. Returns
+    /// `true` if any progress was made.
+    fn step(&mut self) -> OsResult<bool> {
+        let status = sock_status(self.socket)?;
+        match self.state {
+            State::Listening => {
+                if status.is_active {
+                    self.state = State::Reading;
+                    return Ok(true);
+                }
+                Ok(false)
+            }
+            _ if !status.is_active => {
+                self.reset()?;
+                Ok(true)
+            }
+            State::Reading => {
+                if !status.can_recv {
+                    return Ok(false);
+                }
+                let received = sock_recv(self.socket, &mut self.request[self.request_len..])?;
+                self.request_len += received;
+                if let Some(end) = find_head_end(&self.request[..self.request_len]) {
+                    self.respond(end);
+                } else if self.request_len == REQUEST_MAX {
+                    self.error(400, "Bad Request");
+                }
+                Ok(received > 0)
+            }
+            State::Sending => {
+                if !status.can_send {
+                    return Ok(false);
+                }
+                if self.buf_pos == self.buf_len && !self.refill()? {
+                    self.reset()?;
+                    return Ok(true);
+                }
+                let sent = sock_send(self.socket, &self.buf[self.buf_pos..self.buf_len])?;
+                self.buf_pos += sent;
+                Ok(sent > 0)
+            }
+        }
+    }
+
+    /// Parses the request head in `request[..end]` and prepares the response.
+    fn respond(&mut self, end: usize) {
+        let head = match core::str::from_utf8(&self.request[..end]) {
+            Ok(head) => head,
+            Err(_) => return self.error(400, "Bad Request"),
+        };
+        let mut parts = head.lines().next().unwrap_or("").split(' ');
+        let (method, target) = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) if target.starts_with('/') => (method, target),
+            _ => return self.error(400, "Bad Request"),
+        };
+        match method {
+            "GET" | "HEAD" => {}
+            "POST" | "PUT" | "DELETE" => return self.error(405, "Method Not Allowed"),
+            _ => return self.error(501, "Not Implemented"),
+        }
+
+        let path = match target.split('?').next() {
+            Some("/") => "/index.html",
+            Some(path) => path,
+            None => "/index.html",
+        };
+        let file = match open(path) {
+            Ok(file) => file,
+            Err(_) => return self.error(404, "Not Found"),
+        };
+        let stat = match fstat(file) {
+            Ok(stat) => stat,
+            Err(_) => {
+                let _ = close(file);
+                return self.error(500, "Internal Server Error");
+            }
+        };
+
+        println!("[httpd] {} {}", method, path);
+        self.head(200, "OK", content_type(path), stat.size);
+        if method == "GET" {
+            self.file = Some(file);
+        } else {
+            let _ = close(file);
+        }
+    }
+
+    /// Prepares a response with a short plain-text body describing `status`.
+    fn error(&mut self, status: u16, reason: &str) {
+        let body_len = reason.len() as u64 + 1;
+        self.head(status, reason, "text/plain", body_len);
+        let mut writer = BufWriter {
+            buf: &mut self.buf,
+            len: self.buf_len,
+        };
+        let _ = writeln!(writer, "{}", reason);
+        self.buf_len = writer.len;
+    }
+
+    /// Writes the response head into the send buffer and switches to
+    /// `State::Sending`.
+    fn head(&mut self, status: u16, reason: &str, content_type: &str, len: u64) {
+        let mut writer = BufWriter {
+            buf: &mut self.buf,
+            len: 0,
+        };
+        let _ = write!(
+            writer,
+            "HTTP/1.0 {} {}\r\nServer: rustos-httpd\r\nContent-Type: {}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            status, reason, content_type, len
+        );
+        self.buf_len = writer.len;
+        self.buf_pos = 0;
+        self.state = State::Sending;
+    }
+
+    /// Reads the next chunk of the file into the send buffer. Returns `false`
+    /// once there is nothing left to send.
+    fn refill(&mut self) -> OsResult<bool> {
+        let file = match self.file {
+            Some(file) => file,
+            None => return Ok(false),
+        };
+        let read = read(file, &mut self.buf)?;
+        self.buf_pos = 0;
+        self.buf_len = read;
+        Ok(read > 0)
+    }
+
+    /// Closes the current connection and starts listening for the next one.
+    fn reset(&mut self) -> OsResult<()> {
+        if let Some(file) = self.file.take() {
+            let _ = close(file);
+        }
+        let _ = sock_close(self.socket);
+        self.socket = sock_create();
+        sock_listen(self.socket, PORT)?;
+        self.state = State::Listening;
+        self.request_len = 0;
+        self.buf_pos = 0;
+        self.buf_len = 0;
+        Ok(())
+    }
+}
+
+/// Returns the length of the request head if `request` contains a complete
+/// one.
+fn find_head_end(request: &[u8]) -> Option<usize> {
+    request
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+/// Guesses the MIME type of a file from its extension.
+fn content_type(path: &str) -> &'static str {
+    let extension = path.rsplit('.').next().unwrap_or("");
+    match extension {
+        "html" | "htm" => "text/html",
+        "txt" => "text/plain",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+fn main() {
+    let result = main_inner();
+    if let Err(error) = result {
+        println!("Terminating with error: {:?}", error);
+    }
+}
+
+fn main_inner() -> OsResult<()> {
+    let mut connections = [
+        Connection::new()?,
+        Connection::new()?,
+        Connection::new()?,
+        Connection::new()?,
+    ];
+    println!("[httpd] listening on port {} with {} slots", PORT, connections.len());
+
+    loop {
+        let mut busy = false;
+        for connection in connections.iter_mut() {
+            match connection.step() {
+                Ok(progress) => busy |= progress,
+                Err(error) => {
+                    println!("[httpd] connection error: {:?}", error);
+                    connection.reset()?;
+                }
+            }
+        }
+        if !busy {
+            sleep(Duration::from_millis(10))?;
+        }
+    }
+}
@@ -0,0 +1,51 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+mod cr0;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use kernel_api::fs::File;
+use kernel_api::syscall::{sleep, spawn, wait};
+use kernel_api::println;
+
+/// Commands started at boot, one per line. Empty lines and lines starting
+/// with `#` are skipped.
+const INIT_RC: &str = "/init.rc";
+
+fn main() {
+    let mut buf = [0u8; 4096];
+    let len = match File::open(INIT_RC).and_then(|mut file| file.read_all(&mut buf)) {
+        Ok(len) => len,
+        Err(error) => {
+            println!("init: {}: {:?}", INIT_RC, error);
+            0
+        }
+    };
+    let script = core::str::from_utf8(&buf[..len]).unwrap_or("");
+
+    for line in script.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match spawn(args[0], &args) {
+            Ok(pid) => println!("init: started {} as process {}", args[0], pid),
+            Err(error) => println!("init: {}: {:?}", args[0], error),
+        }
+    }
+
+    // Collect the programs as they exit; once none are left, just idle.
+    loop {
+        match wait() {
+            Ok((pid, status)) => println!("init: process {} exited with status {}", pid, status),
+            Err(_) => {
+                let _ = sleep(Duration::from_secs(60));
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+mod cr0;
+
+use kernel_api::env::args;
+use kernel_api::fs::read_dir;
+use kernel_api::syscall::getcwd;
+use kernel_api::{println, OsResult};
+
+fn main() {
+    let mut listed = false;
+    for path in args().skip(1) {
+        if let Err(error) = list(path) {
+            println!("ls: {}: {:?}", path, error);
+        }
+        listed = true;
+    }
+    if !listed {
+        let mut buf = [0u8; 256];
+        let result = getcwd(&mut buf).and_then(list);
+        if let Err(error) = result {
+            println!("ls: {:?}", error);
+        }
+    }
+}
+
+fn list(path: &str) -> OsResult<()> {
+    for entry in read_dir(path)? {
+        let entry = entry?;
+        let name = entry.name();
+        if name == "." || name == ".." || entry.stat.hidden {
+            continue;
+        }
+        if entry.stat.is_dir {
+            println!("d {:>10} {}/", "-", name);
+        } else {
+            println!("- {:>10} {}", entry.stat.size, name);
+        }
+    }
+    Ok(())
+}
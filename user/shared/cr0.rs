@@ -23,8 +23,9 @@ unsafe fn zeros_bss() {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
+pub unsafe extern "C" fn _start(argc: usize, argv: *const *const u8, envp: *const *const u8) -> ! {
     zeros_bss();
+    kernel_api::env::init(argc, argv, envp);
     crate::main();
-    kernel_api::syscall::exit();
+    kernel_api::syscall::exit(0);
 }
@@ -0,0 +1,42 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+mod cr0;
+
+use core::mem::MaybeUninit;
+
+use kernel_api::syscall::{getcwd, read};
+use kernel_api::{println, FileDescriptor, PAGE_SIZE};
+
+/// Size of the frame the buffers live in: more stack than is mapped when the
+/// program starts.
+const DEPTH: usize = 4 * PAGE_SIZE;
+
+/// Reads standard input and the working directory into buffers at the bottom
+/// of a frame that user code never touches, so the kernel has to grow the
+/// stack for the system calls themselves.
+#[inline(never)]
+fn read_deep() {
+    let mut frame = MaybeUninit::<[u8; DEPTH]>::uninit();
+    let buf = unsafe { core::slice::from_raw_parts_mut(frame.as_mut_ptr() as *mut u8, DEPTH) };
+    let (input, rest) = buf.split_at_mut(512);
+
+    match read(FileDescriptor::STDIN, input) {
+        Ok(len) => match core::str::from_utf8(&input[..len]) {
+            Ok(text) => println!("stack: read {} bytes: {}", len, text.trim_end()),
+            Err(_) => println!("stack: read {} bytes", len),
+        },
+        Err(error) => println!("stack: read: {:?}", error),
+    }
+    match getcwd(&mut rest[..256]) {
+        Ok(cwd) => println!("stack: cwd {}", cwd),
+        Err(error) => println!("stack: getcwd: {:?}", error),
+    }
+}
+
+/// Checks that system calls grow the stack. Run it at the end of a
+/// pipeline, such as `run echo hello | stack`.
+fn main() {
+    read_deep();
+}
@@ -0,0 +1,39 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+mod cr0;
+
+use kernel_api::syscall::read;
+use kernel_api::{println, FileDescriptor};
+
+/// Counts the lines, words and bytes read from standard input until the end
+/// of the input, such as a pipe whose writers have all exited.
+fn main() {
+    let (mut lines, mut words, mut bytes) = (0, 0, 0);
+    let mut in_word = false;
+    let mut buf = [0u8; 512];
+    loop {
+        let len = match read(FileDescriptor::STDIN, &mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(error) => {
+                println!("wc: {:?}", error);
+                return;
+            }
+        };
+        for &byte in buf[..len].iter() {
+            if byte == b'\n' {
+                lines += 1;
+            }
+            if byte.is_ascii_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                words += 1;
+            }
+        }
+        bytes += len;
+    }
+    println!("{} {} {}", lines, words, bytes);
+}